@@ -43,7 +43,10 @@ fn create_contract() -> (Instance<MockApi, MockStorage, MockQuerier>, MessageInf
     let gas_limit = 1_000_000_000_000; // ~1ms, enough for many executions within one instance
     let instance_options = InstanceOptions {
         gas_limit,
-        print_debug: false,
+        gas_config: None,
+        gas_profiling: false,
+        wasm_backtraces: false,
+        wasm_limits: None,
     };
     let mut deps = Backend {
         api: MockApi::default(),
@@ -151,7 +154,10 @@ fn create_integrated_query_contract() -> (Instance<MockApi, MockStorage, MockQue
     let gas_limit = 1_000_000_000_000; // ~1ms, enough for many executions within one instance
     let instance_options = InstanceOptions {
         gas_limit,
-        print_debug: false,
+        gas_config: None,
+        gas_profiling: false,
+        wasm_backtraces: false,
+        wasm_limits: None,
     };
     let mut deps = Backend {
         api: MockApi::default(),
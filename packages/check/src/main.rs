@@ -4,11 +4,11 @@ use std::io::Read;
 use std::path::Path;
 use std::process::exit;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use colored::Colorize;
 
-use cosmwasm_vm::capabilities_from_csv;
-use cosmwasm_vm::internals::{check_wasm, compile};
+use cosmwasm_vm::internals::{check_wasm_report, compile};
+use cosmwasm_vm::{capabilities_from_csv, WasmLimits};
 
 const DEFAULT_AVAILABLE_CAPABILITIES: &str = "iterator,staking,stargate,cosmwasm_1_1";
 
@@ -26,6 +26,7 @@ pub fn main() {
                 .help("Sets the available capabilities that the desired target chain has")
                 .takes_value(true)
         )
+        .args(&limit_args())
         .arg(
             Arg::with_name("WASM")
                 .help("Wasm file to read and compile")
@@ -41,6 +42,16 @@ pub fn main() {
         .unwrap_or(DEFAULT_AVAILABLE_CAPABILITIES);
     let available_capabilities = capabilities_from_csv(available_capabilities_csv);
     println!("Available capabilities: {:?}", available_capabilities);
+
+    // Limits
+    let limits = match wasm_limits_from_matches(&matches) {
+        Ok(limits) => limits,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit(2);
+        }
+    };
+    println!("Limits: {:?}", limits);
     println!();
 
     // File
@@ -48,7 +59,7 @@ pub fn main() {
 
     let (passes, failures): (Vec<_>, _) = paths
         .map(|p| {
-            let result = check_contract(p, &available_capabilities);
+            let result = check_contract(p, &available_capabilities, &limits);
             match &result {
                 Ok(_) => println!("{}: {}", p, "pass".green()),
                 Err(e) => {
@@ -82,6 +93,7 @@ pub fn main() {
 fn check_contract(
     path: impl AsRef<Path>,
     available_capabilities: &HashSet<String>,
+    limits: &WasmLimits,
 ) -> anyhow::Result<()> {
     let mut file = File::open(path)?;
 
@@ -89,11 +101,156 @@ fn check_contract(
     let mut wasm = Vec::<u8>::new();
     file.read_to_end(&mut wasm)?;
 
-    // Check wasm
-    check_wasm(&wasm, available_capabilities)?;
+    // Check wasm and report all violations at once
+    let violations = check_wasm_report(&wasm, available_capabilities, limits);
+    if !violations.is_empty() {
+        let list: Vec<String> = violations
+            .iter()
+            .map(|v| format!("  - [{}] {}", v.category, v.message))
+            .collect();
+        anyhow::bail!(
+            "Found {} static validation violation(s):\n{}",
+            violations.len(),
+            list.join("\n")
+        );
+    }
 
     // Compile module
     compile(&wasm, None, &[])?;
 
     Ok(())
 }
+
+/// Names and help texts of the limit options. All of them default to [`WasmLimits::default`].
+const LIMIT_ARGS: &[(&str, &str)] = &[
+    (
+        "max-memory-pages",
+        "Maximum initial memory size in Wasm pages (64 KiB each)",
+    ),
+    (
+        "max-functions",
+        "Maximum number of functions defined in a contract",
+    ),
+    (
+        "max-function-params",
+        "Maximum number of parameters of a function",
+    ),
+    (
+        "max-function-results",
+        "Maximum number of results of a function",
+    ),
+    ("max-wasm-size", "Maximum size of the Wasm file in bytes"),
+    (
+        "max-function-body-size",
+        "Maximum size of a function body in bytes",
+    ),
+    (
+        "max-function-locals",
+        "Maximum number of locals declared in a function",
+    ),
+    ("max-table-size", "Maximum initial number of table elements"),
+    ("max-globals", "Maximum number of globals"),
+    (
+        "max-data-segment-size",
+        "Maximum size of a data segment in bytes",
+    ),
+    ("max-element-segments", "Maximum number of element segments"),
+    (
+        "max-stack-height",
+        "Maximum stack height during execution (not limited by default)",
+    ),
+    (
+        "max-length-db-key",
+        "Maximum length of storage keys in bytes",
+    ),
+    (
+        "max-length-db-value",
+        "Maximum length of storage values in bytes",
+    ),
+    (
+        "max-length-query-chain-request",
+        "Maximum length of query requests in bytes",
+    ),
+];
+
+fn limit_args() -> Vec<Arg<'static, 'static>> {
+    LIMIT_ARGS
+        .iter()
+        .map(|(name, help)| {
+            Arg::with_name(name)
+                .long(name)
+                .value_name("N")
+                .help(help)
+                .takes_value(true)
+        })
+        .collect()
+}
+
+fn wasm_limits_from_matches(matches: &ArgMatches) -> anyhow::Result<WasmLimits> {
+    fn parse<T: std::str::FromStr>(
+        matches: &ArgMatches,
+        name: &str,
+        target: &mut T,
+    ) -> anyhow::Result<()> {
+        if let Some(value) = matches.value_of(name) {
+            *target = value
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Invalid value for --{}: {}", name, value))?;
+        }
+        Ok(())
+    }
+
+    let mut limits = WasmLimits::default();
+    parse(matches, "max-memory-pages", &mut limits.max_memory_pages)?;
+    parse(matches, "max-functions", &mut limits.max_functions)?;
+    parse(
+        matches,
+        "max-function-params",
+        &mut limits.max_function_params,
+    )?;
+    parse(
+        matches,
+        "max-function-results",
+        &mut limits.max_function_results,
+    )?;
+    parse(matches, "max-wasm-size", &mut limits.max_wasm_size)?;
+    parse(
+        matches,
+        "max-function-body-size",
+        &mut limits.max_function_body_size,
+    )?;
+    parse(
+        matches,
+        "max-function-locals",
+        &mut limits.max_function_locals,
+    )?;
+    parse(matches, "max-table-size", &mut limits.max_table_size)?;
+    parse(matches, "max-globals", &mut limits.max_globals)?;
+    parse(
+        matches,
+        "max-data-segment-size",
+        &mut limits.max_data_segment_size,
+    )?;
+    parse(
+        matches,
+        "max-element-segments",
+        &mut limits.max_element_segments,
+    )?;
+    if matches.is_present("max-stack-height") {
+        let mut max_stack_height = 0;
+        parse(matches, "max-stack-height", &mut max_stack_height)?;
+        limits.max_stack_height = Some(max_stack_height);
+    }
+    parse(matches, "max-length-db-key", &mut limits.max_length_db_key)?;
+    parse(
+        matches,
+        "max-length-db-value",
+        &mut limits.max_length_db_value,
+    )?;
+    parse(
+        matches,
+        "max-length-query-chain-request",
+        &mut limits.max_length_query_chain_request,
+    )?;
+    Ok(limits)
+}
@@ -11,8 +11,9 @@ use cosmwasm_vm::testing::{
     mock_backend, mock_env, mock_info, mock_instance_options, MockApi, MockQuerier, MockStorage,
 };
 use cosmwasm_vm::{
-    call_execute, call_instantiate, capabilities_from_csv, Cache, CacheOptions, Checksum, Instance,
-    InstanceOptions, Size,
+    call_execute, call_instantiate, capabilities_from_csv, Cache, CacheOptions, Checksum,
+    GasConfig, GasCostTable, Instance, InstanceOptions, MemoryCacheEvictionPolicy,
+    PinnedRestoreMode, Size, WasmCompression, WasmLimits,
 };
 
 // Instance
@@ -20,7 +21,10 @@ const DEFAULT_MEMORY_LIMIT: Size = Size::mebi(64);
 const DEFAULT_GAS_LIMIT: u64 = 1_000_000_000_000; // ~1ms
 const DEFAULT_INSTANCE_OPTIONS: InstanceOptions = InstanceOptions {
     gas_limit: DEFAULT_GAS_LIMIT,
-    print_debug: false,
+    gas_config: None,
+    gas_profiling: false,
+    wasm_backtraces: false,
+    wasm_limits: None,
 };
 const HIGH_GAS_LIMIT: u64 = 20_000_000_000_000_000; // ~20s, allows many calls on one instance
 
@@ -125,12 +129,18 @@ fn bench_cache(c: &mut Criterion) {
         base_dir: TempDir::new().unwrap().into_path(),
         available_capabilities: capabilities_from_csv("iterator,staking"),
         memory_cache_size: MEMORY_CACHE_SIZE,
+        memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
         instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+        fs_cache_size: None,
+        pinned_restore_mode: PinnedRestoreMode::Eager,
+        wasm_compression: WasmCompression::None,
+        wasm_limits: WasmLimits::default(),
+        gas_cost_table: GasCostTable::default(),
+        gas_config: GasConfig::default(),
     };
 
     group.bench_function("save wasm", |b| {
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options.clone()).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
 
         b.iter(|| {
             let result = cache.save_wasm(CONTRACT);
@@ -139,8 +149,7 @@ fn bench_cache(c: &mut Criterion) {
     });
 
     group.bench_function("load wasm", |b| {
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options.clone()).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         b.iter(|| {
@@ -150,8 +159,7 @@ fn bench_cache(c: &mut Criterion) {
     });
 
     group.bench_function("analyze", |b| {
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options.clone()).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         b.iter(|| {
@@ -165,10 +173,16 @@ fn bench_cache(c: &mut Criterion) {
             base_dir: TempDir::new().unwrap().into_path(),
             available_capabilities: capabilities_from_csv("iterator,staking"),
             memory_cache_size: Size(0),
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
             instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
         };
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(non_memcache).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(non_memcache).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         b.iter(|| {
@@ -184,8 +198,7 @@ fn bench_cache(c: &mut Criterion) {
 
     group.bench_function("instantiate from memory", |b| {
         let checksum = Checksum::generate(CONTRACT);
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options.clone()).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
         // Load into memory
         cache
             .get_instance(&checksum, mock_backend(&[]), DEFAULT_INSTANCE_OPTIONS)
@@ -205,8 +218,7 @@ fn bench_cache(c: &mut Criterion) {
 
     group.bench_function("instantiate from pinned memory", |b| {
         let checksum = Checksum::generate(CONTRACT);
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options.clone()).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
         // Load into pinned memory
         cache.pin(&checksum).unwrap();
 
@@ -231,11 +243,17 @@ pub fn bench_instance_threads(c: &mut Criterion) {
             base_dir: TempDir::new().unwrap().into_path(),
             available_capabilities: capabilities_from_csv("iterator,staking"),
             memory_cache_size: MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
             instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
         };
 
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
         let cache = Arc::new(cache);
 
         // Find sub-sequence helper
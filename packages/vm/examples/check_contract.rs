@@ -3,8 +3,8 @@ use std::io::Read;
 
 use clap::{App, Arg};
 
-use cosmwasm_vm::capabilities_from_csv;
 use cosmwasm_vm::internals::{check_wasm, compile};
+use cosmwasm_vm::{capabilities_from_csv, WasmLimits};
 
 const DEFAULT_AVAILABLE_CAPABILITIES: &str = "iterator,staking,stargate,cosmwasm_1_1";
 
@@ -49,7 +49,7 @@ pub fn main() {
     file.read_to_end(&mut wasm).unwrap();
 
     // Check wasm
-    check_wasm(&wasm, &available_capabilities).unwrap();
+    check_wasm(&wasm, &available_capabilities, &WasmLimits::default()).unwrap();
 
     // Compile module
     compile(&wasm, None, &[]).unwrap();
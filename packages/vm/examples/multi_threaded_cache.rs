@@ -5,8 +5,9 @@ use tempfile::TempDir;
 use cosmwasm_std::{coins, Empty};
 use cosmwasm_vm::testing::{mock_backend, mock_env, mock_info, MockApi, MockQuerier, MockStorage};
 use cosmwasm_vm::{
-    call_execute, call_instantiate, capabilities_from_csv, Cache, CacheOptions, InstanceOptions,
-    Size,
+    call_execute, call_instantiate, capabilities_from_csv, Cache, CacheOptions, GasConfig,
+    GasCostTable, InstanceOptions, MemoryCacheEvictionPolicy, PinnedRestoreMode, Size,
+    WasmCompression, WasmLimits,
 };
 
 // Instance
@@ -14,7 +15,10 @@ const DEFAULT_MEMORY_LIMIT: Size = Size::mebi(64);
 const DEFAULT_GAS_LIMIT: u64 = 400_000 * 150_000;
 const DEFAULT_INSTANCE_OPTIONS: InstanceOptions = InstanceOptions {
     gas_limit: DEFAULT_GAS_LIMIT,
-    print_debug: false,
+    gas_config: None,
+    gas_profiling: false,
+    wasm_backtraces: false,
+    wasm_limits: None,
 };
 // Cache
 const MEMORY_CACHE_SIZE: Size = Size::mebi(200);
@@ -30,10 +34,17 @@ pub fn main() {
         base_dir: TempDir::new().unwrap().into_path(),
         available_capabilities: capabilities_from_csv("iterator,staking"),
         memory_cache_size: MEMORY_CACHE_SIZE,
+        memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
         instance_memory_limit: DEFAULT_MEMORY_LIMIT,
+        fs_cache_size: None,
+        pinned_restore_mode: PinnedRestoreMode::Eager,
+        wasm_compression: WasmCompression::None,
+        wasm_limits: WasmLimits::default(),
+        gas_cost_table: GasCostTable::default(),
+        gas_config: GasConfig::default(),
     };
 
-    let cache: Cache<MockApi, MockStorage, MockQuerier> = unsafe { Cache::new(options).unwrap() };
+    let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
     let cache = Arc::new(cache);
 
     let checksum = cache.save_wasm(CONTRACT).unwrap();
@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 use std::ops::AddAssign;
 use std::string::FromUtf8Error;
@@ -10,7 +11,7 @@ use cosmwasm_std::{Order, Record};
 /// A structure that represents gas cost to be deducted from the remaining gas.
 /// This is always needed when computations are performed outside of
 /// Wasm execution, such as calling crypto APIs or calls into the blockchain.
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
 pub struct GasInfo {
     /// The gas cost of a computation that was executed already but not yet charged.
     ///
@@ -157,7 +158,7 @@ pub trait Querier {
 /// attached.
 pub type BackendResult<T> = (core::result::Result<T, BackendError>, GasInfo);
 
-#[derive(Error, Debug, PartialEq, Eq)]
+#[derive(Error, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 #[non_exhaustive]
 pub enum BackendError {
     #[error("Panic in FFI call")]
@@ -175,6 +176,15 @@ pub enum BackendError {
     // This is the only error case of BackendError that is reported back to the contract.
     #[error("User error during call into backend: {msg}")]
     UserErr { msg: String },
+    /// A replayed execution made a different call into the backend than the recorded one
+    #[error(
+        "Backend call {index} diverged from the replay log: expected {expected}, got {actual}"
+    )]
+    ReplayMismatch {
+        index: usize,
+        expected: String,
+        actual: String,
+    },
 }
 
 impl BackendError {
@@ -201,6 +211,18 @@ impl BackendError {
     pub fn user_err(msg: impl Into<String>) -> Self {
         BackendError::UserErr { msg: msg.into() }
     }
+
+    pub fn replay_mismatch(
+        index: usize,
+        expected: impl Into<String>,
+        actual: impl Into<String>,
+    ) -> Self {
+        BackendError::ReplayMismatch {
+            index,
+            expected: expected.into(),
+            actual: actual.into(),
+        }
+    }
 }
 
 impl From<FromUtf8Error> for BackendError {
@@ -353,6 +375,23 @@ mod tests {
         }
     }
 
+    #[test]
+    fn backend_err_replay_mismatch() {
+        let error = BackendError::replay_mismatch(3, "StorageGet", "StorageRemove");
+        match error {
+            BackendError::ReplayMismatch {
+                index,
+                expected,
+                actual,
+            } => {
+                assert_eq!(index, 3);
+                assert_eq!(expected, "StorageGet");
+                assert_eq!(actual, "StorageRemove");
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     // conversions
 
     #[test]
@@ -1,28 +1,47 @@
-use std::collections::HashSet;
-use std::fs::{create_dir_all, File, OpenOptions};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::{create_dir_all, read_dir, remove_file, File, OpenOptions};
 use std::io::{Read, Write};
 use std::marker::PhantomData;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
 
 use crate::backend::{Backend, BackendApi, Querier, Storage};
 use crate::capabilities::required_capabilities_from_module;
 use crate::checksum::Checksum;
 use crate::compatibility::check_wasm;
+use crate::compression::{compress_wasm, decompress_wasm, WasmCompression};
+use crate::environment::GasConfig;
 use crate::errors::{VmError, VmResult};
 use crate::instance::{Instance, InstanceOptions};
-use crate::modules::{FileSystemCache, InMemoryCache, PinnedMemoryCache};
+use crate::modules::{
+    AnalysisCache, FileSystemCache, GarbageCollectionReport, InMemoryCache,
+    MemoryCacheEvictionPolicy, PinnedManifest, PinnedMemoryCache,
+};
+use crate::prometheus::render_prometheus;
 use crate::size::Size;
-use crate::static_analysis::{deserialize_wasm, has_ibc_entry_points};
-use crate::wasm_backend::{compile, make_runtime_store};
+use crate::snapshot::{SnapshotEntry, SnapshotReader, SnapshotReport, SnapshotWriter};
+use crate::static_analysis::{
+    data_segment_size, deserialize_wasm, entry_points, function_count, has_ibc_entry_points,
+    imported_function_names, initial_memory_pages, interface_version,
+};
+use crate::wasm_backend::{
+    compile_with_gas_costs, compile_with_gas_profiling, make_runtime_store, GasCostTable,
+};
+use crate::wasm_limits::WasmLimits;
 
 const STATE_DIR: &str = "state";
 // Things related to the state of the blockchain.
 const WASM_DIR: &str = "wasm";
+const PINNED_MANIFEST_FILE: &str = "pinned.json";
 
 const CACHE_DIR: &str = "cache";
 // Cacheable things.
 const MODULES_DIR: &str = "modules";
+const ANALYSIS_DIR: &str = "analysis";
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct Stats {
@@ -39,6 +58,49 @@ pub struct Metrics {
     pub elements_memory_cache: usize,
     pub size_pinned_memory_cache: usize,
     pub size_memory_cache: usize,
+    pub elements_fs_cache: usize,
+    /// Cumulative size of all modules in the file system cache in bytes
+    pub size_fs_cache: usize,
+    /// Number of modules that were removed from the file system cache to stay within
+    /// [`CacheOptions::fs_cache_size`]
+    pub evictions_fs_cache: usize,
+    /// Number of modules and outdated version directories removed by [`Cache::collect_garbage`]
+    pub garbage_collected_fs_cache: usize,
+    /// Number of modules migrated from the file system cache directory of a previous version
+    pub migrated_fs_cache: usize,
+    /// Number of pinned contracts from the pinned manifest that were not yet restored
+    /// into the pinned memory cache.
+    pub pending_pinned_restores: usize,
+    /// Number of pinned contracts from the pinned manifest that could not be restored.
+    pub failed_pinned_restores: usize,
+}
+
+/// Cache statistics of a single contract, see [`Cache::metrics_per_contract`].
+///
+/// Those are collected for the lifetime of the cache instance and not persisted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ContractMetrics {
+    pub hits_pinned_memory_cache: u32,
+    pub hits_memory_cache: u32,
+    pub hits_fs_cache: u32,
+    pub misses: u32,
+    /// Cumulative time spent compiling the Wasm code of this contract
+    pub compile_time: Duration,
+    /// Cumulative time spent loading the module from the file system cache
+    pub load_time: Duration,
+    /// Size of the compiled module in bytes, as stored in the memory caches.
+    /// This is 0 until the module was compiled or loaded by this cache instance.
+    pub module_size: usize,
+}
+
+/// Defines when the pinned memory cache is rebuilt from the pinned manifest
+/// that was persisted by a previous cache instance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PinnedRestoreMode {
+    /// All pinned modules are loaded into memory in `Cache::new`.
+    Eager,
+    /// Pinned modules are loaded into memory on first use.
+    Lazy,
 }
 
 #[derive(Clone, Debug)]
@@ -46,26 +108,117 @@ pub struct CacheOptions {
     pub base_dir: PathBuf,
     pub available_capabilities: HashSet<String>,
     pub memory_cache_size: Size,
+    /// Defines which modules are kept when the memory cache is full
+    pub memory_cache_eviction_policy: MemoryCacheEvictionPolicy,
     /// Memory limit for instances, in bytes. Use a value that is divisible by the Wasm page size 65536,
     /// e.g. full MiBs.
     pub instance_memory_limit: Size,
+    /// Disk space budget for compiled modules in bytes. When exceeded, the least recently used
+    /// modules are removed from disk. `None` means the file system cache is unbounded.
+    pub fs_cache_size: Option<Size>,
+    /// When to restore the contracts that were pinned before the last shutdown.
+    pub pinned_restore_mode: PinnedRestoreMode,
+    /// The format in which new Wasm blobs are stored in the state directory.
+    /// Existing files are readable in any format.
+    pub wasm_compression: WasmCompression,
+    /// Limits enforced when storing new contracts and when reading import arguments
+    /// from Wasm memory.
+    pub wasm_limits: WasmLimits,
+    /// Gas costs of Wasm operators. Those are compiled into the modules, so changing the table
+    /// causes all contracts to be compiled again.
+    /// [`GasCostTable::default`] keeps the costs of previous versions. Use [`GasCostTable::v2`]
+    /// to charge for allocated memory pages.
+    pub gas_cost_table: GasCostTable,
+    /// Gas costs of functionality provided by the VM. This can be overridden for single
+    /// instances using [`InstanceOptions::gas_config`].
+    pub gas_config: GasConfig,
+}
+
+impl CacheOptions {
+    /// Creates cache options with the given required values. All other options take their
+    /// defaults, which keep the behaviour of previous versions where possible.
+    pub fn new(
+        base_dir: impl Into<PathBuf>,
+        available_capabilities: HashSet<String>,
+        memory_cache_size: Size,
+        instance_memory_limit: Size,
+    ) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+            available_capabilities,
+            memory_cache_size,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
+            instance_memory_limit,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+        }
+    }
 }
 
 pub struct CacheInner {
     wasm_path: PathBuf,
+    wasm_compression: WasmCompression,
     /// Instances memory limit in bytes. Use a value that is divisible by the Wasm page size 65536,
     /// e.g. full MiBs.
     instance_memory_limit: Size,
     pinned_memory_cache: PinnedMemoryCache,
     memory_cache: InMemoryCache,
     fs_cache: FileSystemCache,
+    analysis_cache: AnalysisCache,
+    pinned_manifest: PinnedManifest,
+    /// Pinned checksums from the manifest that are not yet in the pinned memory cache
+    pending_pinned_restores: HashSet<Checksum>,
+    failed_pinned_restores: usize,
+    garbage_collected_fs_cache: usize,
     stats: Stats,
+    contract_metrics: HashMap<Checksum, ContractMetrics>,
+}
+
+impl CacheInner {
+    fn contract_metrics_mut(&mut self, checksum: &Checksum) -> &mut ContractMetrics {
+        self.contract_metrics.entry(*checksum).or_default()
+    }
+
+    /// Loads a module from the file system cache and records the load time and module size
+    /// in case of a hit.
+    fn load_from_fs_cache(&mut self, checksum: &Checksum) -> VmResult<Option<wasmer::Module>> {
+        let start = Instant::now();
+        let store = make_runtime_store(Some(self.instance_memory_limit));
+        let module = self.fs_cache.load(checksum, &store)?;
+        if let Some(module) = &module {
+            let module_size = loupe::size_of_val(module);
+            let metrics = self.contract_metrics_mut(checksum);
+            metrics.load_time += start.elapsed();
+            metrics.module_size = module_size;
+        }
+        Ok(module)
+    }
+
+    /// Records the compilation of a module
+    fn record_compilation(
+        &mut self,
+        checksum: &Checksum,
+        compile_time: Duration,
+        module_size: usize,
+    ) {
+        let metrics = self.contract_metrics_mut(checksum);
+        metrics.compile_time += compile_time;
+        metrics.module_size = module_size;
+    }
 }
 
 pub struct Cache<A: BackendApi, S: Storage, Q: Querier> {
     /// Available capabilities are immutable for the lifetime of the cache,
     /// i.e. any number of read-only references is allowed to access it concurrently.
     available_capabilities: HashSet<String>,
+    /// Same as available capabilities, the limits never change for the lifetime of the cache.
+    wasm_limits: WasmLimits,
+    gas_cost_table: GasCostTable,
+    gas_config: GasConfig,
     inner: Mutex<CacheInner>,
     // Those two don't store data but only fix type information
     type_api: PhantomData<A>,
@@ -75,10 +228,61 @@ pub struct Cache<A: BackendApi, S: Storage, Q: Querier> {
     instantiation_lock: Mutex<()>,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// What [`Cache::warm_up`] did for a single checksum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarmUpAction {
+    /// The module was already in the memory cache or the pinned memory cache
+    AlreadyInMemory,
+    /// The module was loaded from the file system cache into the memory cache
+    LoadedFromDisk,
+    /// The module was compiled and stored in the file system cache and the memory cache
+    Compiled,
+    /// The module was loaded from or compiled into the file system cache, but the memory cache
+    /// did not admit it. This happens with [`MemoryCacheEvictionPolicy::TinyLfu`] when the
+    /// module was requested less often than the modules it would evict, or when the memory
+    /// cache is disabled.
+    NotAdmitted,
+}
+
+/// The outcome of [`Cache::warm_up`] for a single checksum.
+#[derive(Debug)]
+pub struct WarmUpEntry {
+    pub checksum: Checksum,
+    /// The time spent on this checksum. For compiled modules this includes
+    /// loading the Wasm blob and storing the module but not waiting for a worker.
+    pub duration: Duration,
+    pub result: VmResult<WarmUpAction>,
+}
+
+/// The result of [`Cache::upgrade_modules`].
+#[derive(Debug, Default)]
+pub struct ModuleUpgradeReport {
+    /// Modules that were migrated from the directory of a compatible previous version
+    pub migrated: Vec<Checksum>,
+    /// Modules that were compiled from the Wasm blob
+    pub recompiled: Vec<Checksum>,
+    /// Modules that could not be migrated or compiled
+    pub failed: Vec<(Checksum, VmError)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
 pub struct AnalysisReport {
     pub has_ibc_entry_points: bool,
     pub required_capabilities: HashSet<String>,
+    /// All exported entry points the VM knows, e.g. "execute", "query" or "ibc_packet_receive"
+    pub entry_points: BTreeSet<String>,
+    /// The version from the interface_version_* marker export
+    pub interface_version: Option<u32>,
+    /// The full names ("module.field") of all imported host functions
+    pub imported_functions: BTreeSet<String>,
+    /// Size of the Wasm blob in bytes
+    pub wasm_size: usize,
+    /// Number of functions defined in the contract (not counting imports)
+    pub function_count: usize,
+    /// Initial size of the contract's memory in Wasm pages (64 KiB each)
+    pub initial_memory_pages: u32,
+    /// Cumulative size of all data segments in bytes
+    pub data_segment_size: usize,
 }
 
 impl<A, S, Q> Cache<A, S, Q>
@@ -89,17 +293,26 @@ where
 {
     /// Creates a new cache that stores data in `base_dir`.
     ///
-    /// # Safety
+    /// Compiled modules are stored with an integrity envelope that is verified before
+    /// deserialization. Corrupted modules are deleted and recompiled from the Wasm blob.
     ///
-    /// This function is marked unsafe due to `FileSystemCache::new`, which implicitly
-    /// assumes the disk contents are correct, and there's no way to ensure the artifacts
-    /// stored in the cache haven't been corrupted or tampered with.
-    pub unsafe fn new(options: CacheOptions) -> VmResult<Self> {
+    /// The envelope is no protection against manipulation. Compiled modules are native code
+    /// that is executed without further validation, so this assumes that `base_dir` is only
+    /// writable by this process, which must be ensured by the operating system, and that
+    /// modules imported with [`Cache::import_snapshot_with_modules`] come from a trusted source.
+    pub fn new(options: CacheOptions) -> VmResult<Self> {
         let CacheOptions {
             base_dir,
             available_capabilities,
             memory_cache_size,
+            memory_cache_eviction_policy,
             instance_memory_limit,
+            fs_cache_size,
+            pinned_restore_mode,
+            wasm_compression,
+            wasm_limits,
+            gas_cost_table,
+            gas_config,
         } = options;
 
         let state_path = base_dir.join(STATE_DIR);
@@ -118,23 +331,58 @@ where
             })?;
         }
 
-        let fs_cache = FileSystemCache::new(cache_path.join(MODULES_DIR))
-            .map_err(|e| VmError::cache_err(format!("Error file system cache: {}", e)))?;
-        Ok(Cache {
+        let fs_cache =
+            FileSystemCache::new(cache_path.join(MODULES_DIR), fs_cache_size, &gas_cost_table)
+                .map_err(|e| VmError::cache_err(format!("Error file system cache: {}", e)))?;
+        let analysis_cache = AnalysisCache::new(cache_path.join(ANALYSIS_DIR))
+            .map_err(|e| VmError::cache_err(format!("Error analysis cache: {}", e)))?;
+        let pinned_manifest = PinnedManifest::load(state_path.join(PINNED_MANIFEST_FILE))?;
+        let pending_pinned_restores = pinned_manifest.checksums().into_iter().collect();
+        let cache = Cache {
             available_capabilities,
+            wasm_limits,
+            gas_cost_table,
+            gas_config,
             inner: Mutex::new(CacheInner {
                 wasm_path,
+                wasm_compression,
                 instance_memory_limit,
                 pinned_memory_cache: PinnedMemoryCache::new(),
-                memory_cache: InMemoryCache::new(memory_cache_size),
+                memory_cache: InMemoryCache::new(memory_cache_size, memory_cache_eviction_policy),
                 fs_cache,
+                analysis_cache,
+                pinned_manifest,
+                pending_pinned_restores,
+                failed_pinned_restores: 0,
+                garbage_collected_fs_cache: 0,
                 stats: Stats::default(),
+                contract_metrics: HashMap::new(),
             }),
             type_storage: PhantomData::<S>,
             type_api: PhantomData::<A>,
             type_querier: PhantomData::<Q>,
             instantiation_lock: Mutex::new(()),
-        })
+        };
+        if pinned_restore_mode == PinnedRestoreMode::Eager {
+            cache.restore_pinned();
+        }
+        Ok(cache)
+    }
+
+    /// Loads all pinned contracts from the pinned manifest into the pinned memory cache.
+    ///
+    /// Failures do not abort the process but are counted in [`Metrics::failed_pinned_restores`].
+    fn restore_pinned(&self) {
+        let mut cache = self.inner.lock().unwrap();
+        let pending: Vec<Checksum> = cache.pending_pinned_restores.drain().collect();
+        for checksum in pending {
+            if self
+                .load_into_pinned_memory_cache(&mut cache, &checksum)
+                .is_err()
+            {
+                cache.failed_pinned_restores += 1;
+            }
+        }
     }
 
     pub fn stats(&self) -> Stats {
@@ -149,16 +397,47 @@ where
             elements_memory_cache: cache.memory_cache.len(),
             size_pinned_memory_cache: cache.pinned_memory_cache.size(),
             size_memory_cache: cache.memory_cache.size(),
+            elements_fs_cache: cache.fs_cache.len(),
+            size_fs_cache: cache.fs_cache.size(),
+            evictions_fs_cache: cache.fs_cache.evictions(),
+            garbage_collected_fs_cache: cache.garbage_collected_fs_cache,
+            migrated_fs_cache: cache.fs_cache.migrations(),
+            pending_pinned_restores: cache.pending_pinned_restores.len(),
+            failed_pinned_restores: cache.failed_pinned_restores,
         }
     }
 
+    /// Changes the size of the memory cache (in bytes) at runtime. When shrinking, the least
+    /// recently used modules are evicted until the remaining ones fit.
+    /// A size of 0 disables the memory cache.
+    pub fn set_memory_cache_size(&self, size: Size) {
+        self.inner.lock().unwrap().memory_cache.resize(size);
+    }
+
+    /// Returns the cache statistics of every contract that was used since this cache
+    /// instance was created.
+    pub fn metrics_per_contract(&self) -> HashMap<Checksum, ContractMetrics> {
+        self.inner.lock().unwrap().contract_metrics.clone()
+    }
+
+    /// Renders [`Cache::metrics`] and [`Cache::metrics_per_contract`] in the Prometheus
+    /// text exposition format, ready to be served on a `/metrics` endpoint.
+    pub fn prometheus_metrics(&self) -> String {
+        render_prometheus(&self.metrics(), &self.metrics_per_contract())
+    }
+
     pub fn save_wasm(&self, wasm: &[u8]) -> VmResult<Checksum> {
-        check_wasm(wasm, &self.available_capabilities)?;
-        let module = compile(wasm, None, &[])?;
+        check_wasm(wasm, &self.available_capabilities, &self.wasm_limits)?;
+        let analysis = analyze_wasm(wasm)?;
+        let start = Instant::now();
+        let module = compile_with_gas_costs(wasm, None, &[], &self.gas_cost_table)?;
+        let compile_time = start.elapsed();
 
         let mut cache = self.inner.lock().unwrap();
-        let checksum = save_wasm_to_disk(&cache.wasm_path, wasm)?;
+        let checksum = save_wasm_to_disk(&cache.wasm_path, wasm, cache.wasm_compression)?;
         cache.fs_cache.store(&checksum, &module)?;
+        cache.analysis_cache.store(&checksum, analysis)?;
+        cache.record_compilation(&checksum, compile_time, loupe::size_of_val(&module));
         Ok(checksum)
     }
 
@@ -172,27 +451,184 @@ where
     }
 
     fn load_wasm_with_path(&self, wasm_path: &Path, checksum: &Checksum) -> VmResult<Vec<u8>> {
-        let code = load_wasm_from_disk(wasm_path, checksum)?;
-        // verify hash matches (integrity check)
-        if Checksum::generate(&code) != *checksum {
-            Err(VmError::integrity_err())
-        } else {
-            Ok(code)
+        load_verified_wasm_from_disk(wasm_path, checksum)
+    }
+
+    /// Removes the Wasm blob for the given checksum from disk together with every compiled
+    /// artifact of it, i.e. the module in the file system cache, the memory cache and the
+    /// pinned memory cache as well as the stored analysis result.
+    ///
+    /// Instances that were created from this code before keep working since they hold their
+    /// own reference to the module. Subsequent calls of `get_instance` fail.
+    ///
+    /// If the Wasm blob does not exist, an error is returned.
+    pub fn remove_wasm(&self, checksum: &Checksum) -> VmResult<()> {
+        let mut cache = self.inner.lock().unwrap();
+
+        // Forget about the pin such that it is not restored after a restart
+        cache.pending_pinned_restores.remove(checksum);
+        cache.pinned_manifest.remove(checksum)?;
+
+        // Remove the compiled module from all caches (if it exists)
+        cache.pinned_memory_cache.remove(checksum)?;
+        cache.memory_cache.remove(checksum)?;
+        cache.fs_cache.remove(checksum)?;
+        cache.analysis_cache.remove(checksum)?;
+        cache.contract_metrics.remove(checksum);
+
+        remove_wasm_from_disk(&cache.wasm_path, checksum)
+    }
+
+    /// Removes everything from the file system cache that cannot be used anymore, i.e.
+    /// directories of outdated module versions and modules whose Wasm blob does not exist.
+    pub fn collect_garbage(&self) -> VmResult<GarbageCollectionReport> {
+        let mut cache = self.inner.lock().unwrap();
+        let existing = wasm_checksums_on_disk(&cache.wasm_path)?;
+        let report = cache.fs_cache.collect_garbage(&existing)?;
+        cache.garbage_collected_fs_cache += report.removed_version_dirs + report.removed_modules;
+        Ok(report)
+    }
+
+    /// Writes all stored Wasm blobs into a snapshot that can be imported into another cache
+    /// via [`Cache::import_snapshot`], e.g. to bootstrap a new node.
+    ///
+    /// If `include_modules` is set, the compiled modules from the file system cache are added
+    /// as well, tagged with the Wasmer module version they were created with.
+    /// The cache is only locked while reading a single contract, not for the whole export.
+    pub fn export_snapshot<W: Write>(
+        &self,
+        writer: W,
+        include_modules: bool,
+    ) -> VmResult<SnapshotReport> {
+        let mut checksums: Vec<Checksum> = {
+            let cache = self.inner.lock().unwrap();
+            wasm_checksums_on_disk(&cache.wasm_path)?
+                .into_iter()
+                .collect()
+        };
+        checksums.sort_by_key(|checksum| checksum.to_hex());
+
+        let mut snapshot = SnapshotWriter::new(writer)?;
+        let mut report = SnapshotReport::default();
+        for checksum in checksums {
+            let (wasm, module) = {
+                let cache = self.inner.lock().unwrap();
+                let wasm = load_verified_wasm_from_disk(&cache.wasm_path, &checksum)?;
+                let module = if include_modules {
+                    cache
+                        .fs_cache
+                        .read_raw(&checksum)?
+                        .map(|data| (cache.fs_cache.wasmer_module_version(), data))
+                } else {
+                    None
+                };
+                (wasm, module)
+            };
+            snapshot.write_wasm(&checksum, &wasm)?;
+            report.wasm_blobs += 1;
+            if let Some((wasmer_module_version, data)) = module {
+                snapshot.write_module(&checksum, wasmer_module_version, &data)?;
+                report.modules += 1;
+            }
+        }
+        snapshot.finish()?;
+        Ok(report)
+    }
+
+    /// Imports the Wasm blobs of a snapshot created by [`Cache::export_snapshot`].
+    ///
+    /// The checksum of every Wasm blob is verified. The blobs are not checked against the
+    /// current capabilities and [`WasmLimits`], since the contracts were accepted by the chain
+    /// under the rules of their time. They are compiled on first use.
+    /// Compiled modules contained in the snapshot are skipped since they cannot be verified.
+    /// Use [`Cache::import_snapshot_with_modules`] to import them from a trusted source.
+    ///
+    /// The import fails on the first invalid entry. Entries imported before a failure are kept.
+    pub fn import_snapshot<R: Read>(&self, reader: R) -> VmResult<SnapshotReport> {
+        let mut snapshot = SnapshotReader::new(reader)?;
+        let mut report = SnapshotReport::default();
+        while let Some(entry) = snapshot.next_entry()? {
+            match entry {
+                SnapshotEntry::Wasm { checksum, wasm } => {
+                    self.import_wasm(&checksum, &wasm)?;
+                    report.wasm_blobs += 1;
+                }
+                SnapshotEntry::Module { .. } => report.skipped_modules += 1,
+            }
+        }
+        Ok(report)
+    }
+
+    /// Imports a snapshot created by [`Cache::export_snapshot`] including its compiled modules,
+    /// which saves compiling the contracts.
+    ///
+    /// Wasm blobs are imported like in [`Cache::import_snapshot`]. The integrity envelope of
+    /// every compiled module is verified and the import fails if it is broken or does not belong
+    /// to the checksum. Entries imported before a failure are kept. Compiled modules of a
+    /// different Wasmer module version or gas cost table are skipped, such that they are
+    /// recompiled on first use.
+    ///
+    /// # Safety
+    ///
+    /// The compiled modules are native code that is executed without further validation.
+    /// The integrity envelope only protects against accidental corruption, not against
+    /// manipulation, so the snapshot must come from a trusted source.
+    /// See [`wasmer::Module::deserialize`].
+    pub unsafe fn import_snapshot_with_modules<R: Read>(
+        &self,
+        reader: R,
+    ) -> VmResult<SnapshotReport> {
+        let mut snapshot = SnapshotReader::new(reader)?;
+        let mut report = SnapshotReport::default();
+        while let Some(entry) = snapshot.next_entry()? {
+            match entry {
+                SnapshotEntry::Wasm { checksum, wasm } => {
+                    self.import_wasm(&checksum, &wasm)?;
+                    report.wasm_blobs += 1;
+                }
+                SnapshotEntry::Module {
+                    checksum,
+                    wasmer_module_version,
+                    data,
+                } => {
+                    let mut cache = self.inner.lock().unwrap();
+                    let imported = wasmer_module_version == cache.fs_cache.wasmer_module_version()
+                        && cache.fs_cache.write_raw(&checksum, &data)?;
+                    if imported {
+                        report.modules += 1;
+                    } else {
+                        report.skipped_modules += 1;
+                    }
+                }
+            }
         }
+        Ok(report)
+    }
+
+    /// Stores a Wasm blob of a snapshot without static checks or compilation
+    fn import_wasm(&self, checksum: &Checksum, wasm: &[u8]) -> VmResult<()> {
+        verify_snapshot_checksum(checksum, wasm)?;
+        let cache = self.inner.lock().unwrap();
+        save_wasm_to_disk(&cache.wasm_path, wasm, cache.wasm_compression)?;
+        Ok(())
     }
 
     /// Performs static anlyzation on this Wasm without compiling or instantiating it.
     ///
     /// Once the contract was stored via [`save_wasm`], this can be called at any point in time.
-    /// It does not depend on any caching of the contract.
+    /// The result is computed in [`save_wasm`] and stored next to the compiled module.
+    /// If no stored result exists (e.g. for contracts stored by an older version or after the
+    /// analysis format changed), the Wasm is loaded from disk and analyzed again.
     pub fn analyze(&self, checksum: &Checksum) -> VmResult<AnalysisReport> {
-        // Here we could use a streaming deserializer to slightly improve performance. However, this way it is DRYer.
-        let wasm = self.load_wasm(checksum)?;
-        let module = deserialize_wasm(&wasm)?;
-        Ok(AnalysisReport {
-            has_ibc_entry_points: has_ibc_entry_points(&module),
-            required_capabilities: required_capabilities_from_module(&module),
-        })
+        let mut cache = self.inner.lock().unwrap();
+        if let Some(report) = cache.analysis_cache.load(checksum)? {
+            return Ok(report);
+        }
+
+        let wasm = load_verified_wasm_from_disk(&cache.wasm_path, checksum)?;
+        let report = analyze_wasm(&wasm)?;
+        cache.analysis_cache.store(checksum, report.clone())?;
+        Ok(report)
     }
 
     /// Pins a Module that was previously stored via save_wasm.
@@ -200,25 +636,45 @@ where
     /// The module is lookup first in the memory cache, and then in the file system cache.
     /// If not found, the code is loaded from the file system, compiled, and stored into the
     /// pinned cache.
+    /// The checksum is recorded in the pinned manifest, such that the module is pinned again
+    /// when the cache is created with the same base dir (i.e. after a node restart).
     /// If the given ID is not found, or the content does not match the hash (=ID), an error is returned.
     pub fn pin(&self, checksum: &Checksum) -> VmResult<()> {
         let mut cache = self.inner.lock().unwrap();
-        if cache.pinned_memory_cache.has(checksum) {
-            return Ok(());
+        let newly_loaded = !cache.pinned_memory_cache.has(checksum);
+        if newly_loaded {
+            self.load_into_pinned_memory_cache(&mut cache, checksum)?;
+        }
+        // Roll back such that the pinned memory cache does not contain modules
+        // that would be lost on restart
+        if let Err(err) = cache.pinned_manifest.insert(checksum) {
+            if newly_loaded {
+                cache.pinned_memory_cache.remove(checksum)?;
+            }
+            return Err(err);
         }
+        cache.pending_pinned_restores.remove(checksum);
+        Ok(())
+    }
 
+    fn load_into_pinned_memory_cache(
+        &self,
+        cache: &mut CacheInner,
+        checksum: &Checksum,
+    ) -> VmResult<()> {
         // Try to get module from the memory cache
         if let Some(module) = cache.memory_cache.load(checksum)? {
             cache.stats.hits_memory_cache += 1;
+            cache.contract_metrics_mut(checksum).hits_memory_cache += 1;
             return cache
                 .pinned_memory_cache
                 .store(checksum, module.module, module.size);
         }
 
         // Try to get module from file system cache
-        let store = make_runtime_store(Some(cache.instance_memory_limit));
-        if let Some(module) = cache.fs_cache.load(checksum, &store)? {
+        if let Some(module) = cache.load_from_fs_cache(checksum)? {
             cache.stats.hits_fs_cache += 1;
+            cache.contract_metrics_mut(checksum).hits_fs_cache += 1;
             let module_size = loupe::size_of_val(&module);
             return cache
                 .pinned_memory_cache
@@ -227,25 +683,172 @@ where
 
         // Re-compile from original Wasm bytecode
         let code = self.load_wasm_with_path(&cache.wasm_path, checksum)?;
-        let module = compile(&code, Some(cache.instance_memory_limit), &[])?;
+        cache.contract_metrics_mut(checksum).misses += 1;
+        let start = Instant::now();
+        let module = compile_with_gas_costs(
+            &code,
+            Some(cache.instance_memory_limit),
+            &[],
+            &self.gas_cost_table,
+        )?;
+        let compile_time = start.elapsed();
         // Store into the fs cache too
         cache.fs_cache.store(checksum, &module)?;
         let module_size = loupe::size_of_val(&module);
+        cache.record_compilation(checksum, compile_time, module_size);
         cache
             .pinned_memory_cache
             .store(checksum, module, module_size)
     }
 
-    /// Unpins a Module, i.e. removes it from the pinned memory cache.
+    /// Unpins a Module, i.e. removes it from the pinned memory cache and the pinned manifest.
     ///
     /// Not found IDs are silently ignored, and no integrity check (checksum validation) is done
     /// on the removed value.
     pub fn unpin(&self, checksum: &Checksum) -> VmResult<()> {
-        self.inner
-            .lock()
-            .unwrap()
-            .pinned_memory_cache
-            .remove(checksum)
+        let mut cache = self.inner.lock().unwrap();
+        cache.pending_pinned_restores.remove(checksum);
+        cache.pinned_manifest.remove(checksum)?;
+        cache.pinned_memory_cache.remove(checksum)
+    }
+
+    /// Makes sure the modules of the given checksums are available in memory such that
+    /// subsequent `get_instance` calls do not need to compile or deserialize them.
+    ///
+    /// Modules that are neither in memory nor in the file system cache are compiled on up to
+    /// `parallelism` worker threads without holding the cache lock, so other users of
+    /// the cache are not blocked by compilation. The results are returned in the order of
+    /// `checksums`. A failure for one checksum does not affect the others.
+    pub fn warm_up(&self, checksums: &[Checksum], parallelism: usize) -> Vec<WarmUpEntry> {
+        let mut entries: Vec<Option<WarmUpEntry>> = checksums.iter().map(|_| None).collect();
+        let mut jobs = Vec::new();
+
+        let (wasm_path, instance_memory_limit) = {
+            let mut cache = self.inner.lock().unwrap();
+            for (index, checksum) in checksums.iter().enumerate() {
+                let start = Instant::now();
+                let result = match warm_up_from_memory_or_disk(&mut cache, checksum) {
+                    Ok(Some(action)) => Ok(action),
+                    Ok(None) => {
+                        jobs.push((index, *checksum));
+                        continue;
+                    }
+                    Err(err) => Err(err),
+                };
+                entries[index] = Some(WarmUpEntry {
+                    checksum: *checksum,
+                    duration: start.elapsed(),
+                    result,
+                });
+            }
+            (cache.wasm_path.clone(), cache.instance_memory_limit)
+        };
+
+        compile_in_parallel(
+            &wasm_path,
+            instance_memory_limit,
+            self.gas_cost_table,
+            jobs,
+            parallelism,
+            |index, checksum, start, compiled| {
+                let result = compiled.and_then(|(module, compile_time)| {
+                    let mut cache = self.inner.lock().unwrap();
+                    cache.fs_cache.store(&checksum, &module)?;
+                    let module_size = loupe::size_of_val(&module);
+                    cache.record_compilation(&checksum, compile_time, module_size);
+                    if cache.memory_cache.store(&checksum, module, module_size)? {
+                        Ok(WarmUpAction::Compiled)
+                    } else {
+                        Ok(WarmUpAction::NotAdmitted)
+                    }
+                });
+                entries[index] = Some(WarmUpEntry {
+                    checksum,
+                    duration: start.elapsed(),
+                    result,
+                });
+            },
+        );
+
+        entries
+            .into_iter()
+            .map(|entry| entry.expect("Every job is reported by compile_in_parallel"))
+            .collect()
+    }
+
+    /// Returns the checksums of all stored contracts that have no compiled module of the current
+    /// module version in the file system cache, e.g. after an upgrade that changed the module
+    /// version. Those are compiled on first use unless they are migrated or recompiled via
+    /// [`Cache::upgrade_modules`] before. Modules that were evicted to stay within
+    /// [`CacheOptions::fs_cache_size`] are reported as well.
+    pub fn missing_modules(&self) -> VmResult<Vec<Checksum>> {
+        let cache = self.inner.lock().unwrap();
+        let existing = wasm_checksums_on_disk(&cache.wasm_path)?;
+        Ok(cache.fs_cache.missing_modules(&existing))
+    }
+
+    /// Brings the file system cache up to date after an upgrade that changed the module version.
+    ///
+    /// Every module from [`Cache::missing_modules`] is migrated from the directory of a
+    /// compatible previous version if possible. Otherwise it is compiled on up to
+    /// `parallelism` worker threads. The cache lock is not held while modules are deserialized
+    /// or compiled, so this is meant to be called from a background thread while the cache
+    /// keeps serving requests. Modules are not added to the memory cache.
+    pub fn upgrade_modules(&self, parallelism: usize) -> VmResult<ModuleUpgradeReport> {
+        let mut report = ModuleUpgradeReport::default();
+        let mut jobs = Vec::new();
+
+        let missing = self.missing_modules()?;
+        let (previous_versions, wasm_path, instance_memory_limit) = {
+            let cache = self.inner.lock().unwrap();
+            (
+                cache.fs_cache.previous_versions(),
+                cache.wasm_path.clone(),
+                cache.instance_memory_limit,
+            )
+        };
+
+        for checksum in missing {
+            let store = make_runtime_store(Some(instance_memory_limit));
+            let migrated =
+                previous_versions
+                    .load(&checksum, &store)
+                    .and_then(|module| match module {
+                        Some(module) => {
+                            let mut cache = self.inner.lock().unwrap();
+                            cache.fs_cache.store_migrated(&checksum, &module)?;
+                            Ok(true)
+                        }
+                        None => Ok(false),
+                    });
+            match migrated {
+                Ok(true) => report.migrated.push(checksum),
+                Ok(false) => jobs.push((jobs.len(), checksum)),
+                Err(err) => report.failed.push((checksum, err)),
+            }
+        }
+
+        compile_in_parallel(
+            &wasm_path,
+            instance_memory_limit,
+            self.gas_cost_table,
+            jobs,
+            parallelism,
+            |_, checksum, _, compiled| {
+                let result = compiled.and_then(|(module, compile_time)| {
+                    let mut cache = self.inner.lock().unwrap();
+                    cache.fs_cache.store(&checksum, &module)?;
+                    cache.record_compilation(&checksum, compile_time, loupe::size_of_val(&module));
+                    Ok(())
+                });
+                match result {
+                    Ok(()) => report.recompiled.push(checksum),
+                    Err(err) => report.failed.push((checksum, err)),
+                }
+            },
+        );
+        report.recompiled.sort_by_key(|checksum| checksum.to_hex());
+        Ok(report)
     }
 
     /// Returns an Instance tied to a previously saved Wasm.
@@ -257,39 +860,69 @@ where
         backend: Backend<A, S, Q>,
         options: InstanceOptions,
     ) -> VmResult<Instance<A, S, Q>> {
-        let module = self.get_module(checksum)?;
-        let instance = Instance::from_module(
+        let module = if options.gas_profiling {
+            self.compile_for_gas_profiling(checksum)?
+        } else {
+            self.get_module(checksum)?
+        };
+        let mut instance = Instance::from_module(
             &module,
             backend,
             options.gas_limit,
-            options.print_debug,
+            &options.wasm_limits.unwrap_or(self.wasm_limits),
+            &options.gas_config.unwrap_or(self.gas_config),
             None,
             Some(&self.instantiation_lock),
         )?;
+        if options.wasm_backtraces {
+            instance.enable_wasm_backtraces(&self.load_wasm(checksum)?);
+        }
         Ok(instance)
     }
 
+    /// Compiles a previously saved Wasm for gas profiling. Those modules are not cached.
+    fn compile_for_gas_profiling(&self, checksum: &Checksum) -> VmResult<wasmer::Module> {
+        let (wasm_path, instance_memory_limit) = {
+            let cache = self.inner.lock().unwrap();
+            (cache.wasm_path.clone(), cache.instance_memory_limit)
+        };
+        let wasm = self.load_wasm_with_path(&wasm_path, checksum)?;
+        compile_with_gas_profiling(&wasm, Some(instance_memory_limit), &self.gas_cost_table)
+    }
+
     /// Returns a module tied to a previously saved Wasm.
     /// Depending on availability, this is either generated from a memory cache, file system cache or Wasm code.
     /// This is part of `get_instance` but pulled out to reduce the locking time.
     fn get_module(&self, checksum: &Checksum) -> VmResult<wasmer::Module> {
         let mut cache = self.inner.lock().unwrap();
+        // Restore a pinned module from the pinned manifest on first use
+        if cache.pending_pinned_restores.remove(checksum) {
+            if let Err(err) = self.load_into_pinned_memory_cache(&mut cache, checksum) {
+                cache.failed_pinned_restores += 1;
+                return Err(err);
+            }
+        }
+
         // Try to get module from the pinned memory cache
         if let Some(module) = cache.pinned_memory_cache.load(checksum)? {
             cache.stats.hits_pinned_memory_cache += 1;
+            cache
+                .contract_metrics_mut(checksum)
+                .hits_pinned_memory_cache += 1;
             return Ok(module);
         }
 
         // Get module from memory cache
         if let Some(module) = cache.memory_cache.load(checksum)? {
             cache.stats.hits_memory_cache += 1;
+            cache.contract_metrics_mut(checksum).hits_memory_cache += 1;
             return Ok(module.module);
         }
 
         // Get module from file system cache
-        let store = make_runtime_store(Some(cache.instance_memory_limit));
-        if let Some(module) = cache.fs_cache.load(checksum, &store)? {
+        if let Some(module) = cache.load_from_fs_cache(checksum)? {
             cache.stats.hits_fs_cache += 1;
+            cache.contract_metrics_mut(checksum).hits_fs_cache += 1;
             let module_size = loupe::size_of_val(&module);
             cache
                 .memory_cache
@@ -304,9 +937,18 @@ where
         // stored the old module format.
         let wasm = self.load_wasm_with_path(&cache.wasm_path, checksum)?;
         cache.stats.misses += 1;
-        let module = compile(&wasm, Some(cache.instance_memory_limit), &[])?;
+        cache.contract_metrics_mut(checksum).misses += 1;
+        let start = Instant::now();
+        let module = compile_with_gas_costs(
+            &wasm,
+            Some(cache.instance_memory_limit),
+            &[],
+            &self.gas_cost_table,
+        )?;
+        let compile_time = start.elapsed();
         cache.fs_cache.store(checksum, &module)?;
         let module_size = loupe::size_of_val(&module);
+        cache.record_compilation(checksum, compile_time, module_size);
         cache
             .memory_cache
             .store(checksum, module.clone(), module_size)?;
@@ -330,10 +972,121 @@ where
 {
 }
 
+/// Runs the static analysis for [`Cache::analyze`].
+fn analyze_wasm(wasm: &[u8]) -> VmResult<AnalysisReport> {
+    let module = deserialize_wasm(wasm)?;
+    Ok(AnalysisReport {
+        has_ibc_entry_points: has_ibc_entry_points(&module),
+        required_capabilities: required_capabilities_from_module(&module),
+        entry_points: entry_points(&module),
+        interface_version: interface_version(&module),
+        imported_functions: imported_function_names(&module),
+        wasm_size: wasm.len(),
+        function_count: function_count(&module),
+        initial_memory_pages: initial_memory_pages(&module),
+        data_segment_size: data_segment_size(&module),
+    })
+}
+
+/// Loads and compiles the Wasm blobs of the given jobs on up to `parallelism` worker threads
+/// without holding any lock. A job is an arbitrary index together with the checksum.
+///
+/// `on_result` is called on the calling thread for every job once its result is available,
+/// with the time the worker started on it. Jobs of panicked workers are reported as errors.
+fn compile_in_parallel(
+    wasm_path: &Path,
+    instance_memory_limit: Size,
+    gas_cost_table: GasCostTable,
+    jobs: Vec<(usize, Checksum)>,
+    parallelism: usize,
+    mut on_result: impl FnMut(usize, Checksum, Instant, VmResult<(wasmer::Module, Duration)>),
+) {
+    let mut unreported: HashMap<usize, Checksum> = jobs.iter().copied().collect();
+    let worker_count = parallelism.max(1).min(jobs.len());
+    let queue = Arc::new(Mutex::new(jobs.into_iter()));
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<_> = (0..worker_count)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let sender = sender.clone();
+            let wasm_path = wasm_path.to_path_buf();
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().next();
+                let (index, checksum) = match next {
+                    Some(job) => job,
+                    None => break,
+                };
+                let start = Instant::now();
+                let compiled =
+                    load_verified_wasm_from_disk(&wasm_path, &checksum).and_then(|wasm| {
+                        let compile_start = Instant::now();
+                        let module = compile_with_gas_costs(
+                            &wasm,
+                            Some(instance_memory_limit),
+                            &[],
+                            &gas_cost_table,
+                        )?;
+                        Ok((module, compile_start.elapsed()))
+                    });
+                if sender.send((index, checksum, start, compiled)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+
+    for (index, checksum, start, compiled) in receiver {
+        unreported.remove(&index);
+        on_result(index, checksum, start, compiled);
+    }
+    for worker in workers {
+        // A panicking worker leaves its job unreported, which is handled below
+        let _ = worker.join();
+    }
+    for (index, checksum) in unreported {
+        on_result(
+            index,
+            checksum,
+            Instant::now(),
+            Err(VmError::cache_err("Compilation worker panicked")),
+        );
+    }
+}
+
+/// Checks if a module is available in memory or loads it from the file system cache into
+/// the memory cache. Returns `None` if the module needs to be compiled.
+fn warm_up_from_memory_or_disk(
+    cache: &mut CacheInner,
+    checksum: &Checksum,
+) -> VmResult<Option<WarmUpAction>> {
+    if cache.pinned_memory_cache.has(checksum) || cache.memory_cache.has(checksum) {
+        return Ok(Some(WarmUpAction::AlreadyInMemory));
+    }
+
+    match cache.load_from_fs_cache(checksum)? {
+        Some(module) => {
+            let module_size = loupe::size_of_val(&module);
+            if cache.memory_cache.store(checksum, module, module_size)? {
+                Ok(Some(WarmUpAction::LoadedFromDisk))
+            } else {
+                Ok(Some(WarmUpAction::NotAdmitted))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
 /// save stores the wasm code in the given directory and returns an ID for lookup.
 /// It will create the directory if it doesn't exist.
 /// Saving the same byte code multiple times is allowed.
-fn save_wasm_to_disk(dir: impl Into<PathBuf>, wasm: &[u8]) -> VmResult<Checksum> {
+///
+/// The checksum is always calculated from the uncompressed Wasm.
+fn save_wasm_to_disk(
+    dir: impl Into<PathBuf>,
+    wasm: &[u8],
+    compression: WasmCompression,
+) -> VmResult<Checksum> {
     // calculate filename
     let checksum = Checksum::generate(wasm);
     let filename = checksum.to_hex();
@@ -345,9 +1098,10 @@ fn save_wasm_to_disk(dir: impl Into<PathBuf>, wasm: &[u8]) -> VmResult<Checksum>
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(filepath)
         .map_err(|e| VmError::cache_err(format!("Error opening Wasm file for writing: {}", e)))?;
-    file.write_all(wasm)
+    file.write_all(&compress_wasm(wasm, compression))
         .map_err(|e| VmError::cache_err(format!("Error writing Wasm file: {}", e)))?;
 
     Ok(checksum)
@@ -359,10 +1113,60 @@ fn load_wasm_from_disk(dir: impl Into<PathBuf>, checksum: &Checksum) -> VmResult
     let mut file = File::open(path)
         .map_err(|e| VmError::cache_err(format!("Error opening Wasm file for reading: {}", e)))?;
 
-    let mut wasm = Vec::<u8>::new();
-    file.read_to_end(&mut wasm)
+    let mut data = Vec::<u8>::new();
+    file.read_to_end(&mut data)
         .map_err(|e| VmError::cache_err(format!("Error reading Wasm file: {}", e)))?;
-    Ok(wasm)
+    decompress_wasm(data)
+}
+
+fn verify_snapshot_checksum(checksum: &Checksum, wasm: &[u8]) -> VmResult<()> {
+    if Checksum::generate(wasm) != *checksum {
+        return Err(VmError::cache_err(format!(
+            "Checksum mismatch for Wasm {} in snapshot",
+            checksum
+        )));
+    }
+    Ok(())
+}
+
+/// Loads the Wasm blob with the given checksum and verifies its integrity.
+fn load_verified_wasm_from_disk(dir: &Path, checksum: &Checksum) -> VmResult<Vec<u8>> {
+    let code = load_wasm_from_disk(dir, checksum)?;
+    // verify hash matches (integrity check)
+    if Checksum::generate(&code) != *checksum {
+        Err(VmError::integrity_err())
+    } else {
+        Ok(code)
+    }
+}
+
+/// Removes the Wasm blob with the given checksum from the given directory.
+/// Returns an error if the file does not exist.
+fn remove_wasm_from_disk(dir: impl Into<PathBuf>, checksum: &Checksum) -> VmResult<()> {
+    let path = dir.into().join(checksum.to_hex());
+
+    if !path.exists() {
+        return Err(VmError::cache_err("Wasm file does not exist"));
+    }
+
+    remove_file(path)
+        .map_err(|e| VmError::cache_err(format!("Error removing Wasm file from disk: {}", e)))
+}
+
+/// Returns the checksums of all Wasm blobs in the given directory.
+/// Files that are not named like a checksum are ignored.
+fn wasm_checksums_on_disk(dir: &Path) -> VmResult<HashSet<Checksum>> {
+    let entries = read_dir(dir)
+        .map_err(|e| VmError::cache_err(format!("Error reading Wasm directory: {}", e)))?;
+    let mut checksums = HashSet::new();
+    for entry in entries {
+        let entry = entry
+            .map_err(|e| VmError::cache_err(format!("Error reading Wasm directory: {}", e)))?;
+        if let Some(Ok(checksum)) = entry.file_name().to_str().map(Checksum::from_hex) {
+            checksums.insert(checksum);
+        }
+    }
+    Ok(checksums)
 }
 
 #[cfg(test)]
@@ -371,7 +1175,9 @@ mod tests {
     use crate::calls::{call_execute, call_instantiate};
     use crate::capabilities::capabilities_from_csv;
     use crate::errors::VmError;
+    use crate::modules::current_wasmer_module_version;
     use crate::testing::{mock_backend, mock_env, mock_info, MockApi, MockQuerier, MockStorage};
+    use crate::wasm_backend::compile;
     use cosmwasm_std::{coins, Empty};
     use std::fs::OpenOptions;
     use std::io::Write;
@@ -381,7 +1187,10 @@ mod tests {
     const TESTING_MEMORY_LIMIT: Size = Size::mebi(16);
     const TESTING_OPTIONS: InstanceOptions = InstanceOptions {
         gas_limit: TESTING_GAS_LIMIT,
-        print_debug: false,
+        gas_config: None,
+        gas_profiling: false,
+        wasm_backtraces: false,
+        wasm_limits: None,
     };
     const TESTING_MEMORY_CACHE_SIZE: Size = Size::mebi(200);
 
@@ -397,7 +1206,14 @@ mod tests {
             base_dir: TempDir::new().unwrap().into_path(),
             available_capabilities: default_capabilities(),
             memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
             instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
         }
     }
 
@@ -408,14 +1224,21 @@ mod tests {
             base_dir: TempDir::new().unwrap().into_path(),
             available_capabilities: capabilities,
             memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
             instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
         }
     }
 
     #[test]
     fn save_wasm_works() {
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(make_testing_options()).unwrap() };
+            Cache::new(make_testing_options()).unwrap();
         cache.save_wasm(CONTRACT).unwrap();
     }
 
@@ -423,7 +1246,7 @@ mod tests {
     // This property is required when the same bytecode is uploaded multiple times
     fn save_wasm_allows_saving_multiple_times() {
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(make_testing_options()).unwrap() };
+            Cache::new(make_testing_options()).unwrap();
         cache.save_wasm(CONTRACT).unwrap();
         cache.save_wasm(CONTRACT).unwrap();
     }
@@ -443,7 +1266,7 @@ mod tests {
         .unwrap();
 
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(make_testing_options()).unwrap() };
+            Cache::new(make_testing_options()).unwrap();
         let save_result = cache.save_wasm(&wasm);
         match save_result.unwrap_err() {
             VmError::StaticValidationErr { msg, .. } => {
@@ -458,7 +1281,7 @@ mod tests {
         // Who knows if and when the uploaded contract will be executed. Don't pollute
         // memory cache before the init call.
 
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         let backend = mock_backend(&[]);
@@ -471,10 +1294,172 @@ mod tests {
         assert_eq!(cache.stats().misses, 0);
     }
 
+    #[test]
+    fn remove_wasm_works() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+
+        // Store
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        // Exists
+        cache.load_wasm(&checksum).unwrap();
+
+        // Remove
+        cache.remove_wasm(&checksum).unwrap();
+
+        // Does not exist anymore
+        match cache.load_wasm(&checksum).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg
+                    .starts_with("Error opening Wasm file for reading: No such file or directory"))
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        // Removing again fails
+        match cache.remove_wasm(&checksum).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert_eq!(msg, "Wasm file does not exist")
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn remove_wasm_removes_compiled_modules() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            available_capabilities: default_capabilities(),
+            memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
+            instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        // Fill memory cache and pinned memory cache
+        let instance = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        cache.pin(&checksum).unwrap();
+        assert_eq!(cache.metrics().elements_pinned_memory_cache, 1);
+        assert_eq!(cache.metrics().elements_memory_cache, 1);
+
+        cache.remove_wasm(&checksum).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_pinned_memory_cache, 0);
+        assert_eq!(metrics.elements_memory_cache, 0);
+        assert_eq!(metrics.size_pinned_memory_cache, 0);
+        assert_eq!(metrics.size_memory_cache, 0);
+
+        // No compiled module left on disk
+        let modules_dir = tmp_dir.path().join(CACHE_DIR).join(MODULES_DIR);
+        for entry in std::fs::read_dir(modules_dir).unwrap() {
+            let version_dir = entry.unwrap().path();
+            assert!(!version_dir.join(checksum.to_hex()).exists());
+        }
+
+        // Existing instances are not affected
+        drop(instance);
+
+        // New instances cannot be created anymore
+        let res = cache.get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS);
+        match res {
+            Err(VmError::CacheErr { .. }) => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("This must not succeed"),
+        }
+    }
+
+    #[test]
+    fn fs_cache_size_limits_disk_usage() {
+        let options = CacheOptions {
+            fs_cache_size: Some(Size(1)),
+            ..make_stargate_testing_options()
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+
+        let checksum1 = cache.save_wasm(CONTRACT).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_fs_cache, 1);
+        assert!(metrics.size_fs_cache > 0);
+        assert_eq!(metrics.evictions_fs_cache, 0);
+
+        // The module of the first contract is pushed out
+        let checksum2 = cache.save_wasm(IBC_CONTRACT).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_fs_cache, 1);
+        assert_eq!(metrics.evictions_fs_cache, 1);
+
+        // So the first contract needs to be recompiled
+        let _ = cache
+            .get_instance(&checksum1, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 0);
+        assert_eq!(cache.stats().misses, 1);
+        assert_eq!(cache.metrics().evictions_fs_cache, 2);
+
+        // which pushed out the second contract's module
+        let _ = cache
+            .get_instance(&checksum2, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 0);
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn collect_garbage_works() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            ..make_stargate_testing_options()
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let checksum1 = cache.save_wasm(CONTRACT).unwrap();
+        let checksum2 = cache.save_wasm(IBC_CONTRACT).unwrap();
+
+        // Outdated version
+        let modules_path = tmp_dir.path().join(CACHE_DIR).join(MODULES_DIR);
+        create_dir_all(modules_path.join("v1")).unwrap();
+
+        // Orphan module
+        remove_file(
+            tmp_dir
+                .path()
+                .join(STATE_DIR)
+                .join(WASM_DIR)
+                .join(checksum2.to_hex()),
+        )
+        .unwrap();
+
+        let report = cache.collect_garbage().unwrap();
+        assert_eq!(report.removed_version_dirs, 1);
+        assert_eq!(report.removed_modules, 1);
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_fs_cache, 1);
+        assert_eq!(metrics.garbage_collected_fs_cache, 2);
+        assert!(!modules_path.join("v1").exists());
+
+        // The remaining contract is still served from disk
+        let _ = cache
+            .get_instance(&checksum1, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+        assert_eq!(cache.stats().misses, 0);
+    }
+
     #[test]
     fn load_wasm_works() {
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(make_testing_options()).unwrap() };
+            Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         let restored = cache.load_wasm(&checksum).unwrap();
@@ -491,10 +1476,16 @@ mod tests {
                 base_dir: tmp_dir.path().to_path_buf(),
                 available_capabilities: default_capabilities(),
                 memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+                memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
                 instance_memory_limit: TESTING_MEMORY_LIMIT,
+                fs_cache_size: None,
+                pinned_restore_mode: PinnedRestoreMode::Eager,
+                wasm_compression: WasmCompression::None,
+                wasm_limits: WasmLimits::default(),
+                gas_cost_table: GasCostTable::default(),
+                gas_config: GasConfig::default(),
             };
-            let cache1: Cache<MockApi, MockStorage, MockQuerier> =
-                unsafe { Cache::new(options1).unwrap() };
+            let cache1: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options1).unwrap();
             id = cache1.save_wasm(CONTRACT).unwrap();
         }
 
@@ -503,10 +1494,16 @@ mod tests {
                 base_dir: tmp_dir.path().to_path_buf(),
                 available_capabilities: default_capabilities(),
                 memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+                memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
                 instance_memory_limit: TESTING_MEMORY_LIMIT,
+                fs_cache_size: None,
+                pinned_restore_mode: PinnedRestoreMode::Eager,
+                wasm_compression: WasmCompression::None,
+                wasm_limits: WasmLimits::default(),
+                gas_cost_table: GasCostTable::default(),
+                gas_config: GasConfig::default(),
             };
-            let cache2: Cache<MockApi, MockStorage, MockQuerier> =
-                unsafe { Cache::new(options2).unwrap() };
+            let cache2: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options2).unwrap();
             let restored = cache2.load_wasm(&id).unwrap();
             assert_eq!(restored, CONTRACT);
         }
@@ -515,7 +1512,7 @@ mod tests {
     #[test]
     fn load_wasm_errors_for_non_existent_id() {
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(make_testing_options()).unwrap() };
+            Cache::new(make_testing_options()).unwrap();
         let checksum = Checksum::from([
             5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5, 5,
             5, 5, 5,
@@ -537,10 +1534,16 @@ mod tests {
             base_dir: tmp_dir.path().to_path_buf(),
             available_capabilities: default_capabilities(),
             memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
             instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
         };
-        let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(options).unwrap() };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         // Corrupt cache file
@@ -561,22 +1564,190 @@ mod tests {
     }
 
     #[test]
-    fn get_instance_finds_cached_module() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+    fn get_instance_recompiles_corrupted_module() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            ..make_testing_options()
+        };
+        let cache = Cache::new(options).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
-        let backend = mock_backend(&[]);
+
+        // Corrupt the compiled module
+        let modules_path = tmp_dir.path().join(CACHE_DIR).join(MODULES_DIR);
+        for entry in std::fs::read_dir(modules_path).unwrap() {
+            let module_path = entry.unwrap().path().join(checksum.to_hex());
+            let mut file = OpenOptions::new().write(true).open(module_path).unwrap();
+            file.write_all(b"broken data").unwrap();
+        }
+
         let _instance = cache
-            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
             .unwrap();
-        assert_eq!(cache.stats().hits_pinned_memory_cache, 0);
-        assert_eq!(cache.stats().hits_memory_cache, 0);
-        assert_eq!(cache.stats().hits_fs_cache, 1);
+        assert_eq!(cache.stats().hits_fs_cache, 0);
+        assert_eq!(cache.stats().misses, 1);
+
+        // The recompiled module was stored again
+        let backend = mock_backend(&[]);
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            ..make_testing_options()
+        };
+        let cache2: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let _instance = cache2
+            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache2.stats().hits_fs_cache, 1);
+        assert_eq!(cache2.stats().misses, 0);
+    }
+
+    #[test]
+    fn get_instance_finds_cached_module() {
+        let cache = Cache::new(make_testing_options()).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        let backend = mock_backend(&[]);
+        let _instance = cache
+            .get_instance(&checksum, backend, TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_pinned_memory_cache, 0);
+        assert_eq!(cache.stats().hits_memory_cache, 0);
+        assert_eq!(cache.stats().hits_fs_cache, 1);
         assert_eq!(cache.stats().misses, 0);
     }
 
+    #[test]
+    fn get_instance_uses_gas_config() {
+        // instantiate of hackatom prints the debug message "here we go 🚀" (15 bytes)
+        fn instantiate_gas(
+            cache: &Cache<MockApi, MockStorage, MockQuerier>,
+            checksum: &Checksum,
+            options: InstanceOptions,
+        ) -> u64 {
+            let mut instance = cache
+                .get_instance(checksum, mock_backend(&[]), options)
+                .unwrap();
+            let info = mock_info("creator", &coins(1000, "earth"));
+            let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+            call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+                .unwrap()
+                .unwrap();
+            instance.create_gas_report().used_internally
+        }
+
+        let default_cache = Cache::new(make_testing_options()).unwrap();
+        let checksum = default_cache.save_wasm(CONTRACT).unwrap();
+        let default_gas = instantiate_gas(&default_cache, &checksum, TESTING_OPTIONS);
+
+        let cache = Cache::new(CacheOptions {
+            gas_config: GasConfig {
+                debug_cost_per_byte: 1_000_000,
+                ..GasConfig::default()
+            },
+            ..make_testing_options()
+        })
+        .unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        assert_eq!(
+            instantiate_gas(&cache, &checksum, TESTING_OPTIONS),
+            default_gas + 15 * 1_000_000
+        );
+
+        // Override per instance
+        let options = InstanceOptions {
+            gas_config: Some(GasConfig {
+                debug_cost_per_byte: 2_000_000,
+                ..GasConfig::default()
+            }),
+            ..TESTING_OPTIONS
+        };
+        assert_eq!(
+            instantiate_gas(&cache, &checksum, options),
+            default_gas + 15 * 2_000_000
+        );
+    }
+
+    #[test]
+    fn get_instance_charges_memory_pages_with_v2_gas_cost_table() {
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+
+        let mut gas_used = vec![];
+        for gas_cost_table in [GasCostTable::default(), GasCostTable::v2()] {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(CacheOptions {
+                gas_cost_table,
+                ..make_testing_options()
+            })
+            .unwrap();
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            let mut instance = cache
+                .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+                .unwrap();
+            call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+                .unwrap()
+                .unwrap();
+            gas_used.push(instance.create_gas_report().used_internally);
+        }
+
+        // instantiate grows the memory by one page, which is free by default
+        assert_eq!(gas_used[1], gas_used[0] + 1_228_800_000);
+    }
+
+    #[test]
+    fn get_instance_recompiles_modules_of_other_gas_cost_tables() {
+        let tmp_dir = TempDir::new().unwrap();
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+
+        let default_cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            ..make_testing_options()
+        })
+        .unwrap();
+        let checksum = default_cache.save_wasm(CONTRACT).unwrap();
+        let mut instance = default_cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(default_cache.stats().hits_fs_cache, 1);
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        let default_gas_used = instance.create_gas_report().used_internally;
+
+        let expensive_cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            gas_cost_table: GasCostTable {
+                calls: 1_000_000,
+                ..GasCostTable::default()
+            },
+            ..make_testing_options()
+        })
+        .unwrap();
+        let mut instance = expensive_cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(expensive_cache.stats().hits_fs_cache, 0);
+        assert_eq!(expensive_cache.stats().misses, 1);
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        assert!(instance.create_gas_report().used_internally > default_gas_used);
+
+        // Modules compiled with the default table are still there
+        let default_cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            ..make_testing_options()
+        })
+        .unwrap();
+        let _instance = default_cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(default_cache.stats().hits_fs_cache, 1);
+        assert_eq!(default_cache.stats().misses, 0);
+    }
+
     #[test]
     fn get_instance_finds_cached_modules_and_stores_to_memory() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
         let backend1 = mock_backend(&[]);
         let backend2 = mock_backend(&[]);
@@ -637,9 +1808,209 @@ mod tests {
         assert_eq!(cache.stats().misses, 0);
     }
 
+    #[test]
+    fn warm_up_works() {
+        let cache = Cache::new(make_stargate_testing_options()).unwrap();
+        let checksum1 = cache.save_wasm(CONTRACT).unwrap();
+        let checksum2 = cache.save_wasm(IBC_CONTRACT).unwrap();
+        let non_existent = Checksum::generate(b"non_existent");
+
+        // Only available as Wasm
+        let modules_path = cache.inner.lock().unwrap().wasm_path.clone();
+        let modules_path = modules_path
+            .parent()
+            .unwrap()
+            .parent()
+            .unwrap()
+            .join(CACHE_DIR)
+            .join(MODULES_DIR);
+        std::fs::remove_dir_all(modules_path).unwrap();
+
+        let entries = cache.warm_up(&[checksum1, non_existent, checksum2], 2);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].checksum, checksum1);
+        assert_eq!(entries[0].result.as_ref().unwrap(), &WarmUpAction::Compiled);
+        assert_eq!(entries[1].checksum, non_existent);
+        match &entries[1].result {
+            Err(VmError::CacheErr { msg, .. }) => {
+                assert!(msg.starts_with("Error opening Wasm file for reading"))
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        assert_eq!(entries[2].checksum, checksum2);
+        assert_eq!(entries[2].result.as_ref().unwrap(), &WarmUpAction::Compiled);
+
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_memory_cache, 2);
+        assert_eq!(metrics.elements_fs_cache, 2);
+        assert_eq!(metrics.stats.misses, 0);
+
+        // Served from memory now
+        let _instance = cache
+            .get_instance(&checksum1, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_memory_cache, 1);
+        assert_eq!(cache.stats().hits_fs_cache, 0);
+        assert_eq!(cache.stats().misses, 0);
+
+        // Warming up again is a no-op
+        let entries = cache.warm_up(&[checksum1, checksum2], 2);
+        assert_eq!(
+            entries[0].result.as_ref().unwrap(),
+            &WarmUpAction::AlreadyInMemory
+        );
+        assert_eq!(
+            entries[1].result.as_ref().unwrap(),
+            &WarmUpAction::AlreadyInMemory
+        );
+    }
+
+    #[test]
+    fn warm_up_loads_from_disk() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        let entries = cache.warm_up(&[checksum], 0);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(
+            entries[0].result.as_ref().unwrap(),
+            &WarmUpAction::LoadedFromDisk
+        );
+        assert_eq!(cache.metrics().elements_memory_cache, 1);
+
+        // Nothing to do
+        assert_eq!(cache.warm_up(&[], 4).len(), 0);
+    }
+
+    #[test]
+    fn warm_up_reports_modules_not_admitted() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(CacheOptions {
+            memory_cache_size: Size(0),
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::TinyLfu,
+            ..make_testing_options()
+        })
+        .unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        let entries = cache.warm_up(&[checksum], 1);
+        assert_eq!(
+            entries[0].result.as_ref().unwrap(),
+            &WarmUpAction::NotAdmitted
+        );
+
+        // Compiled but not admitted
+        cache
+            .inner
+            .lock()
+            .unwrap()
+            .fs_cache
+            .remove(&checksum)
+            .unwrap();
+        let entries = cache.warm_up(&[checksum], 1);
+        assert_eq!(
+            entries[0].result.as_ref().unwrap(),
+            &WarmUpAction::NotAdmitted
+        );
+        assert_eq!(cache.metrics().elements_fs_cache, 1);
+        assert_eq!(cache.metrics().elements_memory_cache, 0);
+    }
+
+    #[test]
+    fn set_memory_cache_size_works() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.metrics().elements_memory_cache, 1);
+
+        // Too small for the module
+        cache.set_memory_cache_size(Size::kibi(1));
+        assert_eq!(cache.metrics().elements_memory_cache, 0);
+        assert_eq!(cache.metrics().size_memory_cache, 0);
+
+        cache.set_memory_cache_size(TESTING_MEMORY_CACHE_SIZE);
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.metrics().elements_memory_cache, 1);
+        assert_eq!(cache.stats().hits_fs_cache, 2);
+    }
+
+    #[test]
+    fn metrics_per_contract_works() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        assert_eq!(cache.metrics_per_contract().len(), 0);
+
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        let metrics = cache.metrics_per_contract()[&checksum];
+        assert!(metrics.compile_time > Duration::default());
+        assert!(metrics.module_size > 0);
+        assert_eq!(metrics.load_time, Duration::default());
+        assert_eq!(metrics.hits_fs_cache, 0);
+
+        // from fs cache
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        // from memory cache
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        // from pinned memory cache
+        cache.pin(&checksum).unwrap();
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+
+        let metrics = cache.metrics_per_contract()[&checksum];
+        assert_eq!(metrics.hits_fs_cache, 1);
+        assert_eq!(metrics.hits_memory_cache, 2);
+        assert_eq!(metrics.hits_pinned_memory_cache, 1);
+        assert_eq!(metrics.misses, 0);
+        assert!(metrics.load_time > Duration::default());
+
+        let exposition = cache.prometheus_metrics();
+        assert!(exposition.contains(&format!(
+            "cosmwasm_vm_contract_cache_hits_total{{checksum=\"{}\",layer=\"memory\"}} 2\n",
+            checksum.to_hex()
+        )));
+        assert!(exposition.contains("cosmwasm_vm_cache_hits_total{layer=\"pinned_memory\"} 1\n"));
+
+        cache.remove_wasm(&checksum).unwrap();
+        assert_eq!(cache.metrics_per_contract().len(), 0);
+    }
+
+    #[test]
+    fn metrics_per_contract_counts_misses() {
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        let compile_time = cache.metrics_per_contract()[&checksum].compile_time;
+
+        // Remove compiled module from disk
+        cache
+            .inner
+            .lock()
+            .unwrap()
+            .fs_cache
+            .remove(&checksum)
+            .unwrap();
+
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        let metrics = cache.metrics_per_contract()[&checksum];
+        assert_eq!(metrics.misses, 1);
+        assert!(metrics.compile_time > compile_time);
+    }
+
     #[test]
     fn call_instantiate_on_cached_contract() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         // from file system
@@ -704,7 +2075,7 @@ mod tests {
 
     #[test]
     fn call_execute_on_cached_contract() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         // from file system
@@ -796,7 +2167,7 @@ mod tests {
 
     #[test]
     fn use_multiple_cached_instances_of_same_contract() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         // these differentiate the two instances of the same contract
@@ -850,7 +2221,7 @@ mod tests {
 
     #[test]
     fn resets_gas_when_reusing_instance() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         let backend1 = mock_backend(&[]);
@@ -887,7 +2258,7 @@ mod tests {
 
     #[test]
     fn recovers_from_out_of_gas() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         let backend1 = mock_backend(&[]);
@@ -896,7 +2267,10 @@ mod tests {
         // Init from module cache
         let options = InstanceOptions {
             gas_limit: 10,
-            print_debug: false,
+            gas_config: None,
+            gas_profiling: false,
+            wasm_backtraces: false,
+            wasm_limits: None,
         };
         let mut instance1 = cache.get_instance(&checksum, backend1, options).unwrap();
         assert_eq!(cache.stats().hits_fs_cache, 1);
@@ -916,7 +2290,10 @@ mod tests {
         // Init from memory cache
         let options = InstanceOptions {
             gas_limit: TESTING_GAS_LIMIT,
-            print_debug: false,
+            gas_config: None,
+            gas_profiling: false,
+            wasm_backtraces: false,
+            wasm_limits: None,
         };
         let mut instance2 = cache.get_instance(&checksum, backend2, options).unwrap();
         assert_eq!(cache.stats().hits_pinned_memory_cache, 0);
@@ -939,8 +2316,8 @@ mod tests {
         let path = tmp_dir.path();
         let code = vec![12u8; 17];
 
-        save_wasm_to_disk(path, &code).unwrap();
-        save_wasm_to_disk(path, &code).unwrap();
+        save_wasm_to_disk(path, &code, WasmCompression::None).unwrap();
+        save_wasm_to_disk(path, &code, WasmCompression::None).unwrap();
     }
 
     #[test]
@@ -948,7 +2325,7 @@ mod tests {
         let tmp_dir = TempDir::new().unwrap();
         let path = tmp_dir.path().join("something");
         let code = vec![12u8; 17];
-        let res = save_wasm_to_disk(path.to_str().unwrap(), &code);
+        let res = save_wasm_to_disk(path.to_str().unwrap(), &code, WasmCompression::None);
         assert!(res.is_err());
     }
 
@@ -957,7 +2334,7 @@ mod tests {
         let tmp_dir = TempDir::new().unwrap();
         let path = tmp_dir.path();
         let code = vec![12u8; 17];
-        let checksum = save_wasm_to_disk(path, &code).unwrap();
+        let checksum = save_wasm_to_disk(path, &code, WasmCompression::None).unwrap();
 
         let loaded = load_wasm_from_disk(path, &checksum).unwrap();
         assert_eq!(code, loaded);
@@ -969,45 +2346,557 @@ mod tests {
         let path = tmp_dir.path().join("something");
         create_dir_all(&path).unwrap();
         let code = vec![12u8; 17];
-        let checksum = save_wasm_to_disk(&path, &code).unwrap();
+        let checksum = save_wasm_to_disk(&path, &code, WasmCompression::None).unwrap();
 
         let loaded = load_wasm_from_disk(&path, &checksum).unwrap();
         assert_eq!(code, loaded);
     }
 
+    #[test]
+    fn load_wasm_from_disk_works_for_compressed_and_raw_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path();
+
+        let checksum = save_wasm_to_disk(path, CONTRACT, WasmCompression::Gzip).unwrap();
+        assert_eq!(checksum, Checksum::generate(CONTRACT));
+        let stored = std::fs::read(path.join(checksum.to_hex())).unwrap();
+        assert!(stored.len() < CONTRACT.len());
+        assert_eq!(load_wasm_from_disk(path, &checksum).unwrap(), CONTRACT);
+
+        // Overwriting with a different format works
+        save_wasm_to_disk(path, CONTRACT, WasmCompression::None).unwrap();
+        let stored = std::fs::read(path.join(checksum.to_hex())).unwrap();
+        assert_eq!(stored, CONTRACT);
+        assert_eq!(load_wasm_from_disk(path, &checksum).unwrap(), CONTRACT);
+    }
+
+    #[test]
+    fn load_verified_wasm_from_disk_checks_decompressed_data() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path();
+        let checksum = Checksum::generate(CONTRACT);
+
+        // Valid gzip data of different content stored under the checksum
+        let other = compress_wasm(b"\0asm\x01\0\0\0", WasmCompression::Gzip);
+        std::fs::write(path.join(checksum.to_hex()), other).unwrap();
+        match load_verified_wasm_from_disk(path, &checksum).unwrap_err() {
+            VmError::IntegrityErr { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn cache_with_compression_works() {
+        let options = CacheOptions {
+            wasm_compression: WasmCompression::Gzip,
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+            ..make_testing_options()
+        };
+        let base_dir = options.base_dir.clone();
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+
+        // Legacy raw file from before compression was enabled
+        let legacy_checksum = Checksum::generate(CONTRACT);
+        std::fs::write(
+            base_dir
+                .join(STATE_DIR)
+                .join(WASM_DIR)
+                .join(legacy_checksum.to_hex()),
+            CONTRACT,
+        )
+        .unwrap();
+        assert_eq!(cache.load_wasm(&legacy_checksum).unwrap(), CONTRACT);
+
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        assert_eq!(checksum, legacy_checksum);
+        let stored = std::fs::read(
+            base_dir
+                .join(STATE_DIR)
+                .join(WASM_DIR)
+                .join(checksum.to_hex()),
+        )
+        .unwrap();
+        assert_ne!(stored, CONTRACT);
+        assert_eq!(cache.load_wasm(&checksum).unwrap(), CONTRACT);
+
+        // Recompilation from the compressed Wasm
+        cache
+            .inner
+            .lock()
+            .unwrap()
+            .fs_cache
+            .remove(&checksum)
+            .unwrap();
+        cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn remove_wasm_from_disk_works() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path();
+        let code = vec![12u8; 17];
+        let checksum = save_wasm_to_disk(path, &code, WasmCompression::None).unwrap();
+
+        remove_wasm_from_disk(path, &checksum).unwrap();
+
+        // removing again fails
+        match remove_wasm_from_disk(path, &checksum).unwrap_err() {
+            VmError::CacheErr { msg, .. } => assert_eq!(msg, "Wasm file does not exist"),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn export_and_import_snapshot_work() {
+        let source: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_stargate_testing_options()).unwrap();
+        let checksum1 = source.save_wasm(CONTRACT).unwrap();
+        let checksum2 = source.save_wasm(IBC_CONTRACT).unwrap();
+
+        // Without modules
+        let mut snapshot = Vec::new();
+        let report = source.export_snapshot(&mut snapshot, false).unwrap();
+        assert_eq!(
+            report,
+            SnapshotReport {
+                wasm_blobs: 2,
+                modules: 0,
+                skipped_modules: 0
+            }
+        );
+        let target: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_stargate_testing_options()).unwrap();
+        let report = target.import_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(report.wasm_blobs, 2);
+        assert_eq!(target.load_wasm(&checksum1).unwrap(), CONTRACT);
+        assert_eq!(target.load_wasm(&checksum2).unwrap(), IBC_CONTRACT);
+        // compiled on first use
+        assert_eq!(target.metrics().elements_fs_cache, 0);
+        target
+            .get_instance(&checksum1, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(target.stats().misses, 1);
+
+        // With modules
+        let mut snapshot = Vec::new();
+        let report = source.export_snapshot(&mut snapshot, true).unwrap();
+        assert_eq!(report.wasm_blobs, 2);
+        assert_eq!(report.modules, 2);
+
+        // The safe import ignores the modules
+        let target: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_stargate_testing_options()).unwrap();
+        let report = target.import_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(
+            report,
+            SnapshotReport {
+                wasm_blobs: 2,
+                modules: 0,
+                skipped_modules: 2
+            }
+        );
+        assert_eq!(target.metrics().elements_fs_cache, 0);
+
+        let target: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_stargate_testing_options()).unwrap();
+        let report = unsafe { target.import_snapshot_with_modules(snapshot.as_slice()) }.unwrap();
+        assert_eq!(
+            report,
+            SnapshotReport {
+                wasm_blobs: 2,
+                modules: 2,
+                skipped_modules: 0
+            }
+        );
+        assert_eq!(target.metrics().elements_fs_cache, 2);
+        target
+            .get_instance(&checksum1, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(target.stats().hits_fs_cache, 1);
+        assert_eq!(target.stats().misses, 0);
+    }
+
+    #[test]
+    fn import_snapshot_skips_modules_of_other_versions() {
+        let source: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let checksum = source.save_wasm(CONTRACT).unwrap();
+        let (version, module) = {
+            let cache = source.inner.lock().unwrap();
+            let module = cache.fs_cache.read_raw(&checksum).unwrap().unwrap();
+            (cache.fs_cache.wasmer_module_version(), module)
+        };
+
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        writer.write_wasm(&checksum, CONTRACT).unwrap();
+        writer.write_module(&checksum, 0xDEADBEEF, &module).unwrap();
+        let snapshot = writer.finish().unwrap();
+
+        let target: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let report = unsafe { target.import_snapshot_with_modules(snapshot.as_slice()) }.unwrap();
+        assert_eq!(
+            report,
+            SnapshotReport {
+                wasm_blobs: 1,
+                modules: 0,
+                skipped_modules: 1
+            }
+        );
+        assert_eq!(target.metrics().elements_fs_cache, 0);
+
+        // A module compiled with another gas cost table
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        writer.write_wasm(&checksum, CONTRACT).unwrap();
+        writer.write_module(&checksum, version, &module).unwrap();
+        let snapshot = writer.finish().unwrap();
+        let target: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(CacheOptions {
+            gas_cost_table: GasCostTable::v2(),
+            ..make_testing_options()
+        })
+        .unwrap();
+        let report = unsafe { target.import_snapshot_with_modules(snapshot.as_slice()) }.unwrap();
+        assert_eq!(
+            report,
+            SnapshotReport {
+                wasm_blobs: 1,
+                modules: 0,
+                skipped_modules: 1
+            }
+        );
+        assert_eq!(target.metrics().elements_fs_cache, 0);
+    }
+
+    #[test]
+    fn import_snapshot_verifies_checksums() {
+        let checksum = Checksum::generate(CONTRACT);
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        writer
+            .write_wasm(&checksum, b"\0asm something else")
+            .unwrap();
+        let snapshot = writer.finish().unwrap();
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        match cache.import_snapshot(snapshot.as_slice()).unwrap_err() {
+            VmError::CacheErr { msg, .. } => assert_eq!(
+                msg,
+                format!("Checksum mismatch for Wasm {} in snapshot", checksum)
+            ),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert!(cache.load_wasm(&checksum).is_err());
+
+        // A module that does not belong to the checksum
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        let version = cache.inner.lock().unwrap().fs_cache.wasmer_module_version();
+        writer.write_module(&checksum, version, b"garbage").unwrap();
+        let snapshot = writer.finish().unwrap();
+        match unsafe { cache.import_snapshot_with_modules(snapshot.as_slice()) }.unwrap_err() {
+            VmError::CacheErr { msg, .. } => assert_eq!(
+                msg,
+                format!(
+                    "Invalid integrity envelope of compiled module for {}",
+                    checksum
+                )
+            ),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn import_snapshot_does_not_check_wasm() {
+        // Valid Wasm that is not a contract, e.g. one stored under other rules
+        let wasm = wat::parse_str("(module)").unwrap();
+        let checksum = Checksum::generate(&wasm);
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        writer.write_wasm(&checksum, &wasm).unwrap();
+        let snapshot = writer.finish().unwrap();
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let report = cache.import_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(report.wasm_blobs, 1);
+        assert_eq!(cache.load_wasm(&checksum).unwrap(), wasm);
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> =
+            Cache::new(make_testing_options()).unwrap();
+        let report = unsafe { cache.import_snapshot_with_modules(snapshot.as_slice()) }.unwrap();
+        assert_eq!(report.wasm_blobs, 1);
+        assert_eq!(cache.load_wasm(&checksum).unwrap(), wasm);
+    }
+
+    #[test]
+    fn upgrade_modules_works() {
+        let options = make_stargate_testing_options();
+        let modules_path = options.base_dir.join(CACHE_DIR).join(MODULES_DIR);
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
+        let checksum1 = cache.save_wasm(CONTRACT).unwrap();
+        let checksum2 = cache.save_wasm(IBC_CONTRACT).unwrap();
+        assert_eq!(cache.missing_modules().unwrap(), vec![]);
+
+        // Simulate modules from before an upgrade: checksum1 only exists in v3, checksum2 in v4.
+        // Both are incompatible since the stack limiter was added in v6.
+        let version = current_wasmer_module_version();
+        let latest_path = modules_path.join(format!("v6-wasmer{}", version));
+        let v3_path = modules_path.join(format!("v3-wasmer{}", version));
+        let v4_path = modules_path.join(format!("v4-wasmer{}", version));
+        std::fs::create_dir_all(&v3_path).unwrap();
+        std::fs::create_dir_all(&v4_path).unwrap();
+        std::fs::rename(
+            latest_path.join(checksum1.to_hex()),
+            v3_path.join(checksum1.to_hex()),
+        )
+        .unwrap();
+        let module2 = compile(IBC_CONTRACT, None, &[]).unwrap();
+        std::fs::write(
+            v4_path.join(checksum2.to_hex()),
+            module2.serialize().unwrap(),
+        )
+        .unwrap();
+        std::fs::remove_file(latest_path.join(checksum2.to_hex())).unwrap();
+        drop(cache);
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let mut expected_missing = vec![checksum1, checksum2];
+        expected_missing.sort_by_key(|checksum| checksum.to_hex());
+        assert_eq!(cache.missing_modules().unwrap(), expected_missing);
+
+        let report = cache.upgrade_modules(2).unwrap();
+        assert_eq!(report.migrated, vec![]);
+        assert_eq!(report.recompiled, expected_missing);
+        assert_eq!(report.failed.len(), 0);
+        assert_eq!(cache.missing_modules().unwrap(), vec![]);
+        let metrics = cache.metrics();
+        assert_eq!(metrics.migrated_fs_cache, 0);
+        assert_eq!(metrics.elements_fs_cache, 2);
+        assert_eq!(metrics.elements_memory_cache, 0);
+
+        // Nothing left to do
+        let report = cache.upgrade_modules(2).unwrap();
+        assert_eq!(report.migrated.len() + report.recompiled.len(), 0);
+    }
+
+    #[test]
+    fn missing_modules_ignores_removed_contracts() {
+        let options = make_testing_options();
+        let modules_path = options.base_dir.join(CACHE_DIR).join(MODULES_DIR);
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+
+        // A module of a previous version without Wasm
+        let checksum = Checksum::generate(b"removed");
+        let v3_path = modules_path.join(format!("v3-wasmer{}", current_wasmer_module_version()));
+        std::fs::create_dir_all(&v3_path).unwrap();
+        std::fs::write(v3_path.join(checksum.to_hex()), b"module").unwrap();
+
+        assert_eq!(cache.missing_modules().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn upgrade_modules_works_after_collect_garbage() {
+        let options = make_testing_options();
+        let modules_path = options.base_dir.join(CACHE_DIR).join(MODULES_DIR);
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        // Simulate a module from before an upgrade
+        let version = current_wasmer_module_version();
+        let latest_path = modules_path.join(format!("v6-wasmer{}", version));
+        let v4_path = modules_path.join(format!("v4-wasmer{}", version));
+        std::fs::create_dir_all(&v4_path).unwrap();
+        std::fs::rename(
+            latest_path.join(checksum.to_hex()),
+            v4_path.join(checksum.to_hex()),
+        )
+        .unwrap();
+        drop(cache);
+
+        // The outdated directory is removed before the upgrade
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let report = cache.collect_garbage().unwrap();
+        assert_eq!(report.removed_version_dirs, 1);
+        assert!(!v4_path.exists());
+        assert_eq!(cache.missing_modules().unwrap(), vec![checksum]);
+
+        let report = cache.upgrade_modules(1).unwrap();
+        assert_eq!(report.migrated, vec![]);
+        assert_eq!(report.recompiled, vec![checksum]);
+        assert_eq!(report.failed.len(), 0);
+        assert_eq!(cache.missing_modules().unwrap(), vec![]);
+        assert!(latest_path.join(checksum.to_hex()).exists());
+    }
+
     #[test]
     fn analyze_works() {
         let cache: Cache<MockApi, MockStorage, MockQuerier> =
-            unsafe { Cache::new(make_stargate_testing_options()).unwrap() };
+            Cache::new(make_stargate_testing_options()).unwrap();
 
         let checksum1 = cache.save_wasm(CONTRACT).unwrap();
         let report1 = cache.analyze(&checksum1).unwrap();
+        assert!(!report1.has_ibc_entry_points);
+        assert_eq!(report1.required_capabilities, HashSet::new());
         assert_eq!(
-            report1,
-            AnalysisReport {
-                has_ibc_entry_points: false,
-                required_capabilities: HashSet::new(),
-            }
+            report1.entry_points,
+            BTreeSet::from_iter(
+                ["instantiate", "execute", "migrate", "sudo", "query"].map(String::from)
+            )
         );
+        assert_eq!(report1.interface_version, Some(8));
+        assert!(report1.imported_functions.contains("env.db_read"));
+        assert!(!report1.imported_functions.contains("env.db_scan"));
+        assert_eq!(report1.wasm_size, CONTRACT.len());
+        assert_eq!(report1.initial_memory_pages, 17);
+        assert!(report1.function_count > 0);
+        assert!(report1.data_segment_size > 0);
 
         let checksum2 = cache.save_wasm(IBC_CONTRACT).unwrap();
         let report2 = cache.analyze(&checksum2).unwrap();
+        assert!(report2.has_ibc_entry_points);
         assert_eq!(
-            report2,
-            AnalysisReport {
-                has_ibc_entry_points: true,
-                required_capabilities: HashSet::from_iter(vec![
-                    "iterator".to_string(),
-                    "staking".to_string(),
-                    "stargate".to_string()
-                ]),
-            }
+            report2.required_capabilities,
+            HashSet::from_iter(vec![
+                "iterator".to_string(),
+                "staking".to_string(),
+                "stargate".to_string()
+            ])
+        );
+        assert_eq!(
+            report2.entry_points,
+            BTreeSet::from_iter(
+                [
+                    "instantiate",
+                    "migrate",
+                    "reply",
+                    "query",
+                    "ibc_channel_open",
+                    "ibc_channel_connect",
+                    "ibc_channel_close",
+                    "ibc_packet_receive",
+                    "ibc_packet_ack",
+                    "ibc_packet_timeout",
+                ]
+                .map(String::from)
+            )
         );
+        assert!(report2.imported_functions.contains("env.db_scan"));
+        assert_eq!(report2.wasm_size, IBC_CONTRACT.len());
+    }
+
+    #[test]
+    fn save_wasm_uses_configured_limits() {
+        let options = CacheOptions {
+            wasm_limits: WasmLimits {
+                max_memory_pages: 16,
+                ..WasmLimits::default()
+            },
+            ..make_testing_options()
+        };
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        // hackatom has 17 initial pages
+        match cache.save_wasm(CONTRACT).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract memory's minimum must not exceed 16 pages."
+            ),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn analyze_uses_stored_results() {
+        let options = make_testing_options();
+        let wasm_dir = options.base_dir.join(STATE_DIR).join(WASM_DIR);
+        let analysis_dir = options.base_dir.join(CACHE_DIR).join(ANALYSIS_DIR);
+        let cache1: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
+        let checksum = cache1.save_wasm(CONTRACT).unwrap();
+        let expected = cache1.analyze(&checksum).unwrap();
+        assert!(analysis_dir
+            .join(format!("{}.json", checksum.to_hex()))
+            .exists());
+
+        // Results are read from disk without touching the Wasm blob
+        std::fs::rename(
+            wasm_dir.join(checksum.to_hex()),
+            wasm_dir.join("moved_away"),
+        )
+        .unwrap();
+        let cache2: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options.clone()).unwrap();
+        assert_eq!(cache2.analyze(&checksum).unwrap(), expected);
+
+        // Missing results are computed from the Wasm blob and stored again
+        std::fs::rename(
+            wasm_dir.join("moved_away"),
+            wasm_dir.join(checksum.to_hex()),
+        )
+        .unwrap();
+        std::fs::remove_dir_all(&analysis_dir).unwrap();
+        std::fs::create_dir(&analysis_dir).unwrap();
+        let cache3: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        assert_eq!(cache3.analyze(&checksum).unwrap(), expected);
+        assert!(analysis_dir
+            .join(format!("{}.json", checksum.to_hex()))
+            .exists());
+
+        // Removing the contract removes the stored result
+        cache3.remove_wasm(&checksum).unwrap();
+        assert!(!analysis_dir
+            .join(format!("{}.json", checksum.to_hex()))
+            .exists());
+    }
+
+    #[test]
+    fn cache_options_new_works() {
+        let options = CacheOptions::new(
+            TempDir::new().unwrap().into_path(),
+            default_capabilities(),
+            TESTING_MEMORY_CACHE_SIZE,
+            TESTING_MEMORY_LIMIT,
+        );
+        assert!(options.fs_cache_size.is_none());
+        assert_eq!(options.pinned_restore_mode, PinnedRestoreMode::Eager);
+        assert_eq!(options.wasm_compression, WasmCompression::None);
+        assert_eq!(options.gas_cost_table, GasCostTable::default());
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+        cache
+            .get_instance(
+                &checksum,
+                mock_backend(&[]),
+                InstanceOptions::new(TESTING_GAS_LIMIT),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn pin_rolls_back_when_manifest_cannot_be_written() {
+        let options = make_testing_options();
+        let manifest_path = options.base_dir.join(STATE_DIR).join(PINNED_MANIFEST_FILE);
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let checksum = cache.save_wasm(CONTRACT).unwrap();
+
+        // The manifest cannot be replaced by a file anymore
+        std::fs::create_dir(&manifest_path).unwrap();
+        match cache.pin(&checksum).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.starts_with("Error writing pinned manifest"))
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(cache.metrics().elements_pinned_memory_cache, 0);
+
+        std::fs::remove_dir(&manifest_path).unwrap();
+        cache.pin(&checksum).unwrap();
+        assert_eq!(cache.metrics().elements_pinned_memory_cache, 1);
     }
 
     #[test]
     fn pin_unpin_works() {
-        let cache = unsafe { Cache::new(make_testing_options()).unwrap() };
+        let cache = Cache::new(make_testing_options()).unwrap();
         let checksum = cache.save_wasm(CONTRACT).unwrap();
 
         // check not pinned
@@ -1064,4 +2953,169 @@ mod tests {
         let non_id = Checksum::generate(b"non_existent");
         cache.unpin(&non_id).unwrap();
     }
+
+    #[test]
+    fn pinned_contracts_are_restored_eagerly() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            available_capabilities: default_capabilities(),
+            memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
+            instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+        };
+
+        let checksum = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                Cache::new(options.clone()).unwrap();
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            cache.pin(&checksum).unwrap();
+            checksum
+        };
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_pinned_memory_cache, 1);
+        assert_eq!(metrics.pending_pinned_restores, 0);
+        assert_eq!(metrics.failed_pinned_restores, 0);
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+
+        let _instance = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_pinned_memory_cache, 1);
+        assert_eq!(cache.stats().hits_memory_cache, 0);
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+        assert_eq!(cache.stats().misses, 0);
+    }
+
+    #[test]
+    fn pinned_contracts_are_restored_lazily() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            available_capabilities: default_capabilities(),
+            memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
+            instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Lazy,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+        };
+
+        let checksum = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                Cache::new(options.clone()).unwrap();
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            cache.pin(&checksum).unwrap();
+            checksum
+        };
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_pinned_memory_cache, 0);
+        assert_eq!(metrics.pending_pinned_restores, 1);
+        assert_eq!(metrics.failed_pinned_restores, 0);
+
+        // first use restores the pinned module
+        let _instance = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_pinned_memory_cache, 1);
+        assert_eq!(metrics.elements_memory_cache, 0);
+        assert_eq!(metrics.pending_pinned_restores, 0);
+        assert_eq!(cache.stats().hits_pinned_memory_cache, 1);
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+
+        let _instance = cache
+            .get_instance(&checksum, mock_backend(&[]), TESTING_OPTIONS)
+            .unwrap();
+        assert_eq!(cache.stats().hits_pinned_memory_cache, 2);
+        assert_eq!(cache.stats().hits_fs_cache, 1);
+    }
+
+    #[test]
+    fn unpinned_contracts_are_not_restored() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            available_capabilities: default_capabilities(),
+            memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
+            instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+        };
+
+        {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                Cache::new(options.clone()).unwrap();
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            cache.pin(&checksum).unwrap();
+            cache.unpin(&checksum).unwrap();
+        }
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_pinned_memory_cache, 0);
+        assert_eq!(metrics.pending_pinned_restores, 0);
+        assert_eq!(metrics.failed_pinned_restores, 0);
+    }
+
+    #[test]
+    fn pinned_restore_failures_are_reported() {
+        let tmp_dir = TempDir::new().unwrap();
+        let options = CacheOptions {
+            base_dir: tmp_dir.path().to_path_buf(),
+            available_capabilities: default_capabilities(),
+            memory_cache_size: TESTING_MEMORY_CACHE_SIZE,
+            memory_cache_eviction_policy: MemoryCacheEvictionPolicy::Lru,
+            instance_memory_limit: TESTING_MEMORY_LIMIT,
+            fs_cache_size: None,
+            pinned_restore_mode: PinnedRestoreMode::Eager,
+            wasm_compression: WasmCompression::None,
+            wasm_limits: WasmLimits::default(),
+            gas_cost_table: GasCostTable::default(),
+            gas_config: GasConfig::default(),
+        };
+
+        let checksum = {
+            let cache: Cache<MockApi, MockStorage, MockQuerier> =
+                Cache::new(options.clone()).unwrap();
+            let checksum = cache.save_wasm(CONTRACT).unwrap();
+            cache.pin(&checksum).unwrap();
+            checksum
+        };
+
+        // Lose all data about the contract but the pinned manifest
+        std::fs::remove_dir_all(tmp_dir.path().join(CACHE_DIR)).unwrap();
+        std::fs::remove_file(
+            tmp_dir
+                .path()
+                .join(STATE_DIR)
+                .join(WASM_DIR)
+                .join(checksum.to_hex()),
+        )
+        .unwrap();
+
+        let cache: Cache<MockApi, MockStorage, MockQuerier> = Cache::new(options).unwrap();
+        let metrics = cache.metrics();
+        assert_eq!(metrics.elements_pinned_memory_cache, 0);
+        assert_eq!(metrics.pending_pinned_restores, 0);
+        assert_eq!(metrics.failed_pinned_restores, 1);
+    }
 }
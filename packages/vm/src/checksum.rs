@@ -17,6 +17,19 @@ impl Checksum {
         Checksum(Sha256::digest(wasm).into())
     }
 
+    /// Creates a checksum from a hex encoded string, e.g. a file name
+    /// that was created using [`Checksum::to_hex`].
+    pub fn from_hex(input: &str) -> Result<Self, VmError> {
+        let data = hex::decode(input)
+            .map_err(|e| VmError::cache_err(format!("Invalid hex checksum '{}': {}", input, e)))?;
+        Checksum::try_from(data.as_slice())
+    }
+
+    /// Returns a reference to the inner bytes of this checksum as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.0
+    }
+
     /// Creates a lowercase hex encoded copy of this checksum.
     ///
     /// This takes an owned `self` instead of a reference because `Checksum` is cheap to `Copy`.
@@ -104,6 +117,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn from_hex_works() {
+        let checksum = Checksum::generate(&[0x68, 0x69, 0x6a]);
+        let parsed = Checksum::from_hex(&checksum.to_hex()).unwrap();
+        assert_eq!(parsed, checksum);
+
+        // uppercase works too
+        let parsed = Checksum::from_hex(&checksum.to_hex().to_uppercase()).unwrap();
+        assert_eq!(parsed, checksum);
+
+        // wrong length
+        match Checksum::from_hex("aabbcc").unwrap_err() {
+            VmError::CacheErr { msg, .. } => assert_eq!(msg, "Checksum not of length 32"),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        // invalid hex
+        match Checksum::from_hex("foobar").unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.starts_with("Invalid hex checksum 'foobar'"))
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn as_slice_works() {
+        let checksum = Checksum::generate(&[12u8; 17]);
+        assert_eq!(checksum.as_slice(), &checksum.0);
+    }
+
     #[test]
     fn into_vec_works() {
         let checksum = Checksum::generate(&[12u8; 17]);
@@ -1,12 +1,16 @@
 use parity_wasm::elements::Type;
-use parity_wasm::elements::{External, ImportEntry, Module};
+use parity_wasm::elements::{External, ImportCountType, ImportEntry, Module};
 use std::collections::BTreeSet;
 use std::collections::HashSet;
+use std::fmt;
 
 use crate::capabilities::required_capabilities_from_module;
 use crate::errors::{VmError, VmResult};
 use crate::limited::LimitedDisplay;
-use crate::static_analysis::{deserialize_wasm, ExportInfo};
+use crate::static_analysis::{
+    deserialize_wasm, full_import_name, function_body_sizes, ExportInfo, INTERFACE_VERSION_PREFIX,
+};
+use crate::wasm_limits::WasmLimits;
 
 /// Lists all imports we provide upon instantiating the instance in Instance::from_module()
 /// This should be updated when new imports are added
@@ -43,35 +47,173 @@ const REQUIRED_EXPORTS: &[&str] = &[
     "instantiate",
 ];
 
-const INTERFACE_VERSION_PREFIX: &str = "interface_version_";
 const SUPPORTED_INTERFACE_VERSIONS: &[&str] = &[
     "interface_version_8",
     #[cfg(feature = "allow_interface_version_7")]
     "interface_version_7",
 ];
 
-const MEMORY_LIMIT: u32 = 512; // in pages
+/// The part of static validation that found a violation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WasmViolationCategory {
+    Size,
+    Deserialization,
+    Memories,
+    InterfaceVersion,
+    Exports,
+    Imports,
+    Capabilities,
+    Functions,
+    FunctionBodies,
+    Tables,
+    Globals,
+    DataSegments,
+    ElementSegments,
+}
 
-const MAX_FUNCTIONS: usize = 10000;
+impl WasmViolationCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WasmViolationCategory::Size => "size",
+            WasmViolationCategory::Deserialization => "deserialization",
+            WasmViolationCategory::Memories => "memories",
+            WasmViolationCategory::InterfaceVersion => "interface_version",
+            WasmViolationCategory::Exports => "exports",
+            WasmViolationCategory::Imports => "imports",
+            WasmViolationCategory::Capabilities => "capabilities",
+            WasmViolationCategory::Functions => "functions",
+            WasmViolationCategory::FunctionBodies => "function_bodies",
+            WasmViolationCategory::Tables => "tables",
+            WasmViolationCategory::Globals => "globals",
+            WasmViolationCategory::DataSegments => "data_segments",
+            WasmViolationCategory::ElementSegments => "element_segments",
+        }
+    }
+}
 
-const MAX_FUNCTION_PARAMS: usize = 50;
+impl fmt::Display for WasmViolationCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
-const MAX_FUNCTION_RESULTS: usize = 1;
+/// A single problem found by [`check_wasm_report`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WasmViolation {
+    pub category: WasmViolationCategory,
+    pub message: String,
+}
 
 /// Checks if the data is valid wasm and compatibility with the CosmWasm API (imports and exports)
-pub fn check_wasm(wasm_code: &[u8], available_capabilities: &HashSet<String>) -> VmResult<()> {
-    let module = deserialize_wasm(wasm_code)?;
-    check_wasm_memories(&module)?;
-    check_interface_version(&module)?;
-    check_wasm_exports(&module)?;
-    check_wasm_imports(&module, SUPPORTED_IMPORTS)?;
-    check_wasm_capabilities(&module, available_capabilities)?;
-    check_wasm_functions(&module)?;
+/// as well as the given limits
+///
+/// Returns the first violation as an error without running the remaining checks.
+pub fn check_wasm(
+    wasm_code: &[u8],
+    available_capabilities: &HashSet<String>,
+    limits: &WasmLimits,
+) -> VmResult<()> {
+    let mut first_violation = None;
+    run_checks(wasm_code, available_capabilities, limits, &mut |_, err| {
+        first_violation = Some(err);
+        false
+    });
+    match first_violation {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
 
+/// Runs all checks of [`check_wasm`] and collects every violation instead of stopping at the
+/// first one. Every check reports at most one violation. An empty list means the contract is valid.
+///
+/// If the Wasm exceeds the size limit or cannot be deserialized, only this violation is reported
+/// since the other checks require parsing the Wasm.
+pub fn check_wasm_report(
+    wasm_code: &[u8],
+    available_capabilities: &HashSet<String>,
+    limits: &WasmLimits,
+) -> Vec<WasmViolation> {
+    let mut violations = Vec::new();
+    run_checks(
+        wasm_code,
+        available_capabilities,
+        limits,
+        &mut |category, err| {
+            let message = match err {
+                VmError::StaticValidationErr { msg, .. } => msg,
+                err => err.to_string(),
+            };
+            violations.push(WasmViolation { category, message });
+            true
+        },
+    );
+    violations
+}
+
+/// Runs the checks in order and calls `on_violation` for every failed one.
+/// Stops when `on_violation` returns false.
+fn run_checks(
+    wasm_code: &[u8],
+    available_capabilities: &HashSet<String>,
+    limits: &WasmLimits,
+    on_violation: &mut dyn FnMut(WasmViolationCategory, VmError) -> bool,
+) {
+    use WasmViolationCategory::*;
+
+    // Oversized blobs are not parsed at all
+    if let Err(err) = check_wasm_size(wasm_code, limits) {
+        on_violation(Size, err);
+        return;
+    }
+    let module = match deserialize_wasm(wasm_code) {
+        Ok(module) => module,
+        Err(err) => {
+            on_violation(Deserialization, err);
+            return;
+        }
+    };
+
+    let checks: [(WasmViolationCategory, &dyn Fn() -> VmResult<()>); 11] = [
+        (Memories, &|| check_wasm_memories(&module, limits)),
+        (InterfaceVersion, &|| check_interface_version(&module)),
+        (Exports, &|| check_wasm_exports(&module)),
+        (Imports, &|| check_wasm_imports(&module, SUPPORTED_IMPORTS)),
+        (Capabilities, &|| {
+            check_wasm_capabilities(&module, available_capabilities)
+        }),
+        (Functions, &|| check_wasm_functions(&module, limits)),
+        (FunctionBodies, &|| {
+            check_wasm_function_bodies(wasm_code, &module, limits)
+        }),
+        (Tables, &|| check_wasm_tables(&module, limits)),
+        (Globals, &|| check_wasm_globals(&module, limits)),
+        (DataSegments, &|| check_wasm_data_segments(&module, limits)),
+        (ElementSegments, &|| {
+            check_wasm_element_segments(&module, limits)
+        }),
+    ];
+    for (category, check) in checks {
+        if let Err(err) = check() {
+            if !on_violation(category, err) {
+                return;
+            }
+        }
+    }
+}
+
+fn check_wasm_size(wasm_code: &[u8], limits: &WasmLimits) -> VmResult<()> {
+    if wasm_code.len() > limits.max_wasm_size {
+        return Err(VmError::static_validation_err(format!(
+            "Wasm contract has a size of {} bytes, exceeding the limit of {} bytes",
+            wasm_code.len(),
+            limits.max_wasm_size
+        )));
+    }
     Ok(())
 }
 
-fn check_wasm_memories(module: &Module) -> VmResult<()> {
+fn check_wasm_memories(module: &Module, limits: &WasmLimits) -> VmResult<()> {
     let section = match module.memory_section() {
         Some(section) => section,
         None => {
@@ -90,16 +232,16 @@ fn check_wasm_memories(module: &Module) -> VmResult<()> {
 
     let memory = memories[0];
     // println!("Memory: {:?}", memory);
-    let limits = memory.limits();
+    let memory_limits = memory.limits();
 
-    if limits.initial() > MEMORY_LIMIT {
+    if memory_limits.initial() > limits.max_memory_pages {
         return Err(VmError::static_validation_err(format!(
             "Wasm contract memory's minimum must not exceed {} pages.",
-            MEMORY_LIMIT
+            limits.max_memory_pages
         )));
     }
 
-    if limits.maximum().is_some() {
+    if memory_limits.maximum().is_some() {
         return Err(VmError::static_validation_err(
             "Wasm contract memory's maximum must be unset. The host will set it for you.",
         ));
@@ -180,10 +322,6 @@ fn check_wasm_imports(module: &Module, supported_imports: &[&str]) -> VmResult<(
     Ok(())
 }
 
-fn full_import_name(ie: &ImportEntry) -> String {
-    format!("{}.{}", ie.module(), ie.field())
-}
-
 fn check_wasm_capabilities(
     module: &Module,
     available_capabilities: &HashSet<String>,
@@ -202,15 +340,16 @@ fn check_wasm_capabilities(
     Ok(())
 }
 
-fn check_wasm_functions(module: &Module) -> VmResult<()> {
+fn check_wasm_functions(module: &Module, limits: &WasmLimits) -> VmResult<()> {
     let functions = module
         .function_section()
         .map(|fs| fs.entries())
         .unwrap_or_default();
 
-    if functions.len() > MAX_FUNCTIONS {
+    if functions.len() > limits.max_functions {
         return Err(VmError::static_validation_err(format!(
-            "Wasm contract contains more than {MAX_FUNCTIONS} functions"
+            "Wasm contract contains more than {} functions",
+            limits.max_functions
         )));
     }
 
@@ -234,14 +373,103 @@ fn check_wasm_functions(module: &Module) -> VmResult<()> {
         .max()
         .unwrap_or_default();
 
-    if max_func_params > MAX_FUNCTION_PARAMS {
+    if max_func_params > limits.max_function_params {
         return Err(VmError::static_validation_err(format!(
-            "Wasm contract contains function with more than {MAX_FUNCTION_PARAMS} parameters"
+            "Wasm contract contains function with more than {} parameters",
+            limits.max_function_params
         )));
     }
-    if max_func_results > MAX_FUNCTION_RESULTS {
+    if max_func_results > limits.max_function_results {
         return Err(VmError::static_validation_err(format!(
-            "Wasm contract contains function with more than {MAX_FUNCTION_RESULTS} results"
+            "Wasm contract contains function with more than {} results",
+            limits.max_function_results
+        )));
+    }
+    Ok(())
+}
+
+/// Checks size and locals of every function body. Functions are named by their index in the
+/// function index space, i.e. imported functions are counted as well.
+fn check_wasm_function_bodies(
+    wasm_code: &[u8],
+    module: &Module,
+    limits: &WasmLimits,
+) -> VmResult<()> {
+    let imported_functions = module.import_count(ImportCountType::Function);
+
+    for (i, size) in function_body_sizes(wasm_code)?.into_iter().enumerate() {
+        if size > limits.max_function_body_size {
+            return Err(VmError::static_validation_err(format!(
+                "Wasm contract function {} has a body of {} bytes, exceeding the limit of {} bytes",
+                imported_functions + i,
+                size,
+                limits.max_function_body_size
+            )));
+        }
+    }
+
+    let bodies = module.code_section().map_or(&[][..], |cs| cs.bodies());
+    for (i, body) in bodies.iter().enumerate() {
+        let locals: u64 = body.locals().iter().map(|l| l.count() as u64).sum();
+        if locals > limits.max_function_locals {
+            return Err(VmError::static_validation_err(format!(
+                "Wasm contract function {} declares {} locals, exceeding the limit of {}",
+                imported_functions + i,
+                locals,
+                limits.max_function_locals
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn check_wasm_tables(module: &Module, limits: &WasmLimits) -> VmResult<()> {
+    let tables = module.table_section().map_or(&[][..], |ts| ts.entries());
+    for (i, table) in tables.iter().enumerate() {
+        if table.limits().initial() > limits.max_table_size {
+            return Err(VmError::static_validation_err(format!(
+                "Wasm contract table {} has {} elements, exceeding the limit of {}",
+                i,
+                table.limits().initial(),
+                limits.max_table_size
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn check_wasm_globals(module: &Module, limits: &WasmLimits) -> VmResult<()> {
+    let globals = module.global_section().map_or(0, |gs| gs.entries().len());
+    if globals > limits.max_globals {
+        return Err(VmError::static_validation_err(format!(
+            "Wasm contract contains {} globals, exceeding the limit of {}",
+            globals, limits.max_globals
+        )));
+    }
+    Ok(())
+}
+
+fn check_wasm_data_segments(module: &Module, limits: &WasmLimits) -> VmResult<()> {
+    let segments = module.data_section().map_or(&[][..], |ds| ds.entries());
+    for (i, segment) in segments.iter().enumerate() {
+        if segment.value().len() > limits.max_data_segment_size {
+            return Err(VmError::static_validation_err(format!(
+                "Wasm contract data segment {} has {} bytes, exceeding the limit of {} bytes",
+                i,
+                segment.value().len(),
+                limits.max_data_segment_size
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn check_wasm_element_segments(module: &Module, limits: &WasmLimits) -> VmResult<()> {
+    let segments = module.elements_section().map_or(0, |es| es.entries().len());
+    if segments > limits.max_element_segments {
+        return Err(VmError::static_validation_err(format!(
+            "Wasm contract contains {} element segments, exceeding the limit of {}",
+            segments, limits.max_element_segments
         )));
     }
     Ok(())
@@ -266,18 +494,23 @@ mod tests {
     #[test]
     fn check_wasm_passes_for_latest_contract() {
         // this is our reference check, must pass
-        check_wasm(CONTRACT, &default_capabilities()).unwrap();
+        check_wasm(CONTRACT, &default_capabilities(), &WasmLimits::default()).unwrap();
     }
 
     #[test]
     fn check_wasm_allows_sign_ext() {
         // See https://github.com/CosmWasm/cosmwasm/issues/1727
-        check_wasm(CONTRACT_RUST_170, &default_capabilities()).unwrap();
+        check_wasm(
+            CONTRACT_RUST_170,
+            &default_capabilities(),
+            &WasmLimits::default(),
+        )
+        .unwrap();
     }
 
     #[test]
     fn check_wasm_old_contract() {
-        match check_wasm(CONTRACT_0_15, &default_capabilities()) {
+        match check_wasm(CONTRACT_0_15, &default_capabilities(), &WasmLimits::default()) {
             Err(VmError::StaticValidationErr { msg, .. }) => assert_eq!(
                 msg,
                 "Wasm contract has unknown interface_version_* marker export (see https://github.com/CosmWasm/cosmwasm/blob/main/packages/vm/README.md)"
@@ -286,7 +519,7 @@ mod tests {
             Ok(_) => panic!("This must not succeeed"),
         };
 
-        match check_wasm(CONTRACT_0_14, &default_capabilities()) {
+        match check_wasm(CONTRACT_0_14, &default_capabilities(), &WasmLimits::default()) {
             Err(VmError::StaticValidationErr { msg, .. }) => assert_eq!(
                 msg,
                 "Wasm contract has unknown interface_version_* marker export (see https://github.com/CosmWasm/cosmwasm/blob/main/packages/vm/README.md)"
@@ -295,7 +528,11 @@ mod tests {
             Ok(_) => panic!("This must not succeeed"),
         };
 
-        match check_wasm(CONTRACT_0_12, &default_capabilities()) {
+        match check_wasm(
+            CONTRACT_0_12,
+            &default_capabilities(),
+            &WasmLimits::default(),
+        ) {
             Err(VmError::StaticValidationErr { msg, .. }) => assert_eq!(
                 msg,
                 "Wasm contract missing a required marker export: interface_version_*"
@@ -304,7 +541,11 @@ mod tests {
             Ok(_) => panic!("This must not succeeed"),
         };
 
-        match check_wasm(CONTRACT_0_7, &default_capabilities()) {
+        match check_wasm(
+            CONTRACT_0_7,
+            &default_capabilities(),
+            &WasmLimits::default(),
+        ) {
             Err(VmError::StaticValidationErr { msg, .. }) => assert_eq!(
                 msg,
                 "Wasm contract missing a required marker export: interface_version_*"
@@ -317,13 +558,13 @@ mod tests {
     #[test]
     fn check_wasm_memories_ok() {
         let wasm = wat::parse_str("(module (memory 1))").unwrap();
-        check_wasm_memories(&deserialize_wasm(&wasm).unwrap()).unwrap()
+        check_wasm_memories(&deserialize_wasm(&wasm).unwrap(), &WasmLimits::default()).unwrap()
     }
 
     #[test]
     fn check_wasm_memories_no_memory() {
         let wasm = wat::parse_str("(module)").unwrap();
-        match check_wasm_memories(&deserialize_wasm(&wasm).unwrap()) {
+        match check_wasm_memories(&deserialize_wasm(&wasm).unwrap(), &WasmLimits::default()) {
             Err(VmError::StaticValidationErr { msg, .. }) => {
                 assert!(msg.starts_with("Wasm contract doesn't have a memory section"));
             }
@@ -347,7 +588,7 @@ mod tests {
         ))
         .unwrap();
 
-        match check_wasm_memories(&deserialize_wasm(&wasm).unwrap()) {
+        match check_wasm_memories(&deserialize_wasm(&wasm).unwrap(), &WasmLimits::default()) {
             Err(VmError::StaticValidationErr { msg, .. }) => {
                 assert!(msg.starts_with("Wasm contract must contain exactly one memory"));
             }
@@ -368,7 +609,7 @@ mod tests {
         ))
         .unwrap();
 
-        match check_wasm_memories(&deserialize_wasm(&wasm).unwrap()) {
+        match check_wasm_memories(&deserialize_wasm(&wasm).unwrap(), &WasmLimits::default()) {
             Err(VmError::StaticValidationErr { msg, .. }) => {
                 assert!(msg.starts_with("Wasm contract must contain exactly one memory"));
             }
@@ -380,10 +621,13 @@ mod tests {
     #[test]
     fn check_wasm_memories_initial_size() {
         let wasm_ok = wat::parse_str("(module (memory 512))").unwrap();
-        check_wasm_memories(&deserialize_wasm(&wasm_ok).unwrap()).unwrap();
+        check_wasm_memories(&deserialize_wasm(&wasm_ok).unwrap(), &WasmLimits::default()).unwrap();
 
         let wasm_too_big = wat::parse_str("(module (memory 513))").unwrap();
-        match check_wasm_memories(&deserialize_wasm(&wasm_too_big).unwrap()) {
+        match check_wasm_memories(
+            &deserialize_wasm(&wasm_too_big).unwrap(),
+            &WasmLimits::default(),
+        ) {
             Err(VmError::StaticValidationErr { msg, .. }) => {
                 assert!(msg.starts_with("Wasm contract memory's minimum must not exceed 512 pages"));
             }
@@ -392,10 +636,304 @@ mod tests {
         }
     }
 
+    #[test]
+    fn check_wasm_memories_uses_custom_limit() {
+        let limits = WasmLimits {
+            max_memory_pages: 16,
+            ..WasmLimits::default()
+        };
+        let wasm_ok = wat::parse_str("(module (memory 16))").unwrap();
+        check_wasm_memories(&deserialize_wasm(&wasm_ok).unwrap(), &limits).unwrap();
+
+        let wasm_too_big = wat::parse_str("(module (memory 17))").unwrap();
+        match check_wasm_memories(&deserialize_wasm(&wasm_too_big).unwrap(), &limits) {
+            Err(VmError::StaticValidationErr { msg, .. }) => {
+                assert!(msg.starts_with("Wasm contract memory's minimum must not exceed 16 pages"));
+            }
+            Err(e) => panic!("Unexpected error {:?}", e),
+            Ok(_) => panic!("Didn't reject wasm with too big memory"),
+        }
+    }
+
+    #[test]
+    fn check_wasm_report_collects_all_violations() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "nope" (func))
+                (memory 600)
+                (global i32 (i32.const 1))
+                (global i32 (i32.const 2))
+            )"#,
+        )
+        .unwrap();
+        let limits = WasmLimits {
+            max_globals: 1,
+            ..WasmLimits::default()
+        };
+
+        let violations = check_wasm_report(&wasm, &default_capabilities(), &limits);
+        let categories: Vec<_> = violations.iter().map(|v| v.category).collect();
+        assert_eq!(
+            categories,
+            vec![
+                WasmViolationCategory::Memories,
+                WasmViolationCategory::InterfaceVersion,
+                WasmViolationCategory::Exports,
+                WasmViolationCategory::Imports,
+                WasmViolationCategory::Globals,
+            ]
+        );
+        assert_eq!(
+            violations[0].message,
+            "Wasm contract memory's minimum must not exceed 512 pages."
+        );
+        assert_eq!(
+            violations[4].message,
+            "Wasm contract contains 2 globals, exceeding the limit of 1"
+        );
+
+        // check_wasm returns the first violation
+        match check_wasm(&wasm, &default_capabilities(), &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(msg, violations[0].message),
+            e => panic!("Unexpected error {:?}", e),
+        }
+
+        // valid contracts have no violations
+        let violations =
+            check_wasm_report(CONTRACT, &default_capabilities(), &WasmLimits::default());
+        assert_eq!(violations, vec![]);
+    }
+
+    #[test]
+    fn check_wasm_report_stops_after_size_violation() {
+        let limits = WasmLimits {
+            max_wasm_size: 4,
+            ..WasmLimits::default()
+        };
+        // Not even parsed, so the broken bytecode is not reported
+        let violations = check_wasm_report(b"\0asm\x01", &default_capabilities(), &limits);
+        let categories: Vec<_> = violations.iter().map(|v| v.category).collect();
+        assert_eq!(categories, vec![WasmViolationCategory::Size]);
+        assert_eq!(
+            violations[0].message,
+            "Wasm contract has a size of 5 bytes, exceeding the limit of 4 bytes"
+        );
+        assert_eq!(
+            WasmViolationCategory::FunctionBodies.to_string(),
+            "function_bodies"
+        );
+    }
+
+    #[test]
+    fn check_wasm_report_stops_after_deserialization_failure() {
+        let violations = check_wasm_report(
+            b"\0asm\x01",
+            &default_capabilities(),
+            &WasmLimits::default(),
+        );
+        let categories: Vec<_> = violations.iter().map(|v| v.category).collect();
+        assert_eq!(categories, vec![WasmViolationCategory::Deserialization]);
+        assert!(violations[0]
+            .message
+            .starts_with("Wasm bytecode could not be deserialized"));
+    }
+
+    #[test]
+    fn check_wasm_size_works() {
+        check_wasm_size(CONTRACT, &WasmLimits::default()).unwrap();
+
+        let limits = WasmLimits {
+            max_wasm_size: 100,
+            ..WasmLimits::default()
+        };
+        match check_wasm_size(&[0u8; 101], &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract has a size of 101 bytes, exceeding the limit of 100 bytes"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_wasm_function_bodies_works() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "foo" (func))
+                (func nop)
+                (func (local i32 i32 i64) i32.const 1 drop)
+            )"#,
+        )
+        .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        check_wasm_function_bodies(&wasm, &module, &WasmLimits::default()).unwrap();
+        check_wasm_function_bodies(
+            CONTRACT,
+            &deserialize_wasm(CONTRACT).unwrap(),
+            &WasmLimits::default(),
+        )
+        .unwrap();
+
+        let limits = WasmLimits {
+            max_function_body_size: 5,
+            ..WasmLimits::default()
+        };
+        match check_wasm_function_bodies(&wasm, &module, &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract function 2 has a body of 9 bytes, exceeding the limit of 5 bytes"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+
+        let limits = WasmLimits {
+            max_function_locals: 2,
+            ..WasmLimits::default()
+        };
+        match check_wasm_function_bodies(&wasm, &module, &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract function 2 declares 3 locals, exceeding the limit of 2"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_wasm_tables_works() {
+        let wasm = wat::parse_str("(module (table 10 funcref))").unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        check_wasm_tables(&module, &WasmLimits::default()).unwrap();
+
+        let limits = WasmLimits {
+            max_table_size: 9,
+            ..WasmLimits::default()
+        };
+        match check_wasm_tables(&module, &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract table 0 has 10 elements, exceeding the limit of 9"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_wasm_globals_works() {
+        let wasm =
+            wat::parse_str("(module (global i32 (i32.const 1)) (global (mut i64) (i64.const 2)))")
+                .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        check_wasm_globals(&module, &WasmLimits::default()).unwrap();
+
+        let limits = WasmLimits {
+            max_globals: 1,
+            ..WasmLimits::default()
+        };
+        match check_wasm_globals(&module, &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract contains 2 globals, exceeding the limit of 1"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_wasm_data_segments_works() {
+        let wasm = wat::parse_str(
+            r#"(module (memory 1) (data (i32.const 0) "ab") (data (i32.const 8) "abcd"))"#,
+        )
+        .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        check_wasm_data_segments(&module, &WasmLimits::default()).unwrap();
+
+        let limits = WasmLimits {
+            max_data_segment_size: 3,
+            ..WasmLimits::default()
+        };
+        match check_wasm_data_segments(&module, &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract data segment 1 has 4 bytes, exceeding the limit of 3 bytes"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_wasm_element_segments_works() {
+        let wasm = wat::parse_str(
+            "(module (table 4 funcref) (func) (elem (i32.const 0) 0) (elem (i32.const 1) 0))",
+        )
+        .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        check_wasm_element_segments(&module, &WasmLimits::default()).unwrap();
+
+        let limits = WasmLimits {
+            max_element_segments: 1,
+            ..WasmLimits::default()
+        };
+        match check_wasm_element_segments(&module, &limits).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => assert_eq!(
+                msg,
+                "Wasm contract contains 2 element segments, exceeding the limit of 1"
+            ),
+            e => panic!("Unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_wasm_functions_uses_custom_limits() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (param i32 i32 i32) (result i32) i32.const 0)
+                (func (param i32 i32 i32) (result i32) i32.const 1)
+            )"#,
+        )
+        .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+        check_wasm_functions(&module, &WasmLimits::default()).unwrap();
+
+        let cases = [
+            (
+                WasmLimits {
+                    max_functions: 1,
+                    ..WasmLimits::default()
+                },
+                "Wasm contract contains more than 1 functions",
+            ),
+            (
+                WasmLimits {
+                    max_function_params: 2,
+                    ..WasmLimits::default()
+                },
+                "Wasm contract contains function with more than 2 parameters",
+            ),
+            (
+                WasmLimits {
+                    max_function_results: 0,
+                    ..WasmLimits::default()
+                },
+                "Wasm contract contains function with more than 0 results",
+            ),
+        ];
+        for (limits, expected) in cases {
+            match check_wasm_functions(&module, &limits) {
+                Err(VmError::StaticValidationErr { msg, .. }) => assert_eq!(msg, expected),
+                Err(e) => panic!("Unexpected error {:?}", e),
+                Ok(_) => panic!("Didn't reject wasm exceeding limits"),
+            }
+        }
+    }
+
     #[test]
     fn check_wasm_memories_maximum_size() {
         let wasm_max = wat::parse_str("(module (memory 1 5))").unwrap();
-        match check_wasm_memories(&deserialize_wasm(&wasm_max).unwrap()) {
+        match check_wasm_memories(
+            &deserialize_wasm(&wasm_max).unwrap(),
+            &WasmLimits::default(),
+        ) {
             Err(VmError::StaticValidationErr { msg, .. }) => {
                 assert!(msg.starts_with("Wasm contract memory's maximum must be unset"));
             }
@@ -0,0 +1,148 @@
+//! Optional compression of Wasm blobs stored on disk.
+//!
+//! Compressed files are recognized by the magic bytes of their format, which can never be
+//! confused with the Wasm magic `\0asm` of legacy uncompressed files.
+
+use std::io::{Read, Write};
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use crate::errors::{VmError, VmResult};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Upper bound for the size of decompressed Wasm, which protects against decompression bombs.
+/// This is far above any sensible [`crate::WasmLimits::max_wasm_size`].
+const MAX_DECOMPRESSED_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The format in which Wasm blobs are written to disk.
+///
+/// Loading always supports all formats, so this can be changed at any time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WasmCompression {
+    /// Raw Wasm bytecode
+    None,
+    /// gzip (RFC 1952) compressed Wasm bytecode
+    Gzip,
+}
+
+/// Encodes the Wasm blob for storage in the given format.
+pub fn compress_wasm(wasm: &[u8], compression: WasmCompression) -> Vec<u8> {
+    match compression {
+        WasmCompression::None => wasm.to_vec(),
+        WasmCompression::Gzip => gzip(wasm),
+    }
+}
+
+/// Decodes data written by [`compress_wasm`] with any format.
+/// Data without a known compression marker is returned unchanged.
+pub fn decompress_wasm(data: Vec<u8>) -> VmResult<Vec<u8>> {
+    if data.starts_with(&GZIP_MAGIC) {
+        gunzip(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    // Writing to a Vec cannot fail
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+fn gunzip(data: &[u8]) -> VmResult<Vec<u8>> {
+    let err = |msg: String| VmError::cache_err(format!("Error decompressing Wasm file: {}", msg));
+
+    let mut inflated = Vec::new();
+    // Reading one byte more than allowed tells us if the limit was exceeded
+    GzDecoder::new(data)
+        .take(MAX_DECOMPRESSED_SIZE + 1)
+        .read_to_end(&mut inflated)
+        .map_err(|e| err(e.to_string()))?;
+    if inflated.len() as u64 > MAX_DECOMPRESSED_SIZE {
+        return Err(err(format!(
+            "decompressed size exceeds limit of {} bytes",
+            MAX_DECOMPRESSED_SIZE
+        )));
+    }
+    Ok(inflated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static CONTRACT: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+    #[test]
+    fn compress_wasm_none_is_identity() {
+        assert_eq!(compress_wasm(CONTRACT, WasmCompression::None), CONTRACT);
+        assert_eq!(decompress_wasm(CONTRACT.to_vec()).unwrap(), CONTRACT);
+    }
+
+    #[test]
+    fn gzip_round_trip_works() {
+        let compressed = compress_wasm(CONTRACT, WasmCompression::Gzip);
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        assert!(compressed.len() < CONTRACT.len());
+        assert_eq!(decompress_wasm(compressed).unwrap(), CONTRACT);
+
+        // empty input
+        let compressed = compress_wasm(b"", WasmCompression::Gzip);
+        assert_eq!(decompress_wasm(compressed).unwrap(), b"");
+    }
+
+    #[test]
+    fn gunzip_skips_optional_header_fields() {
+        let mut encoder = flate2::GzBuilder::new()
+            .filename("contract.wasm")
+            .extra(vec![1, 2, 3])
+            .comment("hello")
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(b"\0asm").unwrap();
+        let with_name = encoder.finish().unwrap();
+        assert_eq!(decompress_wasm(with_name).unwrap(), b"\0asm");
+    }
+
+    #[test]
+    fn gunzip_detects_corruption() {
+        let mut compressed = compress_wasm(CONTRACT, WasmCompression::Gzip);
+        let last = compressed.len() - 1;
+        // change the expected length
+        compressed[last] ^= 0x01;
+        match decompress_wasm(compressed).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.starts_with("Error decompressing Wasm file"))
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        let truncated = compress_wasm(CONTRACT, WasmCompression::Gzip)[..12].to_vec();
+        match decompress_wasm(truncated).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.starts_with("Error decompressing Wasm file"))
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn gunzip_limits_decompressed_size() {
+        let bomb = gzip(&vec![0u8; MAX_DECOMPRESSED_SIZE as usize + 1]);
+        match decompress_wasm(bomb).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.ends_with("decompressed size exceeds limit of 67108864 bytes"))
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        let max = gzip(&vec![0u8; MAX_DECOMPRESSED_SIZE as usize]);
+        assert_eq!(
+            decompress_wasm(max).unwrap().len() as u64,
+            MAX_DECOMPRESSED_SIZE
+        );
+    }
+}
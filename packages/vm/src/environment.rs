@@ -1,13 +1,20 @@
 //! Internal details to be used by instance.rs only
 use std::borrow::{Borrow, BorrowMut};
 use std::ptr::NonNull;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+
+use serde::{Deserialize, Serialize};
 
 use wasmer::{HostEnvInitError, Instance as WasmerInstance, Memory, Val, WasmerEnv};
 use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 use crate::backend::{BackendApi, GasInfo, Querier, Storage};
 use crate::errors::{VmError, VmResult};
+use crate::gas_profile::GasProfileRecorder;
+use crate::trace::HostCallTracer;
+use crate::wasm_backend::{get_stack_height, get_stack_limit, set_stack_height};
+use crate::wasm_backtrace::WasmSymbolizer;
+use crate::wasm_limits::WasmLimits;
 
 /// Never can never be instantiated.
 /// Replace this with the [never primitive type](https://doc.rust-lang.org/std/primitive.never.html) when stable.
@@ -16,9 +23,13 @@ pub enum Never {}
 
 /** gas config data */
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// Gas costs of VM (not Backend) provided functionality.
+///
+/// This is serializable such that chains can keep it in their parameters.
+/// Missing fields take their default values.
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(default)]
 pub struct GasConfig {
-    /// Gas costs of VM (not Backend) provided functionality
     /// secp256k1 signature verification cost
     pub secp256k1_verify_cost: u64,
     /// secp256k1 public key recovery cost
@@ -29,12 +40,49 @@ pub struct GasConfig {
     pub ed25519_batch_verify_cost: u64,
     /// ed25519 batch signature verification cost (single public key)
     pub ed25519_batch_verify_one_pubkey_cost: u64,
+    /// Base cost of db_read
+    pub db_read_cost: u64,
+    /// Cost per byte of the key and value copied by db_read
+    pub db_read_cost_per_byte: u64,
+    /// Base cost of db_write
+    pub db_write_cost: u64,
+    /// Cost per byte of the key and value copied by db_write
+    pub db_write_cost_per_byte: u64,
+    /// Base cost of db_remove
+    pub db_remove_cost: u64,
+    /// Cost per byte of the key copied by db_remove
+    pub db_remove_cost_per_byte: u64,
+    /// Base cost of addr_validate
+    pub addr_validate_cost: u64,
+    /// Cost per byte of the address copied by addr_validate
+    pub addr_validate_cost_per_byte: u64,
+    /// Base cost of addr_canonicalize
+    pub addr_canonicalize_cost: u64,
+    /// Cost per byte of the human and canonical address copied by addr_canonicalize
+    pub addr_canonicalize_cost_per_byte: u64,
+    /// Base cost of addr_humanize
+    pub addr_humanize_cost: u64,
+    /// Cost per byte of the canonical and human address copied by addr_humanize
+    pub addr_humanize_cost_per_byte: u64,
+    /// Base cost of query_chain
+    pub query_chain_cost: u64,
+    /// Cost per byte of the request and response copied by query_chain
+    pub query_chain_cost_per_byte: u64,
+    /// Cost per byte of a debug message. If this is not zero, debug messages are charged
+    /// no matter whether they are printed or not.
+    pub debug_cost_per_byte: u64,
+    /// Cost per byte of the start and end keys of db_scan
+    pub db_scan_cost_per_byte: u64,
+    /// Cost per byte of the key and value returned by db_next
+    pub db_next_cost_per_byte: u64,
 }
 
 impl Default for GasConfig {
     fn default() -> Self {
         // Target is 10^12 per millisecond (see GAS.md), i.e. 10^9 gas per µ second.
         const GAS_PER_US: u64 = 1_000_000_000;
+        const HOST_CALL_COST: u64 = GAS_PER_US / 10;
+        const COPY_COST_PER_BYTE: u64 = GAS_PER_US / 50_000;
         Self {
             // ~154 us in crypto benchmarks
             secp256k1_verify_cost: 154 * GAS_PER_US,
@@ -46,6 +94,29 @@ impl Default for GasConfig {
             // From https://docs.rs/ed25519-zebra/2.2.0/ed25519_zebra/batch/index.html
             ed25519_batch_verify_cost: 63 * GAS_PER_US / 2,
             ed25519_batch_verify_one_pubkey_cost: 63 * GAS_PER_US / 4,
+            // Copying data between Wasm memory and the host. Those are charged on top of the
+            // gas reported by the backend, such that backends which do not charge anything
+            // cannot be abused. The base cost covers the overhead of a host call (~100 ns),
+            // the per byte cost the copying (~20 ps per byte).
+            db_read_cost: HOST_CALL_COST,
+            db_read_cost_per_byte: COPY_COST_PER_BYTE,
+            db_write_cost: HOST_CALL_COST,
+            db_write_cost_per_byte: COPY_COST_PER_BYTE,
+            db_remove_cost: HOST_CALL_COST,
+            db_remove_cost_per_byte: COPY_COST_PER_BYTE,
+            addr_validate_cost: HOST_CALL_COST,
+            addr_validate_cost_per_byte: COPY_COST_PER_BYTE,
+            addr_canonicalize_cost: HOST_CALL_COST,
+            addr_canonicalize_cost_per_byte: COPY_COST_PER_BYTE,
+            addr_humanize_cost: HOST_CALL_COST,
+            addr_humanize_cost_per_byte: COPY_COST_PER_BYTE,
+            query_chain_cost: HOST_CALL_COST,
+            query_chain_cost_per_byte: COPY_COST_PER_BYTE,
+            // db_scan and db_next have no base cost since the backend charges for iterating
+            db_scan_cost_per_byte: COPY_COST_PER_BYTE,
+            db_next_cost_per_byte: COPY_COST_PER_BYTE,
+            // Debug messages are meant for development and not charged by default
+            debug_cost_per_byte: 0,
         }
     }
 }
@@ -72,12 +143,27 @@ impl GasState {
     }
 }
 
+/// Call context passed to the debug handler together with the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugInfo<'a> {
+    /// The name of the entry point, e.g. `execute`
+    pub entry_point: &'a str,
+    pub gas_remaining: u64,
+    /// The number of debug messages the instance emitted before this one
+    pub sequence: u64,
+}
+
+/// A callback that receives the debug messages a contract emits with `deps.api.debug`.
+///
+/// See [`crate::Instance::set_debug_handler`].
+pub type DebugHandler = dyn for<'a, 'b> FnMut(/* message */ &'a str, DebugInfo<'b>) + Send;
+
 /// A environment that provides access to the ContextData.
 /// The environment is clonable but clones access the same underlying data.
 pub struct Environment<A: BackendApi, S: Storage, Q: Querier> {
     pub api: A,
-    pub print_debug: bool,
     pub gas_config: GasConfig,
+    pub wasm_limits: WasmLimits,
     data: Arc<RwLock<ContextData<S, Q>>>,
 }
 
@@ -89,8 +175,8 @@ impl<A: BackendApi, S: Storage, Q: Querier> Clone for Environment<A, S, Q> {
     fn clone(&self) -> Self {
         Environment {
             api: self.api,
-            print_debug: self.print_debug,
-            gas_config: self.gas_config.clone(),
+            gas_config: self.gas_config,
+            wasm_limits: self.wasm_limits,
             data: self.data.clone(),
         }
     }
@@ -103,11 +189,11 @@ impl<A: BackendApi, S: Storage, Q: Querier> WasmerEnv for Environment<A, S, Q> {
 }
 
 impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
-    pub fn new(api: A, gas_limit: u64, print_debug: bool) -> Self {
+    pub fn new(api: A, gas_limit: u64) -> Self {
         Environment {
             api,
-            print_debug,
             gas_config: GasConfig::default(),
+            wasm_limits: WasmLimits::default(),
             data: Arc::new(RwLock::new(ContextData::new(gas_limit))),
         }
     }
@@ -163,20 +249,53 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
     /// or [`Self::call_function1`] to ensure the number of return values is checked.
     fn call_function(&self, name: &str, args: &[Val]) -> VmResult<Box<[Val]>> {
         // Clone function before calling it to avoid dead locks
-        let func = self.with_wasmer_instance(|instance| {
+        let (func, stack_height) = self.with_wasmer_instance(|instance| {
             let func = instance.exports.get_function(name)?;
-            Ok(func.clone())
+            Ok((func.clone(), get_stack_height(instance)))
         })?;
-        func.call(args).map_err(|runtime_err| -> VmError {
+        // Calls made while another call is running (e.g. allocate) belong to the outer entry point
+        let is_entry_point = self.with_context_data_mut(|context_data| {
+            let is_entry_point = context_data.entry_point.is_none();
+            if is_entry_point {
+                context_data.entry_point = Some(name.to_string());
+            }
+            is_entry_point
+        });
+        let profile_depth = self.with_gas_profile_mut(|profile, gas_left| profile.begin(gas_left));
+        let result = func.call(args).map_err(|runtime_err| -> VmError {
+            let wasm_backtrace = self.with_context_data_mut(|context_data| {
+                context_data
+                    .wasm_symbolizer
+                    .as_mut()
+                    .map(|symbolizer| symbolizer.backtrace(&runtime_err))
+            });
             self.with_wasmer_instance::<_, Never>(|instance| {
+                let stack_limit = get_stack_limit(instance);
                 let err: VmError = match get_remaining_points(instance) {
-                    MeteringPoints::Remaining(_) => VmError::from(runtime_err),
+                    MeteringPoints::Remaining(_) if get_stack_height(instance) > stack_limit => {
+                        VmError::stack_limit_exceeded(stack_limit)
+                    }
+                    MeteringPoints::Remaining(_) => match wasm_backtrace {
+                        Some(wasm_backtrace) => {
+                            VmError::from(runtime_err).with_wasm_backtrace(wasm_backtrace)
+                        }
+                        None => VmError::from(runtime_err),
+                    },
                     MeteringPoints::Exhausted => VmError::gas_depletion(),
                 };
+                // A trap leaves the frames of the aborted calls behind
+                set_stack_height(instance, stack_height);
                 Err(err)
             })
             .unwrap_err() // with_wasmer_instance can only succeed if the callback succeeds
-        })
+        });
+        if let Some(depth) = profile_depth {
+            self.with_gas_profile_mut(|profile, gas_left| profile.end(depth, gas_left));
+        }
+        if is_entry_point {
+            self.with_context_data_mut(|context_data| context_data.entry_point = None);
+        }
+        result
     }
 
     pub fn call_function0(&self, name: &str, args: &[Val]) -> VmResult<()> {
@@ -237,6 +356,93 @@ impl<A: BackendApi, S: Storage, Q: Querier> Environment<A, S, Q> {
         })
     }
 
+    pub fn set_gas_profile(&self, recorder: Option<GasProfileRecorder>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.gas_profile = recorder;
+        })
+    }
+
+    /// Calls the callback with the gas profile recorder and the gas left if gas profiling is
+    /// enabled. Returns `None` otherwise.
+    pub fn with_gas_profile_mut<C, R>(&self, callback: C) -> Option<R>
+    where
+        C: FnOnce(&mut GasProfileRecorder, u64) -> R,
+    {
+        if self.with_context_data(|context_data| context_data.gas_profile.is_none()) {
+            return None;
+        }
+        let gas_left = self.get_gas_left();
+        self.with_context_data_mut(|context_data| {
+            context_data
+                .gas_profile
+                .as_mut()
+                .map(|recorder| callback(recorder, gas_left))
+        })
+    }
+
+    pub fn set_wasm_symbolizer(&self, symbolizer: Option<WasmSymbolizer>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.wasm_symbolizer = symbolizer;
+        })
+    }
+
+    pub fn set_debug_handler(&self, debug_handler: Option<Arc<Mutex<DebugHandler>>>) {
+        self.with_context_data_mut(|context_data| {
+            context_data.debug_handler = debug_handler;
+        })
+    }
+
+    /// Returns true iff a debug handler is set
+    pub fn has_debug_handler(&self) -> bool {
+        self.with_context_data(|context_data| context_data.debug_handler.is_some())
+    }
+
+    /// Passes a debug message of the contract to the debug handler, if set
+    pub fn call_debug_handler(&self, message: &str) {
+        let handler = self.with_context_data_mut(|context_data| {
+            let handler = context_data.debug_handler.clone()?;
+            let sequence = context_data.debug_sequence;
+            context_data.debug_sequence += 1;
+            Some((handler, sequence, context_data.entry_point.clone()))
+        });
+        // The handler is called without holding the lock of the context data
+        if let Some((handler, sequence, entry_point)) = handler {
+            let info = DebugInfo {
+                entry_point: entry_point.as_deref().unwrap_or_default(),
+                gas_remaining: self.get_gas_left(),
+                sequence,
+            };
+            (*handler.lock().unwrap())(message, info);
+        }
+    }
+
+    /// Starts or stops recording host calls. Stopping drops the calls recorded so far.
+    pub fn set_host_call_tracing(&self, enabled: bool) {
+        self.with_context_data_mut(|context_data| {
+            match (enabled, context_data.host_call_tracer.is_some()) {
+                (true, false) => context_data.host_call_tracer = Some(HostCallTracer::default()),
+                (false, true) => context_data.host_call_tracer = None,
+                _ => {}
+            }
+        })
+    }
+
+    /// Returns true iff host calls are traced
+    pub fn is_tracing_host_calls(&self) -> bool {
+        self.with_context_data(|context_data| context_data.host_call_tracer.is_some())
+    }
+
+    /// Calls the callback with the host call tracer if host calls are traced.
+    /// Returns `None` otherwise.
+    pub fn with_host_call_tracer_mut<C, R>(&self, callback: C) -> Option<R>
+    where
+        C: FnOnce(&mut HostCallTracer) -> R,
+    {
+        self.with_context_data_mut(|context_data| {
+            context_data.host_call_tracer.as_mut().map(callback)
+        })
+    }
+
     pub fn get_gas_left(&self) -> u64 {
         self.with_wasmer_instance(|instance| {
             Ok(match get_remaining_points(instance) {
@@ -319,6 +525,17 @@ pub struct ContextData<S: Storage, Q: Querier> {
     querier: Option<Q>,
     /// A non-owning link to the wasmer instance
     wasmer_instance: Option<NonNull<WasmerInstance>>,
+    /// Only set for instances of modules compiled for gas profiling
+    gas_profile: Option<GasProfileRecorder>,
+    /// Only set while host calls are traced
+    host_call_tracer: Option<HostCallTracer>,
+    debug_handler: Option<Arc<Mutex<DebugHandler>>>,
+    /// The number of debug messages passed to the debug handler so far
+    debug_sequence: u64,
+    /// The name of the exported function the host called, while it is running
+    entry_point: Option<String>,
+    /// Only set for instances that attach Wasm backtraces to runtime errors
+    wasm_symbolizer: Option<WasmSymbolizer>,
 }
 
 impl<S: Storage, Q: Querier> ContextData<S, Q> {
@@ -329,6 +546,12 @@ impl<S: Storage, Q: Querier> ContextData<S, Q> {
             storage_readonly: true,
             querier: None,
             wasmer_instance: None,
+            gas_profile: None,
+            host_call_tracer: None,
+            debug_handler: None,
+            debug_sequence: 0,
+            entry_point: None,
+            wasm_symbolizer: None,
         }
     }
 }
@@ -338,6 +561,7 @@ pub fn process_gas_info<A: BackendApi, S: Storage, Q: Querier>(
     info: GasInfo,
 ) -> VmResult<()> {
     let gas_left = env.get_gas_left();
+    env.with_host_call_tracer_mut(|tracer| tracer.add_gas_info(info));
 
     let new_limit = env.with_gas_state_mut(|gas_state| {
         gas_state.externally_used_gas += info.externally_used;
@@ -392,7 +616,7 @@ mod tests {
         Environment<MockApi, MockStorage, MockQuerier>,
         Box<WasmerInstance>,
     ) {
-        let env = Environment::new(MockApi::default(), gas_limit, false);
+        let env = Environment::new(MockApi::default(), gas_limit);
 
         let module = compile(CONTRACT, TESTING_MEMORY_LIMIT, &[]).unwrap();
         let store = module.store();
@@ -437,6 +661,29 @@ mod tests {
         env.move_in(storage, querier);
     }
 
+    #[test]
+    fn gas_config_serialization_works() {
+        let config = GasConfig {
+            ed25519_verify_cost: 42,
+            debug_cost_per_byte: 7,
+            ..GasConfig::default()
+        };
+        let serialized = serde_json::to_vec(&config).unwrap();
+        let deserialized: GasConfig = serde_json::from_slice(&serialized).unwrap();
+        assert_eq!(deserialized, config);
+
+        // missing fields take their default values
+        let deserialized: GasConfig =
+            serde_json::from_slice(br#"{"secp256k1_verify_cost":1}"#).unwrap();
+        assert_eq!(
+            deserialized,
+            GasConfig {
+                secp256k1_verify_cost: 1,
+                ..GasConfig::default()
+            }
+        );
+    }
+
     #[test]
     fn move_out_works() {
         let (env, _instance) = make_instance(TESTING_GAS_LIMIT);
@@ -7,6 +7,7 @@ use cosmwasm_crypto::CryptoError;
 
 use super::communication_error::CommunicationError;
 use crate::backend::BackendError;
+use crate::wasm_backtrace::WasmBacktrace;
 
 #[derive(Error, Debug)]
 #[non_exhaustive]
@@ -122,9 +123,17 @@ pub enum VmError {
         #[cfg(feature = "backtraces")]
         backtrace: Backtrace,
     },
-    #[error("Error executing Wasm: {}", msg)]
+    #[error("Error executing Wasm: {}{}", msg, display_wasm_backtrace(.wasm_backtrace))]
     RuntimeErr {
         msg: String,
+        /// Only set for instances with [`crate::InstanceOptions::wasm_backtraces`] enabled
+        wasm_backtrace: Option<WasmBacktrace>,
+        #[cfg(feature = "backtraces")]
+        backtrace: Backtrace,
+    },
+    #[error("Exceeded the stack height limit of {limit} during contract execution")]
+    StackLimitExceeded {
+        limit: u32,
         #[cfg(feature = "backtraces")]
         backtrace: Backtrace,
     },
@@ -287,6 +296,23 @@ impl VmError {
     fn runtime_err(msg: impl Into<String>) -> Self {
         VmError::RuntimeErr {
             msg: msg.into(),
+            wasm_backtrace: None,
+            #[cfg(feature = "backtraces")]
+            backtrace: Backtrace::capture(),
+        }
+    }
+
+    /// Attaches a Wasm backtrace to a runtime error. Other errors are returned unchanged.
+    pub(crate) fn with_wasm_backtrace(mut self, backtrace: WasmBacktrace) -> Self {
+        if let VmError::RuntimeErr { wasm_backtrace, .. } = &mut self {
+            *wasm_backtrace = Some(backtrace);
+        }
+        self
+    }
+
+    pub(crate) fn stack_limit_exceeded(limit: u32) -> Self {
+        VmError::StackLimitExceeded {
+            limit,
             #[cfg(feature = "backtraces")]
             backtrace: Backtrace::capture(),
         }
@@ -349,6 +375,13 @@ impl From<wasmer::DeserializeError> for VmError {
     }
 }
 
+fn display_wasm_backtrace(wasm_backtrace: &Option<WasmBacktrace>) -> String {
+    match wasm_backtrace {
+        Some(wasm_backtrace) => format!("\n{}", wasm_backtrace),
+        None => String::new(),
+    }
+}
+
 impl From<wasmer::RuntimeError> for VmError {
     fn from(original: wasmer::RuntimeError) -> Self {
         // Do not use the Display implementation or to_string() of `RuntimeError`
@@ -389,6 +422,7 @@ impl From<VmError> for wasmer::RuntimeError {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::wasm_backtrace::{SourceLocation, WasmFrame};
 
     // constructors
 
@@ -555,6 +589,47 @@ mod tests {
         }
     }
 
+    #[test]
+    fn with_wasm_backtrace_works() {
+        let backtrace = WasmBacktrace {
+            frames: vec![WasmFrame {
+                function_index: 3,
+                function_name: "contract::execute".to_string(),
+                module_offset: 120,
+                location: Some(SourceLocation {
+                    file: "src/contract.rs".to_string(),
+                    line: Some(12),
+                    column: Some(5),
+                }),
+            }],
+        };
+        let error =
+            VmError::runtime_err("something went wrong").with_wasm_backtrace(backtrace.clone());
+        assert_eq!(
+            error.to_string(),
+            "Error executing Wasm: something went wrong\nWasm backtrace:\n  0: contract::execute\n        at src/contract.rs:12:5\n"
+        );
+        match error {
+            VmError::RuntimeErr { wasm_backtrace, .. } => {
+                assert_eq!(wasm_backtrace, Some(backtrace.clone()))
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        // Other errors are unchanged
+        let error = VmError::gas_depletion().with_wasm_backtrace(backtrace);
+        assert!(matches!(error, VmError::GasDepletion { .. }));
+    }
+
+    #[test]
+    fn stack_limit_exceeded_works() {
+        let error = VmError::stack_limit_exceeded(42);
+        match error {
+            VmError::StackLimitExceeded { limit, .. } => assert_eq!(limit, 42),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     #[test]
     fn static_validation_err_works() {
         let error = VmError::static_validation_err("export xy missing");
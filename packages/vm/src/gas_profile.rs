@@ -0,0 +1,299 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use wasmer::Module;
+use wasmer_types::ImportIndex;
+
+use crate::wasm_backend::{PROFILER_IMPORTS, PROFILER_IMPORT_MODULE, PROFILER_INDIRECT_CALL};
+
+/// The gas used by a contract, broken down by Wasm functions and imports.
+///
+/// This is created for instances with [`crate::InstanceOptions::gas_profiling`] enabled. Function
+/// names are taken from the name section of the Wasm. Functions without a name are called
+/// `func[<index>]`, where the index is the one of the original Wasm.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GasProfile {
+    /// The gas used by the code of each function, not including the functions it called
+    pub functions: BTreeMap<String, u64>,
+    /// The gas charged by the VM while an import was running, including externally used gas
+    pub imports: BTreeMap<String, u64>,
+    /// The gas used by the innermost function of each call stack. The entry point comes first.
+    pub stacks: BTreeMap<Vec<String>, u64>,
+}
+
+impl GasProfile {
+    /// Returns the stacks in the folded stack format (one `entry;caller;callee gas` line per stack)
+    /// that is used by flamegraph tools such as [inferno](https://github.com/jonhoo/inferno).
+    pub fn to_folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, gas) in &self.stacks {
+            writeln!(out, "{} {}", stack.join(";"), gas).unwrap();
+        }
+        out
+    }
+}
+
+/// Records the gas used per call stack of an instance that was compiled for gas profiling.
+///
+/// The instrumented code reports calls via [`GasProfileRecorder::enter`] and
+/// [`GasProfileRecorder::leave`]. The gas used between two reports is attributed to the call stack
+/// at that time.
+#[derive(Debug)]
+pub struct GasProfileRecorder {
+    /// Names by function index of the instrumented module
+    names: Vec<String>,
+    /// Imported functions come first
+    imported_functions: u32,
+    stack: Vec<u32>,
+    last_gas_left: u64,
+    stacks: HashMap<Vec<u32>, u64>,
+}
+
+impl GasProfileRecorder {
+    /// Creates a recorder for the given module or returns `None` if it was not compiled
+    /// for gas profiling.
+    pub fn for_module(module: &Module) -> Option<Self> {
+        let info = module.info();
+        let profiled = module
+            .imports()
+            .any(|import| import.module() == PROFILER_IMPORT_MODULE);
+        if !profiled {
+            return None;
+        }
+
+        let mut names: Vec<String> = info
+            .functions
+            .keys()
+            .map(|index| match info.function_names.get(&index) {
+                Some(name) => name.clone(),
+                // Imports are named by their field below
+                None if info.is_imported_function(index) => String::new(),
+                None => format!("func[{}]", index.as_u32() - PROFILER_IMPORTS),
+            })
+            .collect();
+        for ((_, field, _), index) in &info.imports {
+            if let ImportIndex::Function(index) = index {
+                names[index.as_u32() as usize] = field.clone();
+            }
+        }
+
+        Some(Self {
+            names,
+            imported_functions: info.num_imported_functions as u32,
+            stack: Vec::new(),
+            last_gas_left: 0,
+            stacks: HashMap::new(),
+        })
+    }
+
+    /// Called before the host calls an exported function. Returns the stack depth that needs to be
+    /// passed to [`GasProfileRecorder::end`] afterwards.
+    pub fn begin(&mut self, gas_left: u64) -> usize {
+        self.record(gas_left);
+        self.stack.len()
+    }
+
+    /// Called after an exported function returned to the host or trapped
+    pub fn end(&mut self, depth: usize, gas_left: u64) {
+        self.record(gas_left);
+        self.stack.truncate(depth);
+    }
+
+    /// Called when a function is entered or right before an indirect call
+    /// (see [`PROFILER_INDIRECT_CALL`])
+    pub fn enter(&mut self, function_index: u32, gas_left: u64) {
+        self.record(gas_left);
+        match self.stack.last_mut() {
+            // The callee of an indirect call takes the frame of the call
+            Some(top)
+                if *top == PROFILER_INDIRECT_CALL && function_index != PROFILER_INDIRECT_CALL =>
+            {
+                *top = function_index
+            }
+            _ => self.stack.push(function_index),
+        }
+    }
+
+    /// Called when a call returned. Every call added exactly one frame, which is removed.
+    pub fn leave(&mut self, gas_left: u64) {
+        self.record(gas_left);
+        self.stack.pop();
+    }
+
+    fn record(&mut self, gas_left: u64) {
+        let used = self.last_gas_left.saturating_sub(gas_left);
+        // Indirect calls of imports are not reported, so their gas is attributed to the caller
+        let stack = match self.stack.split_last() {
+            Some((&PROFILER_INDIRECT_CALL, callers)) => callers,
+            _ => &self.stack,
+        };
+        if used != 0 && !stack.is_empty() {
+            *self.stacks.entry(stack.to_vec()).or_default() += used;
+        }
+        self.last_gas_left = gas_left;
+    }
+
+    pub fn profile(&self) -> GasProfile {
+        let mut profile = GasProfile::default();
+        for (stack, &gas) in &self.stacks {
+            let innermost = *stack.last().unwrap();
+            let name = &self.names[innermost as usize];
+            let totals = if innermost < self.imported_functions {
+                &mut profile.imports
+            } else {
+                &mut profile.functions
+            };
+            *totals.entry(name.clone()).or_default() += gas;
+            let names = stack
+                .iter()
+                .map(|&index| self.names[index as usize].clone())
+                .collect();
+            *profile.stacks.entry(names).or_default() += gas;
+        }
+        profile
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wasm_backend::compile_with_gas_profiling;
+    use crate::GasCostTable;
+
+    #[test]
+    fn to_folded_works() {
+        let mut profile = GasProfile::default();
+        assert_eq!(profile.to_folded(), "");
+
+        profile
+            .stacks
+            .insert(vec!["execute".to_string(), "db_read".to_string()], 12);
+        profile.stacks.insert(vec!["execute".to_string()], 3);
+        assert_eq!(profile.to_folded(), "execute 3\nexecute;db_read 12\n");
+    }
+
+    #[test]
+    fn recorder_attributes_gas_to_stacks() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "db_read" (func $db_read))
+            (func $execute (export "execute") call $helper)
+            (func $helper call $db_read)
+            (func (export "unnamed"))
+            )"#,
+        )
+        .unwrap();
+        let module = compile_with_gas_profiling(&wasm, None, &GasCostTable::v1()).unwrap();
+        let mut recorder = GasProfileRecorder::for_module(&module).unwrap();
+        // profiler imports, db_read, execute, helper, unnamed
+        assert_eq!(recorder.imported_functions, 3);
+
+        let depth = recorder.begin(1000);
+        recorder.enter(3, 1000);
+        recorder.enter(4, 990);
+        recorder.enter(2, 970);
+        recorder.leave(900);
+        recorder.leave(890);
+        recorder.end(depth, 880);
+        recorder.begin(880);
+        recorder.enter(5, 880);
+        recorder.end(depth, 879);
+
+        let profile = recorder.profile();
+        assert_eq!(
+            profile.functions,
+            BTreeMap::from([
+                ("execute".to_string(), 20),
+                ("func[3]".to_string(), 1),
+                ("helper".to_string(), 30),
+            ])
+        );
+        assert_eq!(
+            profile.imports,
+            BTreeMap::from([("db_read".to_string(), 70)])
+        );
+        assert_eq!(
+            profile.to_folded(),
+            "execute 20\nexecute;helper 30\nexecute;helper;db_read 70\nfunc[3] 1\n"
+        );
+    }
+
+    #[test]
+    fn recorder_handles_recursion() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (func $recurse (export "recurse") (param i32)
+                local.get 0
+                if
+                    local.get 0
+                    i32.const 1
+                    i32.sub
+                    call $recurse
+                end
+            )
+            )"#,
+        )
+        .unwrap();
+        let module = compile_with_gas_profiling(&wasm, None, &GasCostTable::v1()).unwrap();
+        let mut recorder = GasProfileRecorder::for_module(&module).unwrap();
+
+        // recurse(2)
+        let depth = recorder.begin(1000);
+        recorder.enter(2, 1000);
+        recorder.enter(2, 990);
+        recorder.enter(2, 980);
+        recorder.leave(970);
+        recorder.leave(960);
+        recorder.end(depth, 950);
+
+        let name = |depth| vec!["recurse".to_string(); depth];
+        let profile = recorder.profile();
+        assert_eq!(
+            profile.functions,
+            BTreeMap::from([("recurse".to_string(), 50)])
+        );
+        assert_eq!(
+            profile.stacks,
+            BTreeMap::from([(name(1), 20), (name(2), 20), (name(3), 10)])
+        );
+    }
+
+    #[test]
+    fn recorder_handles_indirect_calls() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "db_read" (func $db_read))
+            (func $execute (export "execute") call $helper)
+            (func $helper)
+            )"#,
+        )
+        .unwrap();
+        let module = compile_with_gas_profiling(&wasm, None, &GasCostTable::v1()).unwrap();
+        let mut recorder = GasProfileRecorder::for_module(&module).unwrap();
+
+        let depth = recorder.begin(1000);
+        recorder.enter(3, 1000);
+        // Indirect call of a function
+        recorder.enter(PROFILER_INDIRECT_CALL, 990);
+        recorder.enter(4, 990);
+        recorder.leave(980);
+        // Indirect call of an import
+        recorder.enter(PROFILER_INDIRECT_CALL, 970);
+        recorder.leave(900);
+        recorder.end(depth, 890);
+
+        let profile = recorder.profile();
+        assert_eq!(
+            profile.functions,
+            BTreeMap::from([("execute".to_string(), 100), ("helper".to_string(), 10),])
+        );
+        assert_eq!(profile.imports, BTreeMap::new());
+    }
+
+    #[test]
+    fn for_module_returns_none_for_modules_without_profiling() {
+        let wasm = wat::parse_str(r#"(module (func (export "execute")))"#).unwrap();
+        let module = crate::wasm_backend::compile(&wasm, None, &[]).unwrap();
+        assert!(GasProfileRecorder::for_module(&module).is_none());
+    }
+}
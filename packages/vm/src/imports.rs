@@ -23,23 +23,17 @@ use crate::sections::decode_sections;
 #[allow(unused_imports)]
 use crate::sections::encode_sections;
 use crate::serde::to_vec;
+use crate::trace::TraceReturnValue;
 use crate::GasInfo;
 
-/// A kibi (kilo binary)
-const KI: usize = 1024;
 /// A mibi (mega binary)
 const MI: usize = 1024 * 1024;
-/// Max key length for db_write/db_read/db_remove/db_scan (when VM reads the key argument from Wasm memory)
-const MAX_LENGTH_DB_KEY: usize = 64 * KI;
-/// Max value length for db_write (when VM reads the value argument from Wasm memory)
-const MAX_LENGTH_DB_VALUE: usize = 128 * KI;
 /// Typically 20 (Cosmos SDK, Ethereum), 32 (Nano, Substrate) or 54 (MockApi)
 const MAX_LENGTH_CANONICAL_ADDRESS: usize = 64;
 /// The max length of human address inputs (in bytes).
 /// The maximum allowed size for [bech32](https://github.com/bitcoin/bips/blob/master/bip-0173.mediawiki#bech32)
 /// is 90 characters and we're adding some safety margin around that for other formats.
 const MAX_LENGTH_HUMAN_ADDRESS: usize = 256;
-const MAX_LENGTH_QUERY_CHAIN_REQUEST: usize = 64 * KI;
 /// Length of a serialized Ed25519  signature
 const MAX_LENGTH_ED25519_SIGNATURE: usize = 64;
 /// Max length of a Ed25519 message in bytes.
@@ -69,7 +63,14 @@ pub fn do_db_read<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
     key_ptr: u32,
 ) -> VmResult<u32> {
-    let key = read_region(&env.memory(), key_ptr, MAX_LENGTH_DB_KEY)?;
+    let key = read_input(env, key_ptr, env.wasm_limits.max_length_db_key)?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.db_read_cost,
+        config.db_read_cost_per_byte,
+        key.len(),
+    )?;
 
     let (result, gas_info) = env.with_storage_from_context::<_, _>(|store| Ok(store.get(&key)))?;
     process_gas_info::<A, S, Q>(env, gas_info)?;
@@ -79,6 +80,7 @@ pub fn do_db_read<A: BackendApi, S: Storage, Q: Querier>(
         Some(data) => data,
         None => return Ok(0),
     };
+    charge_host_call(env, 0, config.db_read_cost_per_byte, out_data.len())?;
     write_to_contract::<A, S, Q>(env, &out_data)
 }
 
@@ -92,8 +94,15 @@ pub fn do_db_write<A: BackendApi, S: Storage, Q: Querier>(
         return Err(VmError::write_access_denied());
     }
 
-    let key = read_region(&env.memory(), key_ptr, MAX_LENGTH_DB_KEY)?;
-    let value = read_region(&env.memory(), value_ptr, MAX_LENGTH_DB_VALUE)?;
+    let key = read_input(env, key_ptr, env.wasm_limits.max_length_db_key)?;
+    let value = read_input(env, value_ptr, env.wasm_limits.max_length_db_value)?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.db_write_cost,
+        config.db_write_cost_per_byte,
+        key.len() + value.len(),
+    )?;
 
     let (result, gas_info) =
         env.with_storage_from_context::<_, _>(|store| Ok(store.set(&key, &value)))?;
@@ -111,7 +120,14 @@ pub fn do_db_remove<A: BackendApi, S: Storage, Q: Querier>(
         return Err(VmError::write_access_denied());
     }
 
-    let key = read_region(&env.memory(), key_ptr, MAX_LENGTH_DB_KEY)?;
+    let key = read_input(env, key_ptr, env.wasm_limits.max_length_db_key)?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.db_remove_cost,
+        config.db_remove_cost_per_byte,
+        key.len(),
+    )?;
 
     let (result, gas_info) =
         env.with_storage_from_context::<_, _>(|store| Ok(store.remove(&key)))?;
@@ -125,7 +141,14 @@ pub fn do_addr_validate<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
     source_ptr: u32,
 ) -> VmResult<u32> {
-    let source_data = read_region(&env.memory(), source_ptr, MAX_LENGTH_HUMAN_ADDRESS)?;
+    let source_data = read_input(env, source_ptr, MAX_LENGTH_HUMAN_ADDRESS)?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.addr_validate_cost,
+        config.addr_validate_cost_per_byte,
+        source_data.len(),
+    )?;
     if source_data.is_empty() {
         return write_to_contract::<A, S, Q>(env, b"Input is empty");
     }
@@ -167,7 +190,14 @@ pub fn do_addr_canonicalize<A: BackendApi, S: Storage, Q: Querier>(
     source_ptr: u32,
     destination_ptr: u32,
 ) -> VmResult<u32> {
-    let source_data = read_region(&env.memory(), source_ptr, MAX_LENGTH_HUMAN_ADDRESS)?;
+    let source_data = read_input(env, source_ptr, MAX_LENGTH_HUMAN_ADDRESS)?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.addr_canonicalize_cost,
+        config.addr_canonicalize_cost_per_byte,
+        source_data.len(),
+    )?;
     if source_data.is_empty() {
         return write_to_contract::<A, S, Q>(env, b"Input is empty");
     }
@@ -181,7 +211,13 @@ pub fn do_addr_canonicalize<A: BackendApi, S: Storage, Q: Querier>(
     process_gas_info::<A, S, Q>(env, gas_info)?;
     match result {
         Ok(canonical) => {
-            write_region(&env.memory(), destination_ptr, canonical.as_slice())?;
+            charge_host_call(
+                env,
+                0,
+                config.addr_canonicalize_cost_per_byte,
+                canonical.len(),
+            )?;
+            write_output(env, destination_ptr, canonical.as_slice())?;
             Ok(0)
         }
         Err(BackendError::UserErr { msg, .. }) => {
@@ -196,13 +232,21 @@ pub fn do_addr_humanize<A: BackendApi, S: Storage, Q: Querier>(
     source_ptr: u32,
     destination_ptr: u32,
 ) -> VmResult<u32> {
-    let canonical = read_region(&env.memory(), source_ptr, MAX_LENGTH_CANONICAL_ADDRESS)?;
+    let canonical = read_input(env, source_ptr, MAX_LENGTH_CANONICAL_ADDRESS)?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.addr_humanize_cost,
+        config.addr_humanize_cost_per_byte,
+        canonical.len(),
+    )?;
 
     let (result, gas_info) = env.api.human_address(&canonical);
     process_gas_info::<A, S, Q>(env, gas_info)?;
     match result {
         Ok(human) => {
-            write_region(&env.memory(), destination_ptr, human.as_bytes())?;
+            charge_host_call(env, 0, config.addr_humanize_cost_per_byte, human.len())?;
+            write_output(env, destination_ptr, human.as_bytes())?;
             Ok(0)
         }
         Err(BackendError::UserErr { msg, .. }) => {
@@ -218,9 +262,9 @@ pub fn do_secp256k1_verify<A: BackendApi, S: Storage, Q: Querier>(
     signature_ptr: u32,
     pubkey_ptr: u32,
 ) -> VmResult<u32> {
-    let hash = read_region(&env.memory(), hash_ptr, MESSAGE_HASH_MAX_LEN)?;
-    let signature = read_region(&env.memory(), signature_ptr, ECDSA_SIGNATURE_LEN)?;
-    let pubkey = read_region(&env.memory(), pubkey_ptr, ECDSA_PUBKEY_MAX_LEN)?;
+    let hash = read_input(env, hash_ptr, MESSAGE_HASH_MAX_LEN)?;
+    let signature = read_input(env, signature_ptr, ECDSA_SIGNATURE_LEN)?;
+    let pubkey = read_input(env, pubkey_ptr, ECDSA_PUBKEY_MAX_LEN)?;
 
     let gas_info = GasInfo::with_cost(env.gas_config.secp256k1_verify_cost);
     process_gas_info::<A, S, Q>(env, gas_info)?;
@@ -246,8 +290,8 @@ pub fn do_secp256k1_recover_pubkey<A: BackendApi, S: Storage, Q: Querier>(
     signature_ptr: u32,
     recover_param: u32,
 ) -> VmResult<u64> {
-    let hash = read_region(&env.memory(), hash_ptr, MESSAGE_HASH_MAX_LEN)?;
-    let signature = read_region(&env.memory(), signature_ptr, ECDSA_SIGNATURE_LEN)?;
+    let hash = read_input(env, hash_ptr, MESSAGE_HASH_MAX_LEN)?;
+    let signature = read_input(env, signature_ptr, ECDSA_SIGNATURE_LEN)?;
     let recover_param: u8 = match recover_param.try_into() {
         Ok(rp) => rp,
         Err(_) => return Ok((CryptoError::invalid_recovery_param().code() as u64) << 32),
@@ -280,9 +324,9 @@ pub fn do_ed25519_verify<A: BackendApi, S: Storage, Q: Querier>(
     signature_ptr: u32,
     pubkey_ptr: u32,
 ) -> VmResult<u32> {
-    let message = read_region(&env.memory(), message_ptr, MAX_LENGTH_ED25519_MESSAGE)?;
-    let signature = read_region(&env.memory(), signature_ptr, MAX_LENGTH_ED25519_SIGNATURE)?;
-    let pubkey = read_region(&env.memory(), pubkey_ptr, EDDSA_PUBKEY_LEN)?;
+    let message = read_input(env, message_ptr, MAX_LENGTH_ED25519_MESSAGE)?;
+    let signature = read_input(env, signature_ptr, MAX_LENGTH_ED25519_SIGNATURE)?;
+    let pubkey = read_input(env, pubkey_ptr, EDDSA_PUBKEY_LEN)?;
 
     let gas_info = GasInfo::with_cost(env.gas_config.ed25519_verify_cost);
     process_gas_info::<A, S, Q>(env, gas_info)?;
@@ -308,18 +352,18 @@ pub fn do_ed25519_batch_verify<A: BackendApi, S: Storage, Q: Querier>(
     signatures_ptr: u32,
     public_keys_ptr: u32,
 ) -> VmResult<u32> {
-    let messages = read_region(
-        &env.memory(),
+    let messages = read_input(
+        env,
         messages_ptr,
         (MAX_LENGTH_ED25519_MESSAGE + 4) * MAX_COUNT_ED25519_BATCH,
     )?;
-    let signatures = read_region(
-        &env.memory(),
+    let signatures = read_input(
+        env,
         signatures_ptr,
         (MAX_LENGTH_ED25519_SIGNATURE + 4) * MAX_COUNT_ED25519_BATCH,
     )?;
-    let public_keys = read_region(
-        &env.memory(),
+    let public_keys = read_input(
+        env,
         public_keys_ptr,
         (EDDSA_PUBKEY_LEN + 4) * MAX_COUNT_ED25519_BATCH,
     )?;
@@ -359,16 +403,23 @@ pub fn do_sha1_calculate<A: BackendApi, S: Storage, Q: Querier>(
     Ok(to_high_half(10))
 }
 
-/// Prints a debug message to console.
-/// This does not charge gas, so debug printing should be disabled when used in a blockchain module.
+/// Passes a debug message to the debug handler of the instance.
+/// This only charges gas if [`GasConfig::debug_cost_per_byte`](crate::GasConfig::debug_cost_per_byte)
+/// is set, so the debug handler should be unset when used in a blockchain module otherwise.
 pub fn do_debug<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
     message_ptr: u32,
 ) -> VmResult<()> {
-    if env.print_debug {
-        let message_data = read_region(&env.memory(), message_ptr, MAX_LENGTH_DEBUG)?;
-        let msg = String::from_utf8_lossy(&message_data);
-        println!("{}", msg);
+    let cost_per_byte = env.gas_config.debug_cost_per_byte;
+    let has_debug_handler = env.has_debug_handler();
+    if has_debug_handler || cost_per_byte != 0 {
+        let message_data = read_input(env, message_ptr, MAX_LENGTH_DEBUG)?;
+        let gas_info = GasInfo::with_cost(bytes_cost(cost_per_byte, message_data.len()));
+        process_gas_info::<A, S, Q>(env, gas_info)?;
+        if has_debug_handler {
+            let msg = String::from_utf8_lossy(&message_data);
+            env.call_debug_handler(&msg);
+        }
     }
     Ok(())
 }
@@ -378,11 +429,31 @@ pub fn do_abort<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
     message_ptr: u32,
 ) -> VmResult<()> {
-    let message_data = read_region(&env.memory(), message_ptr, MAX_LENGTH_ABORT)?;
+    let message_data = read_input(env, message_ptr, MAX_LENGTH_ABORT)?;
     let msg = String::from_utf8_lossy(&message_data);
     Err(VmError::aborted(msg))
 }
 
+/// Reports that a function was entered. This is only imported by modules compiled
+/// for gas profiling.
+pub fn do_profiler_enter<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+    function_index: u32,
+) -> VmResult<()> {
+    env.with_gas_profile_mut(|profile, gas_left| profile.enter(function_index, gas_left));
+    Ok(())
+}
+
+/// Reports that a call made by the given function returned. This is only imported by
+/// modules compiled for gas profiling.
+pub fn do_profiler_leave<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+    _caller_index: u32,
+) -> VmResult<()> {
+    env.with_gas_profile_mut(|profile, gas_left| profile.leave(gas_left));
+    Ok(())
+}
+
 /// Creates a Region in the contract, writes the given data to it and returns the memory location
 fn write_to_contract<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
@@ -394,7 +465,7 @@ fn write_to_contract<A: BackendApi, S: Storage, Q: Querier>(
     if target_ptr == 0 {
         return Err(CommunicationError::zero_address().into());
     }
-    write_region(&env.memory(), target_ptr, input)?;
+    write_output(env, target_ptr, input)?;
     Ok(target_ptr)
 }
 
@@ -402,7 +473,18 @@ pub fn do_query_chain<A: BackendApi, S: Storage, Q: Querier>(
     env: &Environment<A, S, Q>,
     request_ptr: u32,
 ) -> VmResult<u32> {
-    let request = read_region(&env.memory(), request_ptr, MAX_LENGTH_QUERY_CHAIN_REQUEST)?;
+    let request = read_input(
+        env,
+        request_ptr,
+        env.wasm_limits.max_length_query_chain_request,
+    )?;
+    let config = &env.gas_config;
+    charge_host_call(
+        env,
+        config.query_chain_cost,
+        config.query_chain_cost_per_byte,
+        request.len(),
+    )?;
 
     let gas_remaining = env.get_gas_left();
     let (result, gas_info) = env.with_querier_from_context::<_, _>(|querier| {
@@ -410,6 +492,7 @@ pub fn do_query_chain<A: BackendApi, S: Storage, Q: Querier>(
     })?;
     process_gas_info::<A, S, Q>(env, gas_info)?;
     let serialized = to_vec(&result?)?;
+    charge_host_call(env, 0, config.query_chain_cost_per_byte, serialized.len())?;
     write_to_contract::<A, S, Q>(env, &serialized)
 }
 
@@ -420,12 +503,16 @@ pub fn do_db_scan<A: BackendApi, S: Storage, Q: Querier>(
     end_ptr: u32,
     order: i32,
 ) -> VmResult<u32> {
-    let start = maybe_read_region(&env.memory(), start_ptr, MAX_LENGTH_DB_KEY)?;
-    let end = maybe_read_region(&env.memory(), end_ptr, MAX_LENGTH_DB_KEY)?;
+    let start = maybe_read_input(env, start_ptr, env.wasm_limits.max_length_db_key)?;
+    let end = maybe_read_input(env, end_ptr, env.wasm_limits.max_length_db_key)?;
     let order: Order = order
         .try_into()
         .map_err(|_| CommunicationError::invalid_order(order))?;
 
+    let key_bytes = start.as_ref().map_or(0, Vec::len) + end.as_ref().map_or(0, Vec::len);
+    let gas_info = GasInfo::with_cost(bytes_cost(env.gas_config.db_scan_cost_per_byte, key_bytes));
+    process_gas_info::<A, S, Q>(env, gas_info)?;
+
     let (result, gas_info) = env.with_storage_from_context::<_, _>(|store| {
         Ok(store.scan(start.as_deref(), end.as_deref(), order))
     })?;
@@ -445,11 +532,96 @@ pub fn do_db_next<A: BackendApi, S: Storage, Q: Querier>(
 
     // Empty key will later be treated as _no more element_.
     let (key, value) = result?.unwrap_or_else(|| (Vec::<u8>::new(), Vec::<u8>::new()));
+    let gas_info = GasInfo::with_cost(bytes_cost(
+        env.gas_config.db_next_cost_per_byte,
+        key.len() + value.len(),
+    ));
+    process_gas_info::<A, S, Q>(env, gas_info)?;
 
     let out_data = encode_sections(&[key, value])?;
     write_to_contract::<A, S, Q>(env, &out_data)
 }
 
+/// Runs the given import and records the call if host calls are traced
+pub fn trace_host_call<A, S, Q, R, F>(
+    env: &Environment<A, S, Q>,
+    name: &str,
+    call: F,
+) -> VmResult<R>
+where
+    A: BackendApi,
+    S: Storage,
+    Q: Querier,
+    R: TraceReturnValue,
+    F: FnOnce() -> VmResult<R>,
+{
+    if !env.is_tracing_host_calls() {
+        return call();
+    }
+
+    let gas_left = env.get_gas_left();
+    env.with_host_call_tracer_mut(|tracer| tracer.begin(name, gas_left));
+    let result = call();
+    let gas_left = env.get_gas_left();
+    env.with_host_call_tracer_mut(|tracer| match &result {
+        Ok(value) => tracer.end(value.trace_return_value(), None, gas_left),
+        Err(err) => tracer.end(None, Some(err.to_string()), gas_left),
+    });
+    result
+}
+
+/// Reads a Region from Wasm memory and records it as an input of the current host call
+fn read_input<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+    ptr: u32,
+    max_length: usize,
+) -> VmResult<Vec<u8>> {
+    let data = read_region(&env.memory(), ptr, max_length)?;
+    env.with_host_call_tracer_mut(|tracer| tracer.add_input(&data));
+    Ok(data)
+}
+
+/// Like [`read_input`] but for optional Regions, which are recorded as empty if not set
+#[cfg(feature = "iterator")]
+fn maybe_read_input<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+    ptr: u32,
+    max_length: usize,
+) -> VmResult<Option<Vec<u8>>> {
+    let data = maybe_read_region(&env.memory(), ptr, max_length)?;
+    env.with_host_call_tracer_mut(|tracer| tracer.add_input(data.as_deref().unwrap_or_default()));
+    Ok(data)
+}
+
+/// Writes data to a Region in Wasm memory and records it as the output of the current host call
+fn write_output<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+    ptr: u32,
+    data: &[u8],
+) -> VmResult<()> {
+    write_region(&env.memory(), ptr, data)?;
+    env.with_host_call_tracer_mut(|tracer| tracer.set_output(data));
+    Ok(())
+}
+
+/// Charges the VM side cost of a host call that copies `len` bytes between Wasm memory
+/// and the host. This is done before calling the backend, independently of the gas it reports.
+fn charge_host_call<A: BackendApi, S: Storage, Q: Querier>(
+    env: &Environment<A, S, Q>,
+    base_cost: u64,
+    cost_per_byte: u64,
+    len: usize,
+) -> VmResult<()> {
+    let cost = base_cost.saturating_add(bytes_cost(cost_per_byte, len));
+    process_gas_info::<A, S, Q>(env, GasInfo::with_cost(cost))
+}
+
+/// Returns the cost of `len` bytes at the given price, saturating at `u64::MAX`.
+#[inline]
+fn bytes_cost(cost_per_byte: u64, len: usize) -> u64 {
+    cost_per_byte.saturating_mul(len as u64)
+}
+
 /// Returns the data shifted by 32 bits towards the most significant bit.
 ///
 /// This is independent of endianness. But to get the idea, it would be
@@ -485,6 +657,7 @@ mod tests {
     use crate::size::Size;
     use crate::testing::{MockApi, MockQuerier, MockStorage};
     use crate::wasm_backend::compile;
+    use crate::wasm_limits::WasmLimits;
 
     static CONTRACT: &[u8] = include_bytes!("../testdata/hackatom.wasm");
 
@@ -518,7 +691,7 @@ mod tests {
         Box<WasmerInstance>,
     ) {
         let gas_limit = TESTING_GAS_LIMIT;
-        let env = Environment::new(api, gas_limit, false);
+        let env = Environment::new(api, gas_limit);
 
         let module = compile(CONTRACT, TESTING_MEMORY_LIMIT, &[]).unwrap();
         let store = module.store();
@@ -631,6 +804,27 @@ mod tests {
         }
     }
 
+    #[test]
+    fn do_db_read_uses_configured_key_limit() {
+        let api = MockApi::default();
+        let (mut env, _instance) = make_instance(api);
+        env.wasm_limits.max_length_db_key = 2;
+        leave_default_data(&env);
+
+        let key_ptr = write_data(&env, KEY1);
+        let result = do_db_read(&env, key_ptr);
+        match result.unwrap_err() {
+            VmError::CommunicationErr {
+                source: CommunicationError::RegionLengthTooBig { length, max_length },
+                ..
+            } => {
+                assert_eq!(length, KEY1.len());
+                assert_eq!(max_length, 2);
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     #[test]
     fn do_db_write_works() {
         let api = MockApi::default();
@@ -654,6 +848,61 @@ mod tests {
         assert_eq!(val, Some(b"new value".to_vec()));
     }
 
+    #[test]
+    fn do_db_write_charges_vm_cost() {
+        let api = MockApi::default();
+        let (mut env, _instance) = make_instance(api);
+
+        let key_ptr = write_data(&env, b"new storage key");
+        let value_ptr = write_data(&env, b"new value");
+
+        leave_default_data(&env);
+
+        // The storage charges as well, so we compare with a free call
+        env.gas_config.db_write_cost = 0;
+        env.gas_config.db_write_cost_per_byte = 0;
+        let gas_before = env.get_gas_left();
+        do_db_write(&env, key_ptr, value_ptr).unwrap();
+        let free_gas_used = gas_before - env.get_gas_left();
+
+        env.gas_config.db_write_cost = 1_000_000;
+        env.gas_config.db_write_cost_per_byte = 1_000;
+        let gas_before = env.get_gas_left();
+        do_db_write(&env, key_ptr, value_ptr).unwrap();
+        assert_eq!(
+            gas_before - env.get_gas_left(),
+            free_gas_used + 1_000_000 + (15 + 9) * 1_000
+        );
+    }
+
+    #[test]
+    fn do_db_write_charges_vm_cost_before_calling_backend() {
+        let api = MockApi::default();
+        let (mut env, _instance) = make_instance(api);
+
+        let key_ptr = write_data(&env, b"new storage key");
+        let value_ptr = write_data(&env, b"new value");
+
+        leave_default_data(&env);
+
+        env.gas_config.db_write_cost = env.get_gas_left() + 1;
+        let result = do_db_write(&env, key_ptr, value_ptr);
+        match result.unwrap_err() {
+            VmError::GasDepletion { .. } => {}
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        let val = env
+            .with_storage_from_context::<_, _>(|store| {
+                Ok(store
+                    .get(b"new storage key")
+                    .0
+                    .expect("error getting value"))
+            })
+            .unwrap();
+        assert_eq!(val, None);
+    }
+
     #[test]
     fn do_db_write_can_override() {
         let api = MockApi::default();
@@ -717,7 +966,7 @@ mod tests {
                 ..
             } => {
                 assert_eq!(length, 300 * 1024);
-                assert_eq!(max_length, MAX_LENGTH_DB_KEY);
+                assert_eq!(max_length, WasmLimits::default().max_length_db_key);
             }
             err => panic!("unexpected error: {:?}", err),
         };
@@ -743,7 +992,7 @@ mod tests {
                 ..
             } => {
                 assert_eq!(length, 300 * 1024);
-                assert_eq!(max_length, MAX_LENGTH_DB_VALUE);
+                assert_eq!(max_length, WasmLimits::default().max_length_db_value);
             }
             err => panic!("unexpected error: {:?}", err),
         };
@@ -839,7 +1088,7 @@ mod tests {
                 ..
             } => {
                 assert_eq!(length, 300 * 1024);
-                assert_eq!(max_length, MAX_LENGTH_DB_KEY);
+                assert_eq!(max_length, WasmLimits::default().max_length_db_key);
             }
             err => panic!("unexpected error: {:?}", err),
         };
@@ -1682,6 +1931,23 @@ mod tests {
         )
     }
 
+    #[test]
+    fn do_debug_charges_per_byte() {
+        let api = MockApi::default();
+        let (mut env, _instance) = make_instance(api);
+
+        let message_ptr = write_data(&env, b"hello");
+
+        // free by default
+        let gas_before = env.get_gas_left();
+        do_debug(&env, message_ptr).unwrap();
+        assert_eq!(env.get_gas_left(), gas_before);
+
+        env.gas_config.debug_cost_per_byte = 1_000;
+        do_debug(&env, message_ptr).unwrap();
+        assert_eq!(env.get_gas_left(), gas_before - 5 * 1_000);
+    }
+
     #[test]
     fn do_query_chain_works() {
         let api = MockApi::default();
@@ -1706,6 +1972,38 @@ mod tests {
         assert_eq!(parsed_again.amount, coins(INIT_AMOUNT, INIT_DENOM));
     }
 
+    #[test]
+    fn do_query_chain_charges_vm_cost_for_request_and_response() {
+        let api = MockApi::default();
+        let (mut env, _instance) = make_instance(api);
+
+        let request: QueryRequest<Empty> = QueryRequest::Bank(BankQuery::AllBalances {
+            address: INIT_ADDR.to_string(),
+        });
+        let request_data = cosmwasm_std::to_vec(&request).unwrap();
+        let request_ptr = write_data(&env, &request_data);
+
+        leave_default_data(&env);
+
+        // The querier charges as well and writing the response executes Wasm code,
+        // so we compare with a free call
+        env.gas_config.query_chain_cost = 0;
+        env.gas_config.query_chain_cost_per_byte = 0;
+        let gas_before = env.get_gas_left();
+        let response_ptr = do_query_chain(&env, request_ptr).unwrap();
+        let free_gas_used = gas_before - env.get_gas_left();
+        let response_len = force_read(&env, response_ptr).len();
+
+        env.gas_config.query_chain_cost = 1_000_000;
+        env.gas_config.query_chain_cost_per_byte = 1_000;
+        let gas_before = env.get_gas_left();
+        do_query_chain(&env, request_ptr).unwrap();
+        assert_eq!(
+            gas_before - env.get_gas_left(),
+            free_gas_used + 1_000_000 + (request_data.len() + response_len) as u64 * 1_000
+        );
+    }
+
     #[test]
     fn do_query_chain_fails_for_broken_request() {
         let api = MockApi::default();
@@ -1928,6 +2226,36 @@ mod tests {
         // API makes no guarantees for value_ptr in this case
     }
 
+    #[test]
+    #[cfg(feature = "iterator")]
+    fn do_db_scan_and_db_next_charge_per_byte() {
+        let api = MockApi::default();
+        let (mut env, _instance) = make_instance(api);
+
+        leave_default_data(&env);
+        let start = write_data(&env, b"anna");
+
+        // The storage charges for both calls as well, so we compare with free calls
+        let gas_used = |env: &Environment<MockApi, MockStorage, MockQuerier>| {
+            let gas_before = env.get_gas_left();
+            let id = do_db_scan(env, start, 0, Order::Ascending.into()).unwrap();
+            let scan_gas = gas_before - env.get_gas_left();
+            let gas_before = env.get_gas_left();
+            do_db_next(env, id).unwrap();
+            (scan_gas, gas_before - env.get_gas_left())
+        };
+        env.gas_config.db_scan_cost_per_byte = 0;
+        env.gas_config.db_next_cost_per_byte = 0;
+        let (free_scan_gas, free_next_gas) = gas_used(&env);
+
+        env.gas_config.db_scan_cost_per_byte = 100;
+        env.gas_config.db_next_cost_per_byte = 1_000;
+        let (scan_gas, next_gas) = gas_used(&env);
+        assert_eq!(scan_gas, free_scan_gas + 4 * 100);
+        // Entry 1 has a key of 3 bytes and a value of 6 bytes
+        assert_eq!(next_gas, free_next_gas + 9 * 1_000);
+    }
+
     #[test]
     #[cfg(feature = "iterator")]
     fn do_db_next_fails_for_non_existent_id() {
@@ -1,24 +1,32 @@
 use std::collections::{HashMap, HashSet};
 use std::ptr::NonNull;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 
 use wasmer::{Exports, Function, ImportObject, Instance as WasmerInstance, Module, Val};
 
 use crate::backend::{Backend, BackendApi, Querier, Storage};
 use crate::capabilities::required_capabilities_from_module;
 use crate::conversion::{ref_to_u32, to_u32};
-use crate::environment::Environment;
+use crate::environment::{DebugInfo, Environment, GasConfig};
 use crate::errors::{CommunicationError, VmError, VmResult};
+use crate::gas_profile::{GasProfile, GasProfileRecorder};
 use crate::imports::{
     do_abort, do_addr_canonicalize, do_addr_humanize, do_addr_validate, do_db_read, do_db_remove,
-    do_db_write, do_debug, do_ed25519_batch_verify, do_ed25519_verify, do_query_chain,
-    do_secp256k1_recover_pubkey, do_secp256k1_verify, do_sha1_calculate,
+    do_db_write, do_debug, do_ed25519_batch_verify, do_ed25519_verify, do_profiler_enter,
+    do_profiler_leave, do_query_chain, do_secp256k1_recover_pubkey, do_secp256k1_verify,
+    do_sha1_calculate, trace_host_call,
 };
 #[cfg(feature = "iterator")]
 use crate::imports::{do_db_next, do_db_scan};
 use crate::memory::{read_region, write_region};
 use crate::size::Size;
-use crate::wasm_backend::compile;
+use crate::trace::HostCallTrace;
+use crate::wasm_backend::{
+    compile, compile_with_gas_profiling, set_stack_limit, GasCostTable, PROFILER_ENTER,
+    PROFILER_IMPORT_MODULE, PROFILER_LEAVE,
+};
+use crate::wasm_backtrace::WasmSymbolizer;
+use crate::wasm_limits::WasmLimits;
 
 #[derive(Copy, Clone, Debug)]
 pub struct GasReport {
@@ -31,13 +39,45 @@ pub struct GasReport {
     /// The amount of gas that was spend and metered internally (i.e. by executing Wasm and calling
     /// API methods which are not metered externally)
     pub used_internally: u64,
+    /// The largest size of the instance's memory in Wasm pages (64 KiB each). Since Wasm memory
+    /// cannot shrink, this is the current size.
+    pub peak_memory_pages: u32,
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct InstanceOptions {
     /// Gas limit measured in [CosmWasm gas](https://github.com/CosmWasm/cosmwasm/blob/main/docs/GAS.md).
     pub gas_limit: u64,
-    pub print_debug: bool,
+    /// Gas config for this instance. If this is `None`, the gas config of the cache is used
+    /// or [`GasConfig::default`] when not instantiated through a cache.
+    pub gas_config: Option<GasConfig>,
+    /// Records the gas used per Wasm function and import (see [`Instance::create_gas_profile`]).
+    /// This compiles the contract with additional instrumentation every time an instance is
+    /// created, so it should only be used during development.
+    pub gas_profiling: bool,
+    /// Attaches a backtrace of the running Wasm functions to runtime errors (see
+    /// [`VmError::RuntimeErr`]). Function names are taken from the name section and source
+    /// locations from DWARF debug info, if present. This is not needed for consensus and costs
+    /// loading the Wasm again for every instance created by a cache.
+    pub wasm_backtraces: bool,
+    /// Limits enforced while executing this instance, such as the maximum stack height and the
+    /// maximum lengths of import arguments. If this is `None`, the limits of the cache are used
+    /// or [`WasmLimits::default`] when not instantiated through a cache.
+    pub wasm_limits: Option<WasmLimits>,
+}
+
+impl InstanceOptions {
+    /// Creates instance options with the given gas limit. All other options are disabled or
+    /// taken from the cache.
+    pub fn new(gas_limit: u64) -> Self {
+        Self {
+            gas_limit,
+            gas_config: None,
+            gas_profiling: false,
+            wasm_backtraces: false,
+            wasm_limits: None,
+        }
+    }
 }
 
 pub struct Instance<A: BackendApi, S: Storage, Q: Querier> {
@@ -64,28 +104,41 @@ where
         options: InstanceOptions,
         memory_limit: Option<Size>,
     ) -> VmResult<Self> {
-        let module = compile(code, memory_limit, &[])?;
-        Instance::from_module(
+        let module = if options.gas_profiling {
+            compile_with_gas_profiling(code, memory_limit, &GasCostTable::default())?
+        } else {
+            compile(code, memory_limit, &[])?
+        };
+        let mut instance = Instance::from_module(
             &module,
             backend,
             options.gas_limit,
-            options.print_debug,
+            &options.wasm_limits.unwrap_or_default(),
+            &options.gas_config.unwrap_or_default(),
             None,
             None,
-        )
+        )?;
+        if options.wasm_backtraces {
+            instance.enable_wasm_backtraces(code);
+        }
+        Ok(instance)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn from_module(
         module: &Module,
         backend: Backend<A, S, Q>,
         gas_limit: u64,
-        print_debug: bool,
+        wasm_limits: &WasmLimits,
+        gas_config: &GasConfig,
         extra_imports: Option<HashMap<&str, Exports>>,
         instantiation_lock: Option<&Mutex<()>>,
     ) -> VmResult<Self> {
         let store = module.store();
 
-        let env = Environment::new(backend.api, gas_limit, print_debug);
+        let mut env = Environment::new(backend.api, gas_limit);
+        env.wasm_limits = *wasm_limits;
+        env.gas_config = *gas_config;
 
         let mut import_obj = ImportObject::new();
         let mut env_imports = Exports::new();
@@ -96,14 +149,26 @@ where
         // Ownership of the value pointer is transferred to the contract.
         env_imports.insert(
             "db_read",
-            Function::new_native_with_env(store, env.clone(), do_db_read),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, key_ptr: u32| {
+                    trace_host_call(env, "db_read", || do_db_read(env, key_ptr))
+                },
+            ),
         );
 
         // Writes the given value into the database entry at the given key.
         // Ownership of both input and output pointer is not transferred to the host.
         env_imports.insert(
             "db_write",
-            Function::new_native_with_env(store, env.clone(), do_db_write),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, key_ptr: u32, value_ptr: u32| {
+                    trace_host_call(env, "db_write", || do_db_write(env, key_ptr, value_ptr))
+                },
+            ),
         );
 
         // Removes the value at the given key. Different than writing &[] as future
@@ -112,7 +177,13 @@ where
         // Ownership of both key pointer is not transferred to the host.
         env_imports.insert(
             "db_remove",
-            Function::new_native_with_env(store, env.clone(), do_db_remove),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, key_ptr: u32| {
+                    trace_host_call(env, "db_remove", || do_db_remove(env, key_ptr))
+                },
+            ),
         );
 
         // Reads human address from source_ptr and checks if it is valid.
@@ -120,7 +191,13 @@ where
         // Ownership of the input pointer is not transferred to the host.
         env_imports.insert(
             "addr_validate",
-            Function::new_native_with_env(store, env.clone(), do_addr_validate),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, source_ptr: u32| {
+                    trace_host_call(env, "addr_validate", || do_addr_validate(env, source_ptr))
+                },
+            ),
         );
 
         // Reads human address from source_ptr and writes canonicalized representation to destination_ptr.
@@ -129,7 +206,15 @@ where
         // Ownership of both input and output pointer is not transferred to the host.
         env_imports.insert(
             "addr_canonicalize",
-            Function::new_native_with_env(store, env.clone(), do_addr_canonicalize),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, source_ptr: u32, destination_ptr: u32| {
+                    trace_host_call(env, "addr_canonicalize", || {
+                        do_addr_canonicalize(env, source_ptr, destination_ptr)
+                    })
+                },
+            ),
         );
 
         // Reads canonical address from source_ptr and writes humanized representation to destination_ptr.
@@ -138,7 +223,15 @@ where
         // Ownership of both input and output pointer is not transferred to the host.
         env_imports.insert(
             "addr_humanize",
-            Function::new_native_with_env(store, env.clone(), do_addr_humanize),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, source_ptr: u32, destination_ptr: u32| {
+                    trace_host_call(env, "addr_humanize", || {
+                        do_addr_humanize(env, source_ptr, destination_ptr)
+                    })
+                },
+            ),
         );
 
         // Verifies message hashes against a signature with a public key, using the secp256k1 ECDSA parametrization.
@@ -146,12 +239,31 @@ where
         // Ownership of input pointers is not transferred to the host.
         env_imports.insert(
             "secp256k1_verify",
-            Function::new_native_with_env(store, env.clone(), do_secp256k1_verify),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, hash_ptr: u32, signature_ptr: u32, pubkey_ptr: u32| {
+                    trace_host_call(env, "secp256k1_verify", || {
+                        do_secp256k1_verify(env, hash_ptr, signature_ptr, pubkey_ptr)
+                    })
+                },
+            ),
         );
 
         env_imports.insert(
             "secp256k1_recover_pubkey",
-            Function::new_native_with_env(store, env.clone(), do_secp256k1_recover_pubkey),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>,
+                 hash_ptr: u32,
+                 signature_ptr: u32,
+                 recover_param: u32| {
+                    trace_host_call(env, "secp256k1_recover_pubkey", || {
+                        do_secp256k1_recover_pubkey(env, hash_ptr, signature_ptr, recover_param)
+                    })
+                },
+            ),
         );
 
         // Verifies a message against a signature with a public key, using the ed25519 EdDSA scheme.
@@ -159,7 +271,18 @@ where
         // Ownership of input pointers is not transferred to the host.
         env_imports.insert(
             "ed25519_verify",
-            Function::new_native_with_env(store, env.clone(), do_ed25519_verify),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>,
+                 message_ptr: u32,
+                 signature_ptr: u32,
+                 pubkey_ptr: u32| {
+                    trace_host_call(env, "ed25519_verify", || {
+                        do_ed25519_verify(env, message_ptr, signature_ptr, pubkey_ptr)
+                    })
+                },
+            ),
         );
 
         // Verifies a batch of messages against a batch of signatures with a batch of public keys,
@@ -169,14 +292,33 @@ where
         // Ownership of input pointers is not transferred to the host.
         env_imports.insert(
             "ed25519_batch_verify",
-            Function::new_native_with_env(store, env.clone(), do_ed25519_batch_verify),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>,
+                 messages_ptr: u32,
+                 signatures_ptr: u32,
+                 public_keys_ptr: u32| {
+                    trace_host_call(env, "ed25519_batch_verify", || {
+                        do_ed25519_batch_verify(env, messages_ptr, signatures_ptr, public_keys_ptr)
+                    })
+                },
+            ),
         );
 
         // No longer supported
         // This function only returns an error to wasm.
         env_imports.insert(
             "sha1_calculate",
-            Function::new_native_with_env(store, env.clone(), do_sha1_calculate),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, hash_inputs_ptr: u32| {
+                    trace_host_call(env, "sha1_calculate", || {
+                        do_sha1_calculate(env, hash_inputs_ptr)
+                    })
+                },
+            ),
         );
 
         // Allows the contract to emit debug logs that the host can either process or ignore.
@@ -185,7 +327,13 @@ where
         // Ownership of both input and output pointer is not transferred to the host.
         env_imports.insert(
             "debug",
-            Function::new_native_with_env(store, env.clone(), do_debug),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, message_ptr: u32| {
+                    trace_host_call(env, "debug", || do_debug(env, message_ptr))
+                },
+            ),
         );
 
         // Aborts the contract execution with an error message provided by the contract.
@@ -193,12 +341,24 @@ where
         // Ownership of both input and output pointer is not transferred to the host.
         env_imports.insert(
             "abort",
-            Function::new_native_with_env(store, env.clone(), do_abort),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, message_ptr: u32| {
+                    trace_host_call(env, "abort", || do_abort(env, message_ptr))
+                },
+            ),
         );
 
         env_imports.insert(
             "query_chain",
-            Function::new_native_with_env(store, env.clone(), do_query_chain),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, request_ptr: u32| {
+                    trace_host_call(env, "query_chain", || do_query_chain(env, request_ptr))
+                },
+            ),
         );
 
         // Creates an iterator that will go from start to end.
@@ -210,7 +370,15 @@ where
         #[cfg(feature = "iterator")]
         env_imports.insert(
             "db_scan",
-            Function::new_native_with_env(store, env.clone(), do_db_scan),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, start_ptr: u32, end_ptr: u32, order: i32| {
+                    trace_host_call(env, "db_scan", || {
+                        do_db_scan(env, start_ptr, end_ptr, order)
+                    })
+                },
+            ),
         );
 
         // Get next element of iterator with ID `iterator_id`.
@@ -221,11 +389,35 @@ where
         #[cfg(feature = "iterator")]
         env_imports.insert(
             "db_next",
-            Function::new_native_with_env(store, env.clone(), do_db_next),
+            Function::new_native_with_env(
+                store,
+                env.clone(),
+                |env: &Environment<A, S, Q>, iterator_id: u32| {
+                    trace_host_call(env, "db_next", || do_db_next(env, iterator_id))
+                },
+            ),
         );
 
+        // All imports above are wrapped in trace_host_call, which records them
+        // when tracing is enabled (see Instance::set_host_call_tracing).
         import_obj.register("env", env_imports);
 
+        // Modules compiled for gas profiling report calls to those imports
+        let gas_profile = GasProfileRecorder::for_module(module);
+        if gas_profile.is_some() {
+            let mut profiler_imports = Exports::new();
+            profiler_imports.insert(
+                PROFILER_ENTER,
+                Function::new_native_with_env(store, env.clone(), do_profiler_enter),
+            );
+            profiler_imports.insert(
+                PROFILER_LEAVE,
+                Function::new_native_with_env(store, env.clone(), do_profiler_leave),
+            );
+            import_obj.register(PROFILER_IMPORT_MODULE, profiler_imports);
+        }
+        env.set_gas_profile(gas_profile);
+
         if let Some(extra_imports) = extra_imports {
             for (namespace, exports_obj) in extra_imports {
                 import_obj.register(namespace, exports_obj);
@@ -242,6 +434,10 @@ where
             })?,
         );
 
+        if let Some(max_stack_height) = wasm_limits.max_stack_height {
+            set_stack_limit(&wasmer_instance, max_stack_height);
+        }
+
         let instance_ptr = NonNull::from(wasmer_instance.as_ref());
         env.set_wasmer_instance(Some(instance_ptr));
         env.set_gas_left(gas_limit);
@@ -311,9 +507,50 @@ where
                 .gas_limit
                 .saturating_sub(state.externally_used_gas)
                 .saturating_sub(gas_left),
+            peak_memory_pages: self.env.memory().size().0,
         }
     }
 
+    /// Creates a snapshot of the gas used per Wasm function and import since the instance was
+    /// created. Returns `None` if [`InstanceOptions::gas_profiling`] is not enabled.
+    pub fn create_gas_profile(&self) -> Option<GasProfile> {
+        self.env
+            .with_gas_profile_mut(|profile, _| profile.profile())
+    }
+
+    /// Attaches symbolized backtraces to runtime errors. The Wasm must be the code the module
+    /// of this instance was compiled from.
+    pub(crate) fn enable_wasm_backtraces(&mut self, wasm: &[u8]) {
+        self.env
+            .set_wasm_symbolizer(Some(WasmSymbolizer::new(wasm)));
+    }
+
+    /// Sets the callback that receives the debug messages of the contract, replacing the
+    /// current one. Without a debug handler, debug messages are ignored.
+    pub fn set_debug_handler<H>(&mut self, debug_handler: H)
+    where
+        H: for<'a, 'b> FnMut(/* message */ &'a str, DebugInfo<'b>) + Send + 'static,
+    {
+        self.env
+            .set_debug_handler(Some(Arc::new(Mutex::new(debug_handler))));
+    }
+
+    pub fn unset_debug_handler(&mut self) {
+        self.env.set_debug_handler(None);
+    }
+
+    /// Starts or stops recording all calls the contract makes to imports (see [`Instance::take_trace`]).
+    /// This is disabled by default. Stopping drops the calls recorded so far.
+    pub fn set_host_call_tracing(&mut self, enabled: bool) {
+        self.env.set_host_call_tracing(enabled);
+    }
+
+    /// Returns the host calls recorded since tracing was enabled or the last call of this function.
+    /// Returns `None` if tracing is not enabled.
+    pub fn take_trace(&mut self) -> Option<HostCallTrace> {
+        self.env.with_host_call_tracer_mut(|tracer| tracer.take())
+    }
+
     /// Sets the readonly storage flag on this instance. Since one instance can be used
     /// for multiple calls in integration tests, this should be set to the desired value
     /// right before every call.
@@ -378,7 +615,6 @@ pub fn instance_from_module<A, S, Q>(
     module: &Module,
     backend: Backend<A, S, Q>,
     gas_limit: u64,
-    print_debug: bool,
     extra_imports: Option<HashMap<&str, Exports>>,
 ) -> VmResult<Instance<A, S, Q>>
 where
@@ -386,7 +622,15 @@ where
     S: Storage + 'static, // 'static is needed here to allow using this in an Environment that is cloned into closures
     Q: Querier + 'static,
 {
-    Instance::from_module(module, backend, gas_limit, print_debug, extra_imports, None)
+    Instance::from_module(
+        module,
+        backend,
+        gas_limit,
+        &WasmLimits::default(),
+        &GasConfig::default(),
+        extra_imports,
+        None,
+    )
 }
 
 #[cfg(test)]
@@ -404,7 +648,7 @@ mod tests {
         mock_instance_with_options, MockInstanceOptions,
     };
     use cosmwasm_std::{
-        coin, coins, from_binary, AllBalanceResponse, BalanceResponse, BankQuery, Empty,
+        coin, coins, from_binary, AllBalanceResponse, BalanceResponse, BankQuery, Binary, Empty,
         QueryRequest,
     };
 
@@ -483,7 +727,8 @@ mod tests {
             &module,
             backend,
             instance_options.gas_limit,
-            false,
+            &WasmLimits::default(),
+            &GasConfig::default(),
             Some(extra_imports),
             None,
         )
@@ -525,6 +770,56 @@ mod tests {
         assert_ne!(result.unwrap_i32(), 0);
     }
 
+    #[test]
+    fn call_function_fails_when_exceeding_stack_limit() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (func $recurse (export "recurse") (param i32) (result i32)
+                local.get 0
+                i32.eqz
+                if (result i32)
+                    i32.const 0
+                else
+                    local.get 0
+                    i32.const 1
+                    i32.sub
+                    call $recurse
+                end
+            )
+            )"#,
+        )
+        .unwrap();
+        let (instance_options, memory_limit) = mock_instance_options();
+        let options = InstanceOptions {
+            wasm_limits: Some(WasmLimits {
+                max_stack_height: Some(50),
+                ..WasmLimits::default()
+            }),
+            ..instance_options
+        };
+        let instance =
+            Instance::from_code(&wasm, mock_backend(&[]), options, memory_limit).unwrap();
+
+        // Every frame costs 5 (2 for the activation, 1 parameter and 2 operands)
+        // and the frame of the entry point is not counted
+        instance.call_function1("recurse", &[10.into()]).unwrap();
+        match instance
+            .call_function1("recurse", &[11.into()])
+            .unwrap_err()
+        {
+            VmError::StackLimitExceeded { limit, .. } => assert_eq!(limit, 50),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+
+        // The frames of the failed call are removed
+        instance.call_function1("recurse", &[10.into()]).unwrap();
+
+        // Not limited by default
+        let backend = mock_backend(&[]);
+        let instance = Instance::from_code(&wasm, backend, instance_options, memory_limit).unwrap();
+        instance.call_function1("recurse", &[5000.into()]).unwrap();
+    }
+
     #[test]
     fn allocate_deallocate_works() {
         let mut instance = mock_instance_with_options(
@@ -705,6 +1000,7 @@ mod tests {
         assert_eq!(report1.used_internally, 0);
         assert_eq!(report1.limit, LIMIT);
         assert_eq!(report1.remaining, LIMIT);
+        assert_eq!(report1.peak_memory_pages, 17);
 
         // init contract
         let info = mock_info("creator", &coins(1000, "earth"));
@@ -715,12 +1011,204 @@ mod tests {
 
         let report2 = instance.create_gas_report();
         assert_eq!(report2.used_externally, 73);
-        assert_eq!(report2.used_internally, 5775750198);
+        assert_eq!(report2.used_internally, 6077530198);
         assert_eq!(report2.limit, LIMIT);
         assert_eq!(
             report2.remaining,
             LIMIT - report2.used_externally - report2.used_internally
         );
+        // instantiate grows the memory by one page
+        assert_eq!(report2.peak_memory_pages, 18);
+    }
+
+    #[test]
+    fn create_gas_profile_works() {
+        let instance = mock_instance(CONTRACT, &[]);
+        assert_eq!(instance.create_gas_profile(), None);
+
+        let (instance_options, memory_limit) = mock_instance_options();
+        let instance_options = InstanceOptions {
+            gas_profiling: true,
+            ..instance_options
+        };
+        let mut instance =
+            Instance::from_code(CONTRACT, mock_backend(&[]), instance_options, memory_limit)
+                .unwrap();
+        assert_eq!(instance.create_gas_profile(), Some(GasProfile::default()));
+
+        // init contract
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+
+        // Same as without profiling
+        let report = instance.create_gas_report();
+        assert_eq!(report.used_externally, 73);
+        assert_eq!(report.used_internally, 6077530198);
+
+        // The test contract has no name section, so only imports have names
+        let profile = instance.create_gas_profile().unwrap();
+        assert_eq!(
+            profile.imports.keys().collect::<Vec<_>>(),
+            ["addr_validate", "db_write"]
+        );
+        assert!(profile
+            .functions
+            .keys()
+            .all(|name| name.starts_with("func[")));
+        let total: u64 = profile
+            .functions
+            .values()
+            .chain(profile.imports.values())
+            .sum();
+        assert_eq!(total, report.used_internally + report.used_externally);
+        assert_eq!(profile.stacks.values().sum::<u64>(), total);
+        assert_eq!(profile.to_folded().lines().count(), profile.stacks.len());
+    }
+
+    #[test]
+    fn create_gas_profile_works_for_recursion() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (type $t (func (param i32)))
+            (memory (export "memory") 1)
+            (table 1 funcref)
+            (elem (i32.const 0) $recurse)
+            (func $recurse (export "recurse") (param i32)
+                local.get 0
+                if
+                    local.get 0
+                    i32.const 1
+                    i32.sub
+                    call $recurse
+                end
+            )
+            (func $indirect (export "indirect") (param i32)
+                local.get 0
+                i32.const 0
+                call_indirect (type $t)
+            )
+            )"#,
+        )
+        .unwrap();
+        let (instance_options, memory_limit) = mock_instance_options();
+        let instance_options = InstanceOptions {
+            gas_profiling: true,
+            ..instance_options
+        };
+        let instance =
+            Instance::from_code(&wasm, mock_backend(&[]), instance_options, memory_limit).unwrap();
+
+        instance.call_function0("recurse", &[2.into()]).unwrap();
+        instance.call_function0("indirect", &[1.into()]).unwrap();
+
+        let profile = instance.create_gas_profile().unwrap();
+        let stacks: Vec<Vec<&str>> = profile
+            .stacks
+            .keys()
+            .map(|stack| stack.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            stacks,
+            [
+                vec!["indirect"],
+                vec!["indirect", "recurse"],
+                vec!["indirect", "recurse", "recurse"],
+                vec!["recurse"],
+                vec!["recurse", "recurse"],
+                vec!["recurse", "recurse", "recurse"],
+            ]
+        );
+        let report = instance.create_gas_report();
+        assert_eq!(profile.stacks.values().sum::<u64>(), report.used_internally);
+    }
+
+    #[test]
+    fn take_trace_works() {
+        let mut instance = mock_instance(CONTRACT, &[]);
+        assert_eq!(instance.take_trace(), None);
+        instance.set_host_call_tracing(true);
+
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        let trace = instance.take_trace().unwrap();
+        let names: Vec<_> = trace.calls.iter().map(|call| call.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["debug", "addr_validate", "addr_validate", "db_write"]
+        );
+
+        let validate = &trace.calls[1];
+        assert_eq!(validate.inputs, [Binary::from(b"verifies")]);
+        assert_eq!(validate.return_value, Some(0));
+        let write = &trace.calls[3];
+        assert_eq!(write.inputs[0], Binary::from(b"config"));
+        assert_eq!(write.output, None);
+        assert_eq!(write.return_value, None);
+        assert_eq!(write.error, None);
+        assert_eq!(write.gas_info.externally_used, 73);
+        for call in &trace.calls {
+            // No Wasm code runs during those calls
+            assert_eq!(
+                call.gas_left_before - call.gas_left_after,
+                call.gas_info.cost + call.gas_info.externally_used
+            );
+        }
+
+        // Taking starts a new trace
+        assert_eq!(instance.take_trace(), Some(HostCallTrace::default()));
+        instance.set_host_call_tracing(false);
+        assert_eq!(instance.take_trace(), None);
+    }
+
+    #[test]
+    fn set_debug_handler_works() {
+        let mut instance = mock_instance(CONTRACT, &[]);
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let recorded = messages.clone();
+        instance.set_debug_handler(move |message, info| {
+            recorded.lock().unwrap().push((
+                message.to_string(),
+                info.entry_point.to_string(),
+                info.gas_remaining,
+                info.sequence,
+            ));
+        });
+
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let msg = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        let gas_left = instance.get_gas_left();
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+
+        let handled = std::mem::take(&mut *messages.lock().unwrap());
+        assert_eq!(handled.len(), 2);
+        for (index, (message, entry_point, gas_remaining, sequence)) in
+            handled.into_iter().enumerate()
+        {
+            assert_eq!(message, "here we go 🚀");
+            assert_eq!(entry_point, "instantiate");
+            assert_eq!(sequence, index as u64);
+            if index == 1 {
+                assert!(gas_remaining < gas_left);
+            }
+        }
+
+        // Without a handler, messages are ignored
+        instance.unset_debug_handler();
+        call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, msg)
+            .unwrap()
+            .unwrap();
+        assert!(messages.lock().unwrap().is_empty());
     }
 
     #[test]
@@ -904,7 +1392,7 @@ mod tests {
             .unwrap();
 
         let init_used = orig_gas - instance.get_gas_left();
-        assert_eq!(init_used, 5775750271);
+        assert_eq!(init_used, 6077530271);
     }
 
     #[test]
@@ -927,7 +1415,7 @@ mod tests {
             .unwrap();
 
         let execute_used = gas_before_execute - instance.get_gas_left();
-        assert_eq!(execute_used, 8627053606);
+        assert_eq!(execute_used, 8830333606);
     }
 
     #[test]
@@ -961,6 +1449,6 @@ mod tests {
         assert_eq!(answer.as_slice(), b"{\"verifier\":\"verifies\"}");
 
         let query_used = gas_before_query - instance.get_gas_left();
-        assert_eq!(query_used, 4438350006);
+        assert_eq!(query_used, 4539810006);
     }
 }
@@ -6,25 +6,36 @@ mod calls;
 mod capabilities;
 mod checksum;
 mod compatibility;
+mod compression;
 mod conversion;
 mod environment;
 mod errors;
+mod gas_profile;
 mod imports;
 mod instance;
 mod limited;
 mod memory;
 mod modules;
+mod prometheus;
+mod replay;
 mod sections;
 mod serde;
 mod size;
+mod snapshot;
 mod static_analysis;
 pub mod testing;
+mod trace;
 mod wasm_backend;
+mod wasm_backtrace;
+mod wasm_limits;
 
 pub use crate::backend::{
     Backend, BackendApi, BackendError, BackendResult, GasInfo, Querier, Storage,
 };
-pub use crate::cache::{AnalysisReport, Cache, CacheOptions, Metrics, Stats};
+pub use crate::cache::{
+    AnalysisReport, Cache, CacheOptions, ContractMetrics, Metrics, ModuleUpgradeReport,
+    PinnedRestoreMode, Stats, WarmUpAction, WarmUpEntry,
+};
 pub use crate::calls::{
     call_execute, call_execute_raw, call_instantiate, call_instantiate_raw, call_migrate,
     call_migrate_raw, call_query, call_query_raw, call_reply, call_reply_raw, call_sudo,
@@ -39,13 +50,28 @@ pub use crate::calls::{
 };
 pub use crate::capabilities::capabilities_from_csv;
 pub use crate::checksum::Checksum;
+pub use crate::compatibility::{WasmViolation, WasmViolationCategory};
+pub use crate::compression::WasmCompression;
+pub use crate::environment::{DebugHandler, DebugInfo, GasConfig};
 pub use crate::errors::{
     CommunicationError, CommunicationResult, RegionValidationError, RegionValidationResult,
     VmError, VmResult,
 };
+pub use crate::gas_profile::GasProfile;
 pub use crate::instance::{GasReport, Instance, InstanceOptions};
+pub use crate::modules::{GarbageCollectionReport, MemoryCacheEvictionPolicy};
+pub use crate::prometheus::render_prometheus;
+pub use crate::replay::{
+    BackendLog, BackendRecorder, BackendRequest, BackendResponse, RecordedCall, RecordingApi,
+    RecordingBackend, ReplayApi, ReplayBackend,
+};
 pub use crate::serde::{from_slice, to_vec};
 pub use crate::size::Size;
+pub use crate::snapshot::SnapshotReport;
+pub use crate::trace::{HostCall, HostCallTrace};
+pub use crate::wasm_backend::GasCostTable;
+pub use crate::wasm_backtrace::{SourceLocation, WasmBacktrace, WasmFrame};
+pub use crate::wasm_limits::WasmLimits;
 
 #[doc(hidden)]
 pub mod internals {
@@ -54,7 +80,9 @@ pub mod internals {
     //! Please don't use any of these types directly, as
     //! they might change frequently or be removed in the future.
 
-    pub use crate::compatibility::check_wasm;
+    pub use crate::compatibility::{check_wasm, check_wasm_report};
     pub use crate::instance::instance_from_module;
-    pub use crate::wasm_backend::{compile, make_runtime_store};
+    pub use crate::wasm_backend::{
+        compile, compile_with_gas_costs, compile_with_gas_profiling, make_runtime_store,
+    };
 }
@@ -0,0 +1,174 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::cache::AnalysisReport;
+use crate::checksum::Checksum;
+use crate::errors::{VmError, VmResult};
+
+/// Bump this version whenever the fields of [`AnalysisReport`] or the way they are computed
+/// change. Stored results of other versions are ignored and computed again.
+///
+/// ## Version history:
+/// - **1**: entry points, interface version, imports and module sizes
+const ANALYSIS_FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct StoredAnalysis {
+    format_version: u32,
+    report: AnalysisReport,
+}
+
+/// Keeps the static analysis results of all contracts in memory and on disk,
+/// such that a contract does not need to be parsed again to be analyzed.
+///
+/// Every result is stored as a JSON file named by the checksum's hex representation.
+pub struct AnalysisCache {
+    path: PathBuf,
+    reports: HashMap<Checksum, AnalysisReport>,
+}
+
+impl AnalysisCache {
+    /// Creates a cache that stores its files in the given directory.
+    /// The directory is created if it does not exist.
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path: PathBuf = path.into();
+        fs::create_dir_all(&path)?;
+        Ok(AnalysisCache {
+            path,
+            reports: HashMap::new(),
+        })
+    }
+
+    /// Looks up the analysis result in memory first and on disk second.
+    ///
+    /// Returns `None` if there is no result or it was stored with a different
+    /// analysis format version or cannot be parsed.
+    pub fn load(&mut self, checksum: &Checksum) -> VmResult<Option<AnalysisReport>> {
+        if let Some(report) = self.reports.get(checksum) {
+            return Ok(Some(report.clone()));
+        }
+
+        let data = match fs::read(self.file_path(checksum)) {
+            Ok(data) => data,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => {
+                return Err(VmError::cache_err(format!(
+                    "Error reading analysis result: {}",
+                    err
+                )))
+            }
+        };
+        match serde_json::from_slice::<StoredAnalysis>(&data) {
+            Ok(stored) if stored.format_version == ANALYSIS_FORMAT_VERSION => {
+                self.reports.insert(*checksum, stored.report.clone());
+                Ok(Some(stored.report))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Stores the analysis result in memory and on disk, replacing existing results.
+    pub fn store(&mut self, checksum: &Checksum, report: AnalysisReport) -> VmResult<()> {
+        let stored = StoredAnalysis {
+            format_version: ANALYSIS_FORMAT_VERSION,
+            report,
+        };
+        let data = serde_json::to_vec(&stored)
+            .map_err(|e| VmError::cache_err(format!("Error serializing analysis result: {}", e)))?;
+
+        // Write to a temporary file first such that no partially written results are left
+        // behind when the process is interrupted
+        let path = self.file_path(checksum);
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .and_then(|_| fs::rename(&tmp_path, &path))
+            .map_err(|e| VmError::cache_err(format!("Error writing analysis result: {}", e)))?;
+        self.reports.insert(*checksum, stored.report);
+        Ok(())
+    }
+
+    /// Removes the analysis result from memory and disk.
+    /// Not found results are silently ignored.
+    pub fn remove(&mut self, checksum: &Checksum) -> VmResult<()> {
+        self.reports.remove(checksum);
+        match fs::remove_file(self.file_path(checksum)) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(VmError::cache_err(format!(
+                "Error removing analysis result: {}",
+                err
+            ))),
+        }
+    }
+
+    fn file_path(&self, checksum: &Checksum) -> PathBuf {
+        self.path.join(format!("{}.json", checksum.to_hex()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeSet, HashSet};
+    use tempfile::TempDir;
+
+    fn make_report(wasm_size: usize) -> AnalysisReport {
+        AnalysisReport {
+            has_ibc_entry_points: false,
+            required_capabilities: HashSet::from_iter(["iterator".to_string()]),
+            entry_points: BTreeSet::from_iter(["instantiate".to_string()]),
+            interface_version: Some(8),
+            imported_functions: BTreeSet::from_iter(["env.db_read".to_string()]),
+            wasm_size,
+            function_count: 3,
+            initial_memory_pages: 17,
+            data_segment_size: 42,
+        }
+    }
+
+    #[test]
+    fn store_and_load_work() {
+        let tmp_dir = TempDir::new().unwrap();
+        let checksum = Checksum::generate(b"wasm");
+
+        let mut cache = AnalysisCache::new(tmp_dir.path().join("analysis")).unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), None);
+        cache.store(&checksum, make_report(1)).unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), Some(make_report(1)));
+
+        // A new instance reads from disk
+        let mut cache = AnalysisCache::new(tmp_dir.path().join("analysis")).unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), Some(make_report(1)));
+
+        cache.remove(&checksum).unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), None);
+        // removing again has no effect
+        cache.remove(&checksum).unwrap();
+    }
+
+    #[test]
+    fn load_ignores_other_format_versions_and_invalid_files() {
+        let tmp_dir = TempDir::new().unwrap();
+        let checksum = Checksum::generate(b"wasm");
+        let mut cache = AnalysisCache::new(tmp_dir.path()).unwrap();
+        let path = cache.file_path(&checksum);
+
+        let stale = StoredAnalysis {
+            format_version: ANALYSIS_FORMAT_VERSION + 1,
+            report: make_report(1),
+        };
+        fs::write(&path, serde_json::to_vec(&stale).unwrap()).unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), None);
+
+        fs::write(&path, b"{\"format_version\":1}").unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), None);
+
+        // Storing replaces the file
+        cache.store(&checksum, make_report(2)).unwrap();
+        let mut cache = AnalysisCache::new(tmp_dir.path()).unwrap();
+        assert_eq!(cache.load(&checksum).unwrap(), Some(make_report(2)));
+    }
+}
@@ -1,15 +1,20 @@
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io;
 use std::panic::catch_unwind;
 use std::panic::AssertUnwindSafe;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use wasmer::{DeserializeError, Module, Store};
+use sha2::{Digest, Sha256};
+use wasmer::{Module, Store};
 
 use crate::checksum::Checksum;
 use crate::errors::{VmError, VmResult};
+use crate::size::Size;
 
 use crate::modules::current_wasmer_module_version;
+use crate::wasm_backend::GasCostTable;
 
 /// Bump this version whenever the module system changes in a way
 /// that old stored modules would be corrupt when loaded in the new system.
@@ -39,36 +44,171 @@ use crate::modules::current_wasmer_module_version;
 ///   the module header version (<https://github.com/wasmerio/wasmer/issues/3193>). In cosmwasm-vm 1.1.0-1.1.1
 ///   the old value "v3" is still used along with Wasmer 2.3.0 (bug). From cosmwasm 1.1.2 onwards, this is
 ///   fixed by bumping to "v4".
-const MODULE_SERIALIZATION_VERSION: &str = "v4";
+/// - **v5**:<br>
+///   Every module is wrapped in an integrity envelope (see [`ENVELOPE_MAGIC`]) that allows detecting
+///   corrupted files before they are deserialized.
+/// - **v6**:<br>
+///   Modules are instrumented with the stack limiter (see [`crate::WasmLimits::max_stack_height`]),
+///   so modules of previous versions cannot be used anymore.
+const MODULE_SERIALIZATION_VERSION: &str = "v6";
+
+/// Previous values of [`MODULE_SERIALIZATION_VERSION`] whose modules can still be deserialized
+/// when they were created with the current Wasmer module version.
+///
+/// When bumping [`MODULE_SERIALIZATION_VERSION`] without changing the serialization of the
+/// modules themselves, add the previous version here such that existing modules are migrated
+/// instead of recompiled. Only versions that use the integrity envelope (v5 and later) can be
+/// added, since files without it cannot be verified before deserialization.
+const COMPATIBLE_SERIALIZATION_VERSIONS: &[&str] = &[];
+
+/// The first bytes of every module file.
+///
+/// A module file consists of the following parts:
+/// 1. this magic (8 bytes)
+/// 2. the Wasmer module version, little endian encoded (4 bytes)
+/// 3. the fingerprint of the gas cost table the module was compiled with (see
+///    [`GasCostTable::fingerprint`]), little endian encoded (4 bytes). This was reserved and
+///    all zero before gas cost tables existed, which is the fingerprint of the default table.
+/// 4. the checksum of the Wasm the module was compiled from (32 bytes)
+/// 5. the SHA-256 hash of the serialized module (32 bytes)
+/// 6. the serialized module
+///
+/// The header length is a multiple of [`SERIALIZED_MODULE_ALIGN`] such that the serialized
+/// module is aligned whenever the file content is.
+const ENVELOPE_MAGIC: &[u8; 8] = b"CWMODENV";
+const ENVELOPE_HEADER_LEN: usize = 8 + 4 + 4 + 32 + 32;
+
+/// Wasmer requires serialized modules to be 16 byte aligned in memory
+const SERIALIZED_MODULE_ALIGN: usize = 16;
 
 /// Representation of a directory that contains compiled Wasm artifacts.
 pub struct FileSystemCache {
     /// The base path this cache operates in. Within this path, versioned directories are created.
-    /// A sophisticated version of this cache might be able to read multiple input versions in the future.
+    /// Modules of the compatible previous versions are read as well (see [`COMPATIBLE_SERIALIZATION_VERSIONS`]).
     base_path: PathBuf,
     wasmer_module_version: u32,
+    /// See [`GasCostTable::fingerprint`]. Modules of tables other than the default one are
+    /// stored in separate directories.
+    gas_cost_fingerprint: u32,
+    /// Always [`COMPATIBLE_SERIALIZATION_VERSIONS`], except for tests of the migration
+    compatible_versions: &'static [&'static str],
+    /// The maximum cumulative size of all modules of the latest version in bytes.
+    /// When exceeded, the least recently used modules are removed from disk.
+    /// `None` means the cache is unbounded.
+    max_size: Option<usize>,
+    /// Size and last access of every module of the latest version on disk
+    entries: HashMap<Checksum, FileSystemCacheEntry>,
+    /// A monotonic counter used to order accesses
+    access_counter: u64,
+    evictions: usize,
+    migrations: usize,
+}
+
+struct FileSystemCacheEntry {
+    size: usize,
+    last_access: u64,
+}
+
+/// The result of [`FileSystemCache::collect_garbage`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GarbageCollectionReport {
+    /// Number of removed directories of outdated module versions
+    pub removed_version_dirs: usize,
+    /// Number of removed modules of the latest version that do not have a Wasm blob anymore
+    pub removed_modules: usize,
+    /// The sum of the sizes of all removed files in bytes
+    pub freed_bytes: usize,
+}
+
+/// The module directories of the compatible previous versions of a [`FileSystemCache`].
+#[derive(Debug, Clone)]
+pub struct PreviousVersions {
+    paths: Vec<PathBuf>,
+    wasmer_module_version: u32,
+    gas_cost_fingerprint: u32,
+}
+
+impl PreviousVersions {
+    /// Loads the module of the given checksum from the first compatible previous version
+    /// that has it. Nothing is written to disk.
+    ///
+    /// Files with an invalid integrity envelope or that cannot be deserialized are ignored,
+    /// such that the module is recompiled.
+    pub fn load(&self, checksum: &Checksum, store: &Store) -> VmResult<Option<Module>> {
+        for path in &self.paths {
+            let file_path = path.join(checksum.to_hex());
+            let data = match fs::read(&file_path) {
+                Ok(data) => data,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => {
+                    return Err(VmError::cache_err(format!(
+                        "Error opening module file: {}",
+                        err
+                    )))
+                }
+            };
+            let serialized = match open_envelope(
+                &data,
+                self.wasmer_module_version,
+                self.gas_cost_fingerprint,
+                checksum,
+            ) {
+                Ok(serialized) => serialized,
+                Err(_) => continue,
+            };
+
+            // SAFETY: The module directories are only writable by this process, so the files
+            // were written by this cache or imported by a caller of the unsafe
+            // `Cache::import_snapshot_with_modules`, who vouches for them. The integrity
+            // envelope only rules out accidental corruption, not deliberate manipulation.
+            if let Ok(module) = unsafe { deserialize_module(store, serialized) } {
+                return Ok(Some(module));
+            }
+        }
+        Ok(None)
+    }
 }
 
 impl FileSystemCache {
     /// Construct a new `FileSystemCache` around the specified directory.
     /// The contents of the cache are stored in sub-versioned directories.
     ///
-    /// # Safety
+    /// If `max_size` is set, the least recently used modules are removed whenever the modules
+    /// of the latest version exceed this size on disk.
+    ///
+    /// Every module is stored in an integrity envelope which is verified before the module is
+    /// deserialized. This detects corrupted or swapped files. It does not protect against an
+    /// attacker with write access to the directory, who could create a valid envelope around
+    /// a malicious module.
     ///
-    /// This method is unsafe because there's no way to ensure the artifacts
-    /// stored in this cache haven't been corrupted or tampered with.
-    pub unsafe fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+    /// All stored modules must be compiled with the given gas cost table. Modules of other tables
+    /// are kept in separate directories and never loaded.
+    pub fn new(
+        path: impl Into<PathBuf>,
+        max_size: Option<Size>,
+        gas_costs: &GasCostTable,
+    ) -> io::Result<Self> {
         let wasmer_module_version = current_wasmer_module_version();
+        let gas_cost_fingerprint = gas_costs.fingerprint();
 
         let path: PathBuf = path.into();
         if path.exists() {
             let metadata = path.metadata()?;
             if metadata.is_dir() {
                 if !metadata.permissions().readonly() {
-                    Ok(Self {
+                    let mut cache = Self {
                         base_path: path,
                         wasmer_module_version,
-                    })
+                        gas_cost_fingerprint,
+                        compatible_versions: COMPATIBLE_SERIALIZATION_VERSIONS,
+                        max_size: max_size.map(|size| size.0),
+                        entries: HashMap::new(),
+                        access_counter: 0,
+                        evictions: 0,
+                        migrations: 0,
+                    };
+                    cache.scan_entries()?;
+                    Ok(cache)
                 } else {
                     // This directory is readonly.
                     Err(io::Error::new(
@@ -92,34 +232,119 @@ impl FileSystemCache {
             Ok(Self {
                 base_path: path,
                 wasmer_module_version,
+                gas_cost_fingerprint,
+                compatible_versions: COMPATIBLE_SERIALIZATION_VERSIONS,
+                max_size: max_size.map(|size| size.0),
+                entries: HashMap::new(),
+                access_counter: 0,
+                evictions: 0,
+                migrations: 0,
             })
         }
     }
 
     /// Loads a serialized module from the file system and returns a module (i.e. artifact + store),
     /// along with the size of the serialized module.
-    pub fn load(&self, checksum: &Checksum, store: &Store) -> VmResult<Option<Module>> {
+    ///
+    /// If the integrity envelope of the module file does not match, the file is deleted
+    /// and this is treated like a cache miss.
+    ///
+    /// If the module does not exist in the latest version, the directories of compatible
+    /// previous versions are checked. Modules found there are migrated to the latest version.
+    pub fn load(&mut self, checksum: &Checksum, store: &Store) -> VmResult<Option<Module>> {
         let filename = checksum.to_hex();
         let file_path = self.latest_modules_path().join(filename);
 
-        let result = unsafe { Module::deserialize_from_file(store, &file_path) };
-        match result {
-            Ok(module) => Ok(Some(module)),
-            Err(DeserializeError::Io(err)) => match err.kind() {
-                io::ErrorKind::NotFound => Ok(None),
-                _ => Err(VmError::cache_err(format!(
-                    "Error opening module file: {}",
-                    err
-                ))),
-            },
-            Err(err) => Err(VmError::cache_err(format!(
-                "Error deserializing module: {}",
-                err
-            ))),
+        let data = match fs::read(&file_path) {
+            Ok(data) => data,
+            Err(err) => {
+                return match err.kind() {
+                    io::ErrorKind::NotFound => self.load_from_previous_versions(checksum, store),
+                    _ => Err(VmError::cache_err(format!(
+                        "Error opening module file: {}",
+                        err
+                    ))),
+                }
+            }
+        };
+
+        let serialized = match open_envelope(
+            &data,
+            self.wasmer_module_version,
+            self.gas_cost_fingerprint,
+            checksum,
+        ) {
+            Ok(serialized) => serialized,
+            Err(_) => {
+                self.remove(checksum)?;
+                return Ok(None);
+            }
+        };
+
+        // SAFETY: The module directory is only writable by this process, so the file was
+        // written by this cache or imported by a caller of the unsafe
+        // `Cache::import_snapshot_with_modules`, who vouches for it. The integrity envelope
+        // only rules out accidental corruption, not deliberate manipulation.
+        let module = unsafe { deserialize_module(store, serialized) }?;
+        self.touch(checksum, &file_path);
+        Ok(Some(module))
+    }
+
+    /// Looks up a module in the directories of compatible previous versions and stores it
+    /// in the latest version if found. The old file is left in place.
+    fn load_from_previous_versions(
+        &mut self,
+        checksum: &Checksum,
+        store: &Store,
+    ) -> VmResult<Option<Module>> {
+        let module = self.previous_versions().load(checksum, store)?;
+        if let Some(module) = &module {
+            self.store_migrated(checksum, module)?;
         }
+        Ok(module)
+    }
+
+    /// Returns a reader for the modules of compatible previous versions, which can be used
+    /// without access to this cache. Modules loaded from it should be stored via
+    /// [`FileSystemCache::store_migrated`].
+    pub fn previous_versions(&self) -> PreviousVersions {
+        PreviousVersions {
+            paths: self
+                .compatible_versions
+                .iter()
+                .map(|serialization_version| self.modules_path(serialization_version))
+                .collect(),
+            wasmer_module_version: self.wasmer_module_version,
+            gas_cost_fingerprint: self.gas_cost_fingerprint,
+        }
+    }
+
+    /// Stores a module that was loaded from a previous version and counts the migration.
+    pub fn store_migrated(&mut self, checksum: &Checksum, module: &Module) -> VmResult<()> {
+        self.store(checksum, module)?;
+        self.migrations += 1;
+        Ok(())
+    }
+
+    /// Returns the given checksums that do not have a module of the latest version,
+    /// sorted by their hex representation.
+    ///
+    /// Those modules need to be migrated (see [`FileSystemCache::previous_versions`]) or
+    /// recompiled before they can be used from this cache.
+    pub fn missing_modules(&self, existing: &HashSet<Checksum>) -> Vec<Checksum> {
+        let mut missing: Vec<Checksum> = existing
+            .iter()
+            .filter(|checksum| !self.entries.contains_key(checksum))
+            .copied()
+            .collect();
+        missing.sort_by_key(|checksum| checksum.to_hex());
+        missing
     }
 
     /// Stores a serialized module to the file system. Returns the size of the serialized module.
+    ///
+    /// If this exceeds the maximum size of the cache, the least recently used other
+    /// modules are removed.
     pub fn store(&mut self, checksum: &Checksum, module: &Module) -> VmResult<()> {
         let modules_dir = self.latest_modules_path();
         fs::create_dir_all(&modules_dir)
@@ -127,29 +352,386 @@ impl FileSystemCache {
         let filename = checksum.to_hex();
         let path = modules_dir.join(filename);
 
-        catch_unwind(AssertUnwindSafe(|| {
+        let serialized = catch_unwind(AssertUnwindSafe(|| {
             module
-                .serialize_to_file(&path)
-                .map_err(|e| VmError::cache_err(format!("Error writing module to disk: {e}")))
+                .serialize()
+                .map_err(|e| VmError::cache_err(format!("Error serializing module: {e}")))
         }))
-        .map_err(|_| VmError::cache_err("Could not write module to disk"))??;
+        .map_err(|_| VmError::cache_err("Could not serialize module"))??;
+        let data = seal_envelope(
+            &serialized,
+            self.wasmer_module_version,
+            self.gas_cost_fingerprint,
+            checksum,
+        );
+        self.write_module_file(checksum, &path, &data)
+    }
+
+    /// Returns the Wasmer module version of the modules this cache reads and writes.
+    pub fn wasmer_module_version(&self) -> u32 {
+        self.wasmer_module_version
+    }
+
+    /// Reads a module file including its integrity envelope, e.g. for copying it to another
+    /// machine. Returns `None` if the module does not exist or the envelope is invalid.
+    pub fn read_raw(&self, checksum: &Checksum) -> VmResult<Option<Vec<u8>>> {
+        let file_path = self.latest_modules_path().join(checksum.to_hex());
+        match fs::read(file_path) {
+            Ok(data) => {
+                let valid = open_envelope(
+                    &data,
+                    self.wasmer_module_version,
+                    self.gas_cost_fingerprint,
+                    checksum,
+                )
+                .is_ok();
+                Ok(if valid { Some(data) } else { None })
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(VmError::cache_err(format!(
+                "Error opening module file: {}",
+                err
+            ))),
+        }
+    }
+
+    /// Stores a module file that was obtained via [`FileSystemCache::read_raw`].
+    ///
+    /// Returns false and writes nothing if the module was created for another Wasmer module
+    /// version or gas cost table. Fails if the integrity envelope is broken or does not belong
+    /// to the checksum.
+    pub fn write_raw(&mut self, checksum: &Checksum, data: &[u8]) -> VmResult<bool> {
+        match open_envelope(
+            data,
+            self.wasmer_module_version,
+            self.gas_cost_fingerprint,
+            checksum,
+        ) {
+            Ok(_) => {}
+            Err(EnvelopeMismatch::Incompatible) => return Ok(false),
+            Err(EnvelopeMismatch::Invalid) => {
+                return Err(VmError::cache_err(format!(
+                    "Invalid integrity envelope of compiled module for {}",
+                    checksum
+                )))
+            }
+        }
+        let modules_dir = self.latest_modules_path();
+        fs::create_dir_all(&modules_dir)
+            .map_err(|e| VmError::cache_err(format!("Error creating directory: {}", e)))?;
+        let path = modules_dir.join(checksum.to_hex());
+        self.write_module_file(checksum, &path, data)?;
+        Ok(true)
+    }
+
+    fn write_module_file(&mut self, checksum: &Checksum, path: &Path, data: &[u8]) -> VmResult<()> {
+        // Write to a temporary file first such that no partially written modules are left
+        // behind when the process is interrupted
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|e| VmError::cache_err(format!("Error writing module to disk: {e}")))?;
+        self.touch(checksum, path);
+        self.evict_to_fit(checksum)
+    }
+
+    /// Removes a serialized module from the file system.
+    ///
+    /// Returns true if the file existed and false if the file did not exist.
+    pub fn remove(&mut self, checksum: &Checksum) -> VmResult<bool> {
+        let file_path = self.latest_modules_path().join(checksum.to_hex());
+        self.entries.remove(checksum);
+
+        if file_path.exists() {
+            fs::remove_file(file_path).map_err(|e| {
+                VmError::cache_err(format!("Error deleting module from disk: {}", e))
+            })?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Removes the directories of all module versions other than the latest one as well as
+    /// all files of the latest version that are not a module of one of the given checksums.
+    pub fn collect_garbage(
+        &mut self,
+        existing: &HashSet<Checksum>,
+    ) -> VmResult<GarbageCollectionReport> {
+        let mut report = GarbageCollectionReport::default();
+        let latest_modules_path = self.latest_modules_path();
+
+        for entry in read_dir_entries(&self.base_path)? {
+            let path = entry.path();
+            if path == latest_modules_path || !path.is_dir() {
+                continue;
+            }
+            report.freed_bytes += dir_size(&path);
+            fs::remove_dir_all(&path).map_err(|e| {
+                VmError::cache_err(format!("Error removing {}: {}", path.display(), e))
+            })?;
+            report.removed_version_dirs += 1;
+        }
+
+        if latest_modules_path.exists() {
+            for entry in read_dir_entries(&latest_modules_path)? {
+                let path = entry.path();
+                let checksum = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(|name| Checksum::from_hex(name).ok());
+                if matches!(checksum, Some(checksum) if existing.contains(&checksum)) {
+                    continue;
+                }
+                report.freed_bytes += dir_size(&path);
+                if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                }
+                .map_err(|e| {
+                    VmError::cache_err(format!("Error removing {}: {}", path.display(), e))
+                })?;
+                if let Some(checksum) = checksum {
+                    self.entries.remove(&checksum);
+                }
+                report.removed_modules += 1;
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Returns the number of modules of the latest version on disk.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns cumulative size of all modules of the latest version on disk in bytes.
+    pub fn size(&self) -> usize {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    /// Returns the number of modules that were removed to stay within the maximum size.
+    pub fn evictions(&self) -> usize {
+        self.evictions
+    }
+
+    /// Returns the number of modules that were migrated from a previous version
+    pub fn migrations(&self) -> usize {
+        self.migrations
+    }
+
+    /// Registers an access to the given module. This creates an entry if none exists yet.
+    fn touch(&mut self, checksum: &Checksum, path: &Path) {
+        self.access_counter += 1;
+        let size = path
+            .metadata()
+            .map(|metadata| metadata.len() as usize)
+            .unwrap_or_default();
+        self.entries.insert(
+            *checksum,
+            FileSystemCacheEntry {
+                size,
+                last_access: self.access_counter,
+            },
+        );
+    }
+
+    /// Removes least recently used modules until the cache fits into its maximum size.
+    /// The module with the given checksum is never removed.
+    fn evict_to_fit(&mut self, keep: &Checksum) -> VmResult<()> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(()),
+        };
+
+        let mut size = self.size();
+        while size > max_size {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(checksum, _)| *checksum != keep)
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(checksum, entry)| (*checksum, entry.size));
+            match oldest {
+                Some((checksum, entry_size)) => {
+                    self.remove(&checksum)?;
+                    self.evictions += 1;
+                    size -= entry_size;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates an entry for all modules of the latest version on disk. The access order
+    /// is initialized with the last access times of the files as reported by the operating
+    /// system, falling back to the modification time where this is not available.
+    fn scan_entries(&mut self) -> io::Result<()> {
+        let latest_modules_path = self.latest_modules_path();
+        if !latest_modules_path.exists() {
+            return Ok(());
+        }
+
+        let mut found = Vec::new();
+        for entry in fs::read_dir(latest_modules_path)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if !metadata.is_file() {
+                continue;
+            }
+            let checksum = match entry.file_name().to_str().map(Checksum::from_hex) {
+                Some(Ok(checksum)) => checksum,
+                _ => continue,
+            };
+            let accessed = metadata
+                .accessed()
+                .or_else(|_| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            found.push((accessed, checksum, metadata.len() as usize));
+        }
+
+        found.sort_by_key(|(accessed, _, _)| *accessed);
+        for (_, checksum, size) in found {
+            self.access_counter += 1;
+            self.entries.insert(
+                checksum,
+                FileSystemCacheEntry {
+                    size,
+                    last_access: self.access_counter,
+                },
+            );
+        }
         Ok(())
     }
 
     /// The path to the latest version of the modules.
     fn latest_modules_path(&self) -> PathBuf {
-        let version = format!(
+        self.modules_path(MODULE_SERIALIZATION_VERSION)
+    }
+
+    fn modules_path(&self, serialization_version: &str) -> PathBuf {
+        let mut version = format!(
             "{}-wasmer{}",
-            MODULE_SERIALIZATION_VERSION, self.wasmer_module_version
+            serialization_version, self.wasmer_module_version
         );
+        if self.gas_cost_fingerprint != 0 {
+            version.push_str(&format!("-gas{:08x}", self.gas_cost_fingerprint));
+        }
         self.base_path.join(version)
     }
 }
 
+/// Deserializes a module, copying it to an aligned buffer first if needed.
+///
+/// # Safety
+///
+/// This is unsafe since the serialized module is not validated. See [`Module::deserialize`].
+unsafe fn deserialize_module(store: &Store, serialized: &[u8]) -> VmResult<Module> {
+    // Memory from the allocator is usually aligned, but this is not guaranteed
+    let aligned_copy;
+    let serialized = if serialized.as_ptr() as usize % SERIALIZED_MODULE_ALIGN == 0 {
+        serialized
+    } else {
+        let mut buffer = vec![0u8; serialized.len() + SERIALIZED_MODULE_ALIGN - 1];
+        let offset = buffer.as_ptr().align_offset(SERIALIZED_MODULE_ALIGN);
+        buffer[offset..offset + serialized.len()].copy_from_slice(serialized);
+        aligned_copy = buffer;
+        &aligned_copy[offset..offset + serialized.len()]
+    };
+
+    Module::deserialize(store, serialized)
+        .map_err(|err| VmError::cache_err(format!("Error deserializing module: {}", err)))
+}
+
+/// Wraps a serialized module in an integrity envelope.
+fn seal_envelope(
+    serialized: &[u8],
+    wasmer_module_version: u32,
+    gas_cost_fingerprint: u32,
+    checksum: &Checksum,
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(ENVELOPE_HEADER_LEN + serialized.len());
+    out.extend_from_slice(ENVELOPE_MAGIC);
+    out.extend_from_slice(&wasmer_module_version.to_le_bytes());
+    out.extend_from_slice(&gas_cost_fingerprint.to_le_bytes());
+    out.extend_from_slice(checksum.as_slice());
+    out.extend_from_slice(&Sha256::digest(serialized));
+    out.extend_from_slice(serialized);
+    out
+}
+
+/// Why [`open_envelope`] rejected a module file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EnvelopeMismatch {
+    /// The module was created for another Wasmer module version or gas cost table
+    Incompatible,
+    /// The data is not an envelope, belongs to another checksum or was modified
+    Invalid,
+}
+
+/// Verifies an integrity envelope and returns the serialized module in it.
+fn open_envelope<'a>(
+    data: &'a [u8],
+    wasmer_module_version: u32,
+    gas_cost_fingerprint: u32,
+    checksum: &Checksum,
+) -> Result<&'a [u8], EnvelopeMismatch> {
+    if data.len() < ENVELOPE_HEADER_LEN {
+        return Err(EnvelopeMismatch::Invalid);
+    }
+    let (header, serialized) = data.split_at(ENVELOPE_HEADER_LEN);
+    let (magic, rest) = header.split_at(ENVELOPE_MAGIC.len());
+    let (version, rest) = rest.split_at(4);
+    let (fingerprint, rest) = rest.split_at(4);
+    let (stored_checksum, hash) = rest.split_at(32);
+
+    if magic != ENVELOPE_MAGIC
+        || stored_checksum != checksum.as_slice()
+        || hash != Sha256::digest(serialized).as_slice()
+    {
+        return Err(EnvelopeMismatch::Invalid);
+    }
+    if version != wasmer_module_version.to_le_bytes()
+        || fingerprint != gas_cost_fingerprint.to_le_bytes()
+    {
+        return Err(EnvelopeMismatch::Incompatible);
+    }
+    Ok(serialized)
+}
+
+fn read_dir_entries(path: &Path) -> VmResult<Vec<fs::DirEntry>> {
+    fs::read_dir(path)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(|e| VmError::cache_err(format!("Error reading {}: {}", path.display(), e)))
+}
+
+/// Returns the size of a file or the cumulative size of all files in a directory.
+/// Errors are ignored since this is only used for reporting.
+fn dir_size(path: &Path) -> usize {
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if metadata.is_dir() {
+        fs::read_dir(path)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok())
+                    .map(|entry| dir_size(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or_default()
+    } else {
+        metadata.len() as usize
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::size::Size;
     use crate::wasm_backend::{compile, make_runtime_store};
     use tempfile::TempDir;
     use wasmer::{imports, Instance as WasmerInstance};
@@ -169,7 +751,8 @@ mod tests {
     #[test]
     fn file_system_cache_run() {
         let tmp_dir = TempDir::new().unwrap();
-        let mut cache = unsafe { FileSystemCache::new(tmp_dir.path()).unwrap() };
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
 
         // Create module
         let wasm = wat::parse_str(SOME_WAT).unwrap();
@@ -205,7 +788,8 @@ mod tests {
     #[test]
     fn file_system_cache_store_uses_expected_path() {
         let tmp_dir = TempDir::new().unwrap();
-        let mut cache = unsafe { FileSystemCache::new(tmp_dir.path()).unwrap() };
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
 
         // Create module
         let wasm = wat::parse_str(SOME_WAT).unwrap();
@@ -216,10 +800,437 @@ mod tests {
         cache.store(&checksum, &module).unwrap();
 
         let file_path = format!(
-            "{}/v4-wasmer1/{}",
+            "{}/v6-wasmer1/{}",
             tmp_dir.path().to_string_lossy(),
             checksum
         );
         let _serialized_module = fs::read(file_path).unwrap();
     }
+
+    #[test]
+    fn file_system_cache_remove_works() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+
+        // Create module
+        let wasm = wat::parse_str(SOME_WAT).unwrap();
+        let checksum = Checksum::generate(&wasm);
+
+        // Store module
+        let module = compile(&wasm, None, &[]).unwrap();
+        cache.store(&checksum, &module).unwrap();
+
+        // It's there
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum, &store).unwrap().is_some());
+
+        // Remove module
+        let existed = cache.remove(&checksum).unwrap();
+        assert!(existed);
+
+        // it's gone now
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum, &store).unwrap().is_none());
+
+        // Removing again is a no-op
+        let existed = cache.remove(&checksum).unwrap();
+        assert!(!existed);
+    }
+
+    fn compile_add_wat(n: i32) -> (Checksum, Module) {
+        let wasm = wat::parse_str(format!(
+            r#"(module
+            (type $t0 (func (param i32) (result i32)))
+            (func $add (export "add") (type $t0) (param $p0 i32) (result i32)
+                get_local $p0
+                i32.const {}
+                i32.add))
+            "#,
+            n
+        ))
+        .unwrap();
+        (
+            Checksum::generate(&wasm),
+            compile(&wasm, None, &[]).unwrap(),
+        )
+    }
+
+    #[test]
+    fn file_system_cache_len_and_size_work() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.size(), 0);
+
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, module2) = compile_add_wat(2);
+        cache.store(&checksum1, &module1).unwrap();
+        cache.store(&checksum2, &module2).unwrap();
+        assert_eq!(cache.len(), 2);
+        let size = cache.size();
+        assert!(size > 0);
+
+        // a new instance finds the existing modules
+        let cache2 = FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+        assert_eq!(cache2.len(), 2);
+        assert_eq!(cache2.size(), size);
+
+        cache.remove(&checksum1).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert!(cache.size() < size);
+    }
+
+    #[test]
+    fn file_system_cache_evicts_least_recently_used() {
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, module2) = compile_add_wat(2);
+        let (checksum3, module3) = compile_add_wat(3);
+
+        // All modules are roughly of the same size. Get the size of one.
+        let module_size = {
+            let tmp_dir = TempDir::new().unwrap();
+            let mut cache =
+                FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+            cache.store(&checksum1, &module1).unwrap();
+            cache.size()
+        };
+
+        // Space for two modules
+        let tmp_dir = TempDir::new().unwrap();
+        let max_size = Size(module_size * 5 / 2);
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), Some(max_size), &GasCostTable::default()).unwrap();
+
+        cache.store(&checksum1, &module1).unwrap();
+        cache.store(&checksum2, &module2).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evictions(), 0);
+
+        // access 1, such that 2 is the least recently used module
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum1, &store).unwrap().is_some());
+
+        cache.store(&checksum3, &module3).unwrap();
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.evictions(), 1);
+        assert!(cache.size() <= max_size.0);
+
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum1, &store).unwrap().is_some());
+        assert!(cache.load(&checksum2, &store).unwrap().is_none());
+        assert!(cache.load(&checksum3, &store).unwrap().is_some());
+    }
+
+    #[test]
+    fn file_system_cache_never_evicts_the_stored_module() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), Some(Size(1)), &GasCostTable::default()).unwrap();
+
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, module2) = compile_add_wat(2);
+        cache.store(&checksum1, &module1).unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.store(&checksum2, &module2).unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.evictions(), 1);
+
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum2, &store).unwrap().is_some());
+    }
+
+    #[test]
+    fn file_system_cache_collect_garbage_works() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, module2) = compile_add_wat(2);
+        cache.store(&checksum1, &module1).unwrap();
+        cache.store(&checksum2, &module2).unwrap();
+
+        // Outdated versions and junk
+        fs::create_dir_all(tmp_dir.path().join("v3-wasmer1")).unwrap();
+        fs::write(tmp_dir.path().join("v3-wasmer1").join("abcd"), b"old").unwrap();
+        fs::create_dir_all(tmp_dir.path().join("v2")).unwrap();
+        fs::write(cache.latest_modules_path().join("junk"), b"junk").unwrap();
+
+        let existing = HashSet::from([checksum1]);
+        let report = cache.collect_garbage(&existing).unwrap();
+        assert_eq!(report.removed_version_dirs, 2);
+        assert_eq!(report.removed_modules, 2);
+        assert!(report.freed_bytes > 7);
+
+        assert!(!tmp_dir.path().join("v3-wasmer1").exists());
+        assert!(!tmp_dir.path().join("v2").exists());
+        assert!(!cache.latest_modules_path().join("junk").exists());
+        assert_eq!(cache.len(), 1);
+
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum1, &store).unwrap().is_some());
+        assert!(cache.load(&checksum2, &store).unwrap().is_none());
+
+        // Nothing left to do
+        let report = cache.collect_garbage(&existing).unwrap();
+        assert_eq!(report, GarbageCollectionReport::default());
+    }
+
+    #[test]
+    fn file_system_cache_detects_corrupted_modules() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+
+        let (checksum, module) = compile_add_wat(1);
+        cache.store(&checksum, &module).unwrap();
+        let file_path = cache.latest_modules_path().join(checksum.to_hex());
+
+        // Flip a bit in the serialized module
+        let mut data = fs::read(&file_path).unwrap();
+        let last = data.len() - 1;
+        data[last] ^= 0x01;
+        fs::write(&file_path, data).unwrap();
+
+        // Treated as a miss and the file is removed
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum, &store).unwrap().is_none());
+        assert!(!file_path.exists());
+        assert_eq!(cache.len(), 0);
+
+        // Truncated files are detected as well
+        cache.store(&checksum, &module).unwrap();
+        fs::write(&file_path, b"CWMODENV").unwrap();
+        assert!(cache.load(&checksum, &store).unwrap().is_none());
+        assert!(!file_path.exists());
+    }
+
+    #[test]
+    fn file_system_cache_detects_swapped_modules() {
+        let tmp_dir = TempDir::new().unwrap();
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, _) = compile_add_wat(2);
+        cache.store(&checksum1, &module1).unwrap();
+
+        // A valid module stored under the wrong checksum
+        let modules_path = cache.latest_modules_path();
+        fs::copy(
+            modules_path.join(checksum1.to_hex()),
+            modules_path.join(checksum2.to_hex()),
+        )
+        .unwrap();
+
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum2, &store).unwrap().is_none());
+        assert!(!modules_path.join(checksum2.to_hex()).exists());
+        assert!(cache.load(&checksum1, &store).unwrap().is_some());
+    }
+
+    #[test]
+    fn file_system_cache_read_raw_and_write_raw_work() {
+        let source_dir = TempDir::new().unwrap();
+        let mut source =
+            FileSystemCache::new(source_dir.path(), None, &GasCostTable::default()).unwrap();
+        let target_dir = TempDir::new().unwrap();
+        let mut target =
+            FileSystemCache::new(target_dir.path(), None, &GasCostTable::default()).unwrap();
+
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, _) = compile_add_wat(2);
+        assert_eq!(source.read_raw(&checksum1).unwrap(), None);
+        source.store(&checksum1, &module1).unwrap();
+        let data = source.read_raw(&checksum1).unwrap().unwrap();
+
+        // Does not match a different checksum
+        match target.write_raw(&checksum2, &data).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.starts_with("Invalid integrity envelope"))
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(target.len(), 0);
+
+        // Modules of other gas cost tables are skipped
+        let mut other_table =
+            FileSystemCache::new(target_dir.path(), None, &GasCostTable::v2()).unwrap();
+        assert!(!other_table.write_raw(&checksum1, &data).unwrap());
+        assert_eq!(other_table.len(), 0);
+
+        assert!(target.write_raw(&checksum1, &data).unwrap());
+        assert_eq!(target.len(), 1);
+        assert_eq!(target.size(), data.len());
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(target.load(&checksum1, &store).unwrap().is_some());
+    }
+
+    #[test]
+    fn file_system_cache_migrates_modules_from_compatible_versions() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, module2) = compile_add_wat(2);
+
+        // A module from v5 and one from the incompatible v3
+        let v5_path = tmp_dir.path().join("v5-wasmer1");
+        let v3_path = tmp_dir.path().join("v3-wasmer1");
+        fs::create_dir_all(&v5_path).unwrap();
+        fs::create_dir_all(&v3_path).unwrap();
+        let sealed = seal_envelope(
+            &module1.serialize().unwrap(),
+            current_wasmer_module_version(),
+            GasCostTable::v1().fingerprint(),
+            &checksum1,
+        );
+        fs::write(v5_path.join(checksum1.to_hex()), sealed).unwrap();
+        fs::write(
+            v3_path.join(checksum2.to_hex()),
+            module2.serialize().unwrap(),
+        )
+        .unwrap();
+
+        // No previous version is compatible at the moment, so we pretend v5 still was
+        let mut cache = FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::v1()).unwrap();
+        cache.compatible_versions = &["v5"];
+        assert_eq!(cache.len(), 0);
+        let existing = HashSet::from([checksum1, checksum2]);
+        let mut expected_missing = vec![checksum1, checksum2];
+        expected_missing.sort_by_key(|checksum| checksum.to_hex());
+        assert_eq!(cache.missing_modules(&existing), expected_missing);
+
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        let module = cache.load(&checksum1, &store).unwrap().unwrap();
+        let instance = WasmerInstance::new(&module, &imports! {}).unwrap();
+        set_remaining_points(&instance, TESTING_GAS_LIMIT);
+        let add = instance.exports.get_function("add").unwrap();
+        assert_eq!(add.call(&[42.into()]).unwrap()[0].unwrap_i32(), 43);
+
+        // Copied forward, the old file is kept
+        assert!(tmp_dir
+            .path()
+            .join("v6-wasmer1")
+            .join(checksum1.to_hex())
+            .exists());
+        assert!(v5_path.join(checksum1.to_hex()).exists());
+        assert_eq!(cache.migrations(), 1);
+        assert_eq!(cache.len(), 1);
+
+        // v3 is not compatible
+        assert!(cache.load(&checksum2, &store).unwrap().is_none());
+        assert_eq!(cache.migrations(), 1);
+        assert_eq!(cache.missing_modules(&existing), vec![checksum2]);
+    }
+
+    #[test]
+    fn file_system_cache_ignores_modules_of_previous_versions_without_valid_envelope() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (checksum1, module1) = compile_add_wat(1);
+        let (checksum2, _) = compile_add_wat(2);
+        let v5_path = tmp_dir.path().join("v5-wasmer1");
+        fs::create_dir_all(&v5_path).unwrap();
+        // A raw module without envelope is never deserialized
+        fs::write(
+            v5_path.join(checksum1.to_hex()),
+            module1.serialize().unwrap(),
+        )
+        .unwrap();
+        // An envelope of another checksum
+        let sealed = seal_envelope(
+            &module1.serialize().unwrap(),
+            current_wasmer_module_version(),
+            GasCostTable::default().fingerprint(),
+            &checksum1,
+        );
+        fs::write(v5_path.join(checksum2.to_hex()), sealed).unwrap();
+
+        let mut cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+        cache.compatible_versions = &["v5"];
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum1, &store).unwrap().is_none());
+        assert!(cache.load(&checksum2, &store).unwrap().is_none());
+        assert_eq!(cache.migrations(), 0);
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn file_system_cache_separates_gas_cost_tables() {
+        let tmp_dir = TempDir::new().unwrap();
+        let (checksum, module) = compile_add_wat(1);
+        let gas_costs = GasCostTable {
+            division: 1,
+            ..GasCostTable::v1()
+        };
+
+        let mut default_cache =
+            FileSystemCache::new(tmp_dir.path(), None, &GasCostTable::default()).unwrap();
+        default_cache.store(&checksum, &module).unwrap();
+        let data = default_cache.read_raw(&checksum).unwrap().unwrap();
+
+        let mut cache = FileSystemCache::new(tmp_dir.path(), None, &gas_costs).unwrap();
+        let store = make_runtime_store(TESTING_MEMORY_LIMIT);
+        assert!(cache.load(&checksum, &store).unwrap().is_none());
+        assert!(cache.read_raw(&checksum).unwrap().is_none());
+        // modules of other tables cannot be imported
+        assert!(!cache.write_raw(&checksum, &data).unwrap());
+
+        cache.store(&checksum, &module).unwrap();
+        let expected_dir = format!("v6-wasmer1-gas{:08x}", gas_costs.fingerprint());
+        assert!(tmp_dir
+            .path()
+            .join(expected_dir)
+            .join(checksum.to_hex())
+            .exists());
+        assert!(cache.load(&checksum, &store).unwrap().is_some());
+    }
+
+    #[test]
+    fn open_envelope_works() {
+        let checksum = Checksum::generate(b"wasm");
+        let sealed = seal_envelope(b"module", 7, 0, &checksum);
+        assert_eq!(sealed.len(), ENVELOPE_HEADER_LEN + 6);
+        assert_eq!(
+            open_envelope(&sealed, 7, 0, &checksum),
+            Ok(b"module".as_slice())
+        );
+
+        // wrong Wasmer module version
+        assert_eq!(
+            open_envelope(&sealed, 8, 0, &checksum),
+            Err(EnvelopeMismatch::Incompatible)
+        );
+        // wrong gas cost table
+        assert_eq!(
+            open_envelope(&sealed, 7, 42, &checksum),
+            Err(EnvelopeMismatch::Incompatible)
+        );
+        // wrong checksum
+        let other = Checksum::generate(b"other");
+        assert_eq!(
+            open_envelope(&sealed, 7, 0, &other),
+            Err(EnvelopeMismatch::Invalid)
+        );
+        // wrong magic
+        let mut broken = sealed.clone();
+        broken[0] = b'X';
+        assert_eq!(
+            open_envelope(&broken, 7, 0, &checksum),
+            Err(EnvelopeMismatch::Invalid)
+        );
+        // modified module
+        let mut broken = sealed.clone();
+        *broken.last_mut().unwrap() = b'X';
+        assert_eq!(
+            open_envelope(&broken, 7, 0, &checksum),
+            Err(EnvelopeMismatch::Invalid)
+        );
+        // too short
+        assert_eq!(
+            open_envelope(&sealed[..10], 7, 0, &checksum),
+            Err(EnvelopeMismatch::Invalid)
+        );
+    }
 }
@@ -1,5 +1,6 @@
 use clru::{CLruCache, CLruCacheConfig, WeightScale};
 use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
 use std::num::NonZeroUsize;
 use wasmer::Module;
 
@@ -15,6 +16,53 @@ use crate::{Checksum, Size, VmError, VmResult};
 // Which is a very small percentage (~0.03%) of our typical cache memory budget (2 GB).
 const MINIMUM_MODULE_SIZE: Size = Size::kibi(250);
 
+// Number of recorded accesses after which all access frequencies are halved, such that
+// contracts that were popular a long time ago do not stay in the cache forever.
+const FREQUENCY_SAMPLE_SIZE: u32 = 10_000;
+
+/// Defines which modules are kept when the memory cache is full.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryCacheEvictionPolicy {
+    /// Every new module is admitted and the least recently used modules are evicted.
+    Lru,
+    /// The least recently used modules are evicted, but a new module is only admitted if it
+    /// was requested more often than every module it would evict (TinyLFU admission).
+    /// This prevents one-off executions of rarely used contracts from pushing out hot ones.
+    TinyLfu,
+}
+
+/// Approximate access frequencies of recently requested modules.
+///
+/// The counters are halved every [`FREQUENCY_SAMPLE_SIZE`] accesses.
+#[derive(Debug, Default)]
+struct AccessFrequencies {
+    counts: HashMap<Checksum, u32>,
+    accesses: u32,
+}
+
+impl AccessFrequencies {
+    fn record(&mut self, checksum: &Checksum) {
+        let count = self.counts.entry(*checksum).or_default();
+        *count = count.saturating_add(1);
+        self.accesses += 1;
+        if self.accesses >= FREQUENCY_SAMPLE_SIZE {
+            self.age();
+        }
+    }
+
+    fn get(&self, checksum: &Checksum) -> u32 {
+        self.counts.get(checksum).copied().unwrap_or_default()
+    }
+
+    fn age(&mut self) {
+        self.counts.retain(|_, count| {
+            *count /= 2;
+            *count > 0
+        });
+        self.accesses /= 2;
+    }
+}
+
 #[derive(Debug)]
 struct SizeScale;
 
@@ -25,41 +73,69 @@ impl WeightScale<Checksum, SizedModule> for SizeScale {
     }
 }
 
+type Modules = CLruCache<Checksum, SizedModule, RandomState, SizeScale>;
+
 /// An in-memory module cache
 pub struct InMemoryCache {
-    modules: Option<CLruCache<Checksum, SizedModule, RandomState, SizeScale>>,
+    modules: Option<Modules>,
+    /// Only tracked for [`MemoryCacheEvictionPolicy::TinyLfu`]
+    frequencies: Option<AccessFrequencies>,
 }
 
 impl InMemoryCache {
     /// Creates a new cache with the given size (in bytes)
     /// and pre-allocated entries.
-    pub fn new(size: Size) -> Self {
-        let preallocated_entries = size.0 / MINIMUM_MODULE_SIZE.0;
-
+    pub fn new(size: Size, policy: MemoryCacheEvictionPolicy) -> Self {
         InMemoryCache {
-            modules: if size.0 > 0 {
-                Some(CLruCache::with_config(
-                    CLruCacheConfig::new(NonZeroUsize::new(size.0).unwrap())
-                        .with_memory(preallocated_entries)
-                        .with_scale(SizeScale),
-                ))
-            } else {
-                None
+            modules: make_modules(size),
+            frequencies: match policy {
+                MemoryCacheEvictionPolicy::Lru => None,
+                MemoryCacheEvictionPolicy::TinyLfu => Some(AccessFrequencies::default()),
             },
         }
     }
 
-    pub fn store(&mut self, checksum: &Checksum, module: Module, size: usize) -> VmResult<()> {
+    /// Changes the size (in bytes) of the cache. When shrinking, the least recently used
+    /// modules are evicted until the remaining ones fit. A size of 0 disables the cache.
+    pub fn resize(&mut self, size: Size) {
+        match (&mut self.modules, NonZeroUsize::new(size.0)) {
+            (Some(modules), Some(capacity)) => modules.resize(capacity),
+            _ => self.modules = make_modules(size),
+        }
+    }
+
+    /// Stores a module in the cache. Returns false if the module was not admitted by the
+    /// eviction policy or the cache is disabled.
+    pub fn store(&mut self, checksum: &Checksum, module: Module, size: usize) -> VmResult<bool> {
         if let Some(modules) = &mut self.modules {
+            if let Some(frequencies) = &self.frequencies {
+                if !admits(modules, frequencies, checksum, size) {
+                    return Ok(false);
+                }
+            }
             modules
                 .put_with_weight(*checksum, SizedModule { module, size })
                 .map_err(|e| VmError::cache_err(format!("{:?}", e)))?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Removes a module from the cache.
+    /// Not found modules are silently ignored.
+    pub fn remove(&mut self, checksum: &Checksum) -> VmResult<()> {
+        if let Some(modules) = &mut self.modules {
+            modules.pop(checksum);
         }
         Ok(())
     }
 
     /// Looks up a module in the cache and creates a new module
     pub fn load(&mut self, checksum: &Checksum) -> VmResult<Option<SizedModule>> {
+        if let Some(frequencies) = &mut self.frequencies {
+            frequencies.record(checksum);
+        }
         if let Some(modules) = &mut self.modules {
             match modules.get(checksum) {
                 Some(module) => Ok(Some(module.clone())),
@@ -70,6 +146,15 @@ impl InMemoryCache {
         }
     }
 
+    /// Returns true if and only if this cache has an entry identified by the given checksum.
+    /// This does not count as a use of the entry.
+    pub fn has(&self, checksum: &Checksum) -> bool {
+        self.modules
+            .as_ref()
+            .map(|modules| modules.peek(checksum).is_some())
+            .unwrap_or_default()
+    }
+
     /// Returns the number of elements in the cache.
     pub fn len(&self) -> usize {
         self.modules
@@ -90,6 +175,51 @@ impl InMemoryCache {
     }
 }
 
+fn make_modules(size: Size) -> Option<Modules> {
+    let preallocated_entries = size.0 / MINIMUM_MODULE_SIZE.0;
+    NonZeroUsize::new(size.0).map(|capacity| {
+        CLruCache::with_config(
+            CLruCacheConfig::new(capacity)
+                .with_memory(preallocated_entries)
+                .with_scale(SizeScale),
+        )
+    })
+}
+
+/// Returns true if the module should be stored, i.e. it is already in the cache, the cache has
+/// enough free space, or the module was requested more often than all modules that would
+/// be evicted for it.
+fn admits(
+    modules: &Modules,
+    frequencies: &AccessFrequencies,
+    checksum: &Checksum,
+    size: usize,
+) -> bool {
+    if modules.peek(checksum).is_some() {
+        return true;
+    }
+
+    let frequency = frequencies.get(checksum);
+    let mut len = modules.len();
+    let mut weight = modules.weight();
+    // Walk the eviction candidates from least to most recently used, using the same
+    // fill condition as `CLruCache::put_with_weight`
+    let mut candidates = modules.iter().rev();
+    while len + weight + size >= modules.capacity() {
+        match candidates.next() {
+            Some((victim, victim_module)) => {
+                if frequencies.get(victim) >= frequency {
+                    return false;
+                }
+                len -= 1;
+                weight -= victim_module.size;
+            }
+            None => break,
+        }
+    }
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -122,7 +252,7 @@ mod tests {
 
     #[test]
     fn in_memory_cache_run() {
-        let mut cache = InMemoryCache::new(Size::mebi(200));
+        let mut cache = InMemoryCache::new(Size::mebi(200), MemoryCacheEvictionPolicy::Lru);
 
         // Create module
         let wasm = wat::parse_str(
@@ -170,9 +300,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn remove_works() {
+        let mut cache = InMemoryCache::new(Size::mebi(200), MemoryCacheEvictionPolicy::Lru);
+
+        // Create module
+        let wasm = wat::parse_str(
+            r#"(module
+            (type $t0 (func (param i32) (result i32)))
+            (func $add_one (export "add_one") (type $t0) (param $p0 i32) (result i32)
+                get_local $p0
+                i32.const 1
+                i32.add)
+            )"#,
+        )
+        .unwrap();
+        let checksum = Checksum::generate(&wasm);
+
+        // Store module
+        cache
+            .store(&checksum, compile(&wasm, None, &[]).unwrap(), 900_000)
+            .unwrap();
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), 900_000);
+        assert!(cache.has(&checksum));
+
+        // Remove module
+        cache.remove(&checksum).unwrap();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.size(), 0);
+        assert!(cache.load(&checksum).unwrap().is_none());
+
+        // Removing again has no effect
+        cache.remove(&checksum).unwrap();
+        assert!(!cache.has(&checksum));
+        assert_eq!(cache.len(), 0);
+    }
+
     #[test]
     fn len_works() {
-        let mut cache = InMemoryCache::new(Size::mebi(2));
+        let mut cache = InMemoryCache::new(Size::mebi(2), MemoryCacheEvictionPolicy::Lru);
 
         // Create module
         let wasm1 = wat::parse_str(
@@ -232,7 +399,7 @@ mod tests {
 
     #[test]
     fn size_works() {
-        let mut cache = InMemoryCache::new(Size::mebi(2));
+        let mut cache = InMemoryCache::new(Size::mebi(2), MemoryCacheEvictionPolicy::Lru);
 
         // Create module
         let wasm1 = wat::parse_str(
@@ -289,4 +456,111 @@ mod tests {
             .unwrap();
         assert_eq!(cache.size(), 1_500_000);
     }
+
+    fn make_module() -> Module {
+        let wasm = wat::parse_str(r#"(module (func (export "noop")))"#).unwrap();
+        compile(&wasm, None, &[]).unwrap()
+    }
+
+    #[test]
+    fn resize_works() {
+        let mut cache = InMemoryCache::new(Size::mebi(2), MemoryCacheEvictionPolicy::Lru);
+        let module = make_module();
+        let checksum1 = Checksum::generate(b"one");
+        let checksum2 = Checksum::generate(b"two");
+
+        assert!(cache.store(&checksum1, module.clone(), 900_000).unwrap());
+        assert!(cache.store(&checksum2, module.clone(), 800_000).unwrap());
+        assert_eq!(cache.len(), 2);
+
+        // Growing keeps all entries
+        cache.resize(Size::mebi(4));
+        assert_eq!(cache.size(), 1_700_000);
+
+        // Shrinking evicts the least recently used module
+        cache.resize(Size::mebi(1));
+        assert_eq!(cache.len(), 1);
+        assert!(!cache.has(&checksum1));
+        assert!(cache.has(&checksum2));
+
+        // Size 0 disables the cache
+        cache.resize(Size(0));
+        assert_eq!(cache.len(), 0);
+        assert!(!cache.store(&checksum1, module.clone(), 900_000).unwrap());
+        assert_eq!(cache.len(), 0);
+
+        // And it can be enabled again
+        cache.resize(Size::mebi(2));
+        cache.store(&checksum1, module, 900_000).unwrap();
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn tiny_lfu_keeps_frequently_used_modules() {
+        let module = make_module();
+        let hot = Checksum::generate(b"hot");
+        let cold = Checksum::generate(b"cold");
+
+        for (policy, cold_admitted) in [
+            (MemoryCacheEvictionPolicy::Lru, true),
+            (MemoryCacheEvictionPolicy::TinyLfu, false),
+        ] {
+            let mut cache = InMemoryCache::new(Size::mebi(1), policy);
+            assert!(cache.load(&hot).unwrap().is_none());
+            cache.store(&hot, module.clone(), 900_000).unwrap();
+            for _ in 0..3 {
+                assert!(cache.load(&hot).unwrap().is_some());
+            }
+
+            // A one-off request for a cold module
+            assert!(cache.load(&cold).unwrap().is_none());
+            let admitted = cache.store(&cold, module.clone(), 900_000).unwrap();
+            assert_eq!(admitted, cold_admitted);
+            assert_eq!(cache.has(&cold), cold_admitted);
+            assert_eq!(cache.has(&hot), !cold_admitted);
+            assert_eq!(cache.len(), 1);
+        }
+    }
+
+    #[test]
+    fn tiny_lfu_admits_modules_that_become_hot() {
+        let mut cache = InMemoryCache::new(Size::mebi(1), MemoryCacheEvictionPolicy::TinyLfu);
+        let module = make_module();
+        let old = Checksum::generate(b"old");
+        let new = Checksum::generate(b"new");
+
+        cache.load(&old).unwrap();
+        cache.store(&old, module.clone(), 900_000).unwrap();
+
+        // Rejected as long as it is not requested more often than the cached module
+        cache.load(&new).unwrap();
+        assert!(!cache.store(&new, module.clone(), 900_000).unwrap());
+        assert!(cache.has(&old));
+        assert!(!cache.has(&new));
+
+        cache.load(&new).unwrap();
+        assert!(cache.store(&new, module, 900_000).unwrap());
+        assert!(!cache.has(&old));
+        assert!(cache.has(&new));
+    }
+
+    #[test]
+    fn access_frequencies_age() {
+        let mut frequencies = AccessFrequencies::default();
+        let hot = Checksum::generate(b"hot");
+        let cold = Checksum::generate(b"cold");
+
+        frequencies.record(&cold);
+        for _ in 0..(FREQUENCY_SAMPLE_SIZE - 2) {
+            frequencies.record(&hot);
+        }
+        assert_eq!(frequencies.get(&cold), 1);
+        assert_eq!(frequencies.get(&hot), FREQUENCY_SAMPLE_SIZE - 2);
+
+        // Reaching the sample size halves all counters
+        frequencies.record(&hot);
+        assert_eq!(frequencies.get(&cold), 0);
+        assert_eq!(frequencies.get(&hot), (FREQUENCY_SAMPLE_SIZE - 1) / 2);
+        assert_eq!(frequencies.counts.len(), 1);
+    }
 }
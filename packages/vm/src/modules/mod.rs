@@ -1,10 +1,14 @@
+mod analysis_cache;
 mod file_system_cache;
 mod in_memory_cache;
+mod pinned_manifest;
 mod pinned_memory_cache;
 mod sized_module;
 mod versioning;
 
-pub use file_system_cache::FileSystemCache;
-pub use in_memory_cache::InMemoryCache;
+pub use analysis_cache::AnalysisCache;
+pub use file_system_cache::{FileSystemCache, GarbageCollectionReport};
+pub use in_memory_cache::{InMemoryCache, MemoryCacheEvictionPolicy};
+pub use pinned_manifest::PinnedManifest;
 pub use pinned_memory_cache::PinnedMemoryCache;
 pub use versioning::current_wasmer_module_version;
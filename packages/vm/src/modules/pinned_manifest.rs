@@ -0,0 +1,170 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::checksum::Checksum;
+use crate::errors::{VmError, VmResult};
+
+/// A file on disk that keeps track of the checksums of all pinned contracts,
+/// such that the pinned memory cache can be rebuilt after a restart.
+///
+/// The file contains a JSON array of lowercase hex encoded checksums, sorted
+/// alphabetically in order to get a stable output.
+#[derive(Debug)]
+pub struct PinnedManifest {
+    path: PathBuf,
+    checksums: HashSet<Checksum>,
+}
+
+impl PinnedManifest {
+    /// Loads the manifest from the given file path.
+    /// If the file does not exist, an empty manifest is created that will be
+    /// written to this location on the first change.
+    pub fn load(path: impl Into<PathBuf>) -> VmResult<Self> {
+        let path: PathBuf = path.into();
+        let checksums = match fs::read(&path) {
+            Ok(data) => {
+                let entries: Vec<String> = serde_json::from_slice(&data).map_err(|e| {
+                    VmError::cache_err(format!("Error parsing pinned manifest: {}", e))
+                })?;
+                entries
+                    .iter()
+                    .map(|entry| Checksum::from_hex(entry))
+                    .collect::<VmResult<HashSet<Checksum>>>()?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => {
+                return Err(VmError::cache_err(format!(
+                    "Error reading pinned manifest: {}",
+                    err
+                )))
+            }
+        };
+        Ok(Self { path, checksums })
+    }
+
+    /// Returns all checksums in the manifest in an unspecified order.
+    pub fn checksums(&self) -> Vec<Checksum> {
+        self.checksums.iter().copied().collect()
+    }
+
+    /// Adds a checksum to the manifest and writes it to disk if it was not yet contained.
+    /// If writing fails, the checksum is not added.
+    pub fn insert(&mut self, checksum: &Checksum) -> VmResult<()> {
+        if self.checksums.insert(*checksum) {
+            if let Err(err) = self.save() {
+                self.checksums.remove(checksum);
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes a checksum from the manifest and writes it to disk if it was contained.
+    /// Not found checksums are silently ignored.
+    pub fn remove(&mut self, checksum: &Checksum) -> VmResult<()> {
+        if self.checksums.remove(checksum) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the manifest to disk.
+    ///
+    /// The data is written to a temporary file first, which is then moved to the
+    /// final location. That way a crash never leaves a half-written manifest behind.
+    fn save(&self) -> VmResult<()> {
+        let mut entries: Vec<String> = self.checksums.iter().map(|c| c.to_hex()).collect();
+        entries.sort();
+        let data = serde_json::to_vec(&entries)
+            .map_err(|e| VmError::cache_err(format!("Error serializing pinned manifest: {}", e)))?;
+
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .map_err(|e| VmError::cache_err(format!("Error writing pinned manifest: {}", e)))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| VmError::cache_err(format!("Error writing pinned manifest: {}", e)))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn load_works_for_non_existent_file() {
+        let tmp_dir = TempDir::new().unwrap();
+        let manifest = PinnedManifest::load(tmp_dir.path().join("pinned.json")).unwrap();
+        assert_eq!(manifest.checksums(), vec![]);
+        // nothing written yet
+        assert!(!tmp_dir.path().join("pinned.json").exists());
+    }
+
+    #[test]
+    fn insert_and_remove_are_persisted() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("pinned.json");
+        let checksum1 = Checksum::generate(b"one");
+        let checksum2 = Checksum::generate(b"two");
+
+        let mut manifest = PinnedManifest::load(&path).unwrap();
+        manifest.insert(&checksum1).unwrap();
+        manifest.insert(&checksum2).unwrap();
+        manifest.insert(&checksum2).unwrap();
+        assert_eq!(manifest.checksums().len(), 2);
+
+        let reloaded = PinnedManifest::load(&path).unwrap();
+        let checksums = reloaded.checksums();
+        assert_eq!(checksums.len(), 2);
+        assert!(checksums.contains(&checksum1));
+        assert!(checksums.contains(&checksum2));
+
+        manifest.remove(&checksum1).unwrap();
+        // removing a non-existent entry is a no-op
+        manifest.remove(&checksum1).unwrap();
+
+        let reloaded = PinnedManifest::load(&path).unwrap();
+        assert_eq!(reloaded.checksums(), vec![checksum2]);
+    }
+
+    #[test]
+    fn save_writes_sorted_hex_list() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("pinned.json");
+        let checksum1 = Checksum::generate(b"one");
+        let checksum2 = Checksum::generate(b"two");
+
+        let mut manifest = PinnedManifest::load(&path).unwrap();
+        manifest.insert(&checksum2).unwrap();
+        manifest.insert(&checksum1).unwrap();
+
+        let mut expected = vec![checksum1.to_hex(), checksum2.to_hex()];
+        expected.sort();
+        let data = fs::read(&path).unwrap();
+        let stored: Vec<String> = serde_json::from_slice(&data).unwrap();
+        assert_eq!(stored, expected);
+    }
+
+    #[test]
+    fn load_fails_for_invalid_content() {
+        let tmp_dir = TempDir::new().unwrap();
+        let path = tmp_dir.path().join("pinned.json");
+
+        fs::write(&path, b"[\"aabb\"]").unwrap();
+        match PinnedManifest::load(&path).unwrap_err() {
+            VmError::CacheErr { msg, .. } => assert_eq!(msg, "Checksum not of length 32"),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        fs::write(&path, b"{}").unwrap();
+        match PinnedManifest::load(&path).unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert!(msg.starts_with("Error parsing pinned manifest"))
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+}
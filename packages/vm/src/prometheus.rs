@@ -0,0 +1,338 @@
+//! Rendering of cache metrics in the Prometheus text exposition format
+//! (see https://prometheus.io/docs/instrumenting/exposition_formats/).
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::cache::{ContractMetrics, Metrics};
+use crate::checksum::Checksum;
+
+const PREFIX: &str = "cosmwasm_vm";
+
+enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        }
+    }
+}
+
+/// Accumulates metric families in the text exposition format
+struct Exposition {
+    out: String,
+}
+
+impl Exposition {
+    fn new() -> Self {
+        Exposition { out: String::new() }
+    }
+
+    /// Adds a metric family with the given samples. Each sample is a list of labels and a value.
+    fn family(
+        &mut self,
+        name: &str,
+        help: &str,
+        metric_type: MetricType,
+        samples: &[(Vec<(&str, String)>, String)],
+    ) {
+        // Writing to a String never fails
+        let _ = writeln!(self.out, "# HELP {}_{} {}", PREFIX, name, help);
+        let _ = writeln!(
+            self.out,
+            "# TYPE {}_{} {}",
+            PREFIX,
+            name,
+            metric_type.as_str()
+        );
+        for (labels, value) in samples {
+            let _ = write!(self.out, "{}_{}", PREFIX, name);
+            if !labels.is_empty() {
+                let rendered: Vec<String> = labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, value))
+                    .collect();
+                let _ = write!(self.out, "{{{}}}", rendered.join(","));
+            }
+            let _ = writeln!(self.out, " {}", value);
+        }
+    }
+}
+
+/// Renders global and per contract cache metrics in the Prometheus text exposition format.
+///
+/// Contracts are sorted by checksum in order to get a stable output.
+pub fn render_prometheus(
+    metrics: &Metrics,
+    per_contract: &HashMap<Checksum, ContractMetrics>,
+) -> String {
+    let mut exposition = Exposition::new();
+    let stats = &metrics.stats;
+
+    exposition.family(
+        "cache_hits_total",
+        "Number of modules served from a cache layer.",
+        MetricType::Counter,
+        &[
+            (
+                vec![("layer", "pinned_memory".to_string())],
+                stats.hits_pinned_memory_cache.to_string(),
+            ),
+            (
+                vec![("layer", "memory".to_string())],
+                stats.hits_memory_cache.to_string(),
+            ),
+            (
+                vec![("layer", "fs".to_string())],
+                stats.hits_fs_cache.to_string(),
+            ),
+        ],
+    );
+    exposition.family(
+        "cache_misses_total",
+        "Number of modules that had to be compiled from Wasm.",
+        MetricType::Counter,
+        &[(vec![], stats.misses.to_string())],
+    );
+    exposition.family(
+        "cache_elements",
+        "Number of modules in a cache layer.",
+        MetricType::Gauge,
+        &[
+            (
+                vec![("layer", "pinned_memory".to_string())],
+                metrics.elements_pinned_memory_cache.to_string(),
+            ),
+            (
+                vec![("layer", "memory".to_string())],
+                metrics.elements_memory_cache.to_string(),
+            ),
+            (
+                vec![("layer", "fs".to_string())],
+                metrics.elements_fs_cache.to_string(),
+            ),
+        ],
+    );
+    exposition.family(
+        "cache_size_bytes",
+        "Size of all modules in a cache layer.",
+        MetricType::Gauge,
+        &[
+            (
+                vec![("layer", "pinned_memory".to_string())],
+                metrics.size_pinned_memory_cache.to_string(),
+            ),
+            (
+                vec![("layer", "memory".to_string())],
+                metrics.size_memory_cache.to_string(),
+            ),
+            (
+                vec![("layer", "fs".to_string())],
+                metrics.size_fs_cache.to_string(),
+            ),
+        ],
+    );
+    exposition.family(
+        "cache_fs_evictions_total",
+        "Number of modules evicted from the file system cache.",
+        MetricType::Counter,
+        &[(vec![], metrics.evictions_fs_cache.to_string())],
+    );
+    exposition.family(
+        "cache_fs_garbage_collected_total",
+        "Number of modules and version directories removed by garbage collection.",
+        MetricType::Counter,
+        &[(vec![], metrics.garbage_collected_fs_cache.to_string())],
+    );
+    exposition.family(
+        "cache_fs_migrations_total",
+        "Number of modules migrated from a previous module version.",
+        MetricType::Counter,
+        &[(vec![], metrics.migrated_fs_cache.to_string())],
+    );
+    exposition.family(
+        "cache_pinned_restores_pending",
+        "Number of pinned contracts not yet restored into memory.",
+        MetricType::Gauge,
+        &[(vec![], metrics.pending_pinned_restores.to_string())],
+    );
+    exposition.family(
+        "cache_pinned_restores_failed_total",
+        "Number of pinned contracts that could not be restored.",
+        MetricType::Counter,
+        &[(vec![], metrics.failed_pinned_restores.to_string())],
+    );
+
+    let mut contracts: Vec<(String, &ContractMetrics)> = per_contract
+        .iter()
+        .map(|(checksum, metrics)| (checksum.to_hex(), metrics))
+        .collect();
+    contracts.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut hits = Vec::with_capacity(contracts.len() * 3);
+    for (checksum, metrics) in &contracts {
+        for (layer, value) in [
+            ("pinned_memory", metrics.hits_pinned_memory_cache),
+            ("memory", metrics.hits_memory_cache),
+            ("fs", metrics.hits_fs_cache),
+        ] {
+            hits.push((
+                vec![("checksum", checksum.clone()), ("layer", layer.to_string())],
+                value.to_string(),
+            ));
+        }
+    }
+    let per_checksum = |value: fn(&ContractMetrics) -> String| {
+        contracts
+            .iter()
+            .map(|(checksum, metrics)| (vec![("checksum", checksum.clone())], value(metrics)))
+            .collect::<Vec<_>>()
+    };
+
+    exposition.family(
+        "contract_cache_hits_total",
+        "Number of modules of a contract served from a cache layer.",
+        MetricType::Counter,
+        &hits,
+    );
+    exposition.family(
+        "contract_cache_misses_total",
+        "Number of times a contract had to be compiled from Wasm.",
+        MetricType::Counter,
+        &per_checksum(|m| m.misses.to_string()),
+    );
+    exposition.family(
+        "contract_compile_seconds_total",
+        "Time spent compiling a contract.",
+        MetricType::Counter,
+        &per_checksum(|m| m.compile_time.as_secs_f64().to_string()),
+    );
+    exposition.family(
+        "contract_load_seconds_total",
+        "Time spent loading a contract from the file system cache.",
+        MetricType::Counter,
+        &per_checksum(|m| m.load_time.as_secs_f64().to_string()),
+    );
+    exposition.family(
+        "contract_module_size_bytes",
+        "Size of the compiled module of a contract.",
+        MetricType::Gauge,
+        &per_checksum(|m| m.module_size.to_string()),
+    );
+
+    exposition.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::Stats;
+    use std::time::Duration;
+
+    fn make_metrics() -> Metrics {
+        Metrics {
+            stats: Stats {
+                hits_pinned_memory_cache: 1,
+                hits_memory_cache: 2,
+                hits_fs_cache: 3,
+                misses: 4,
+            },
+            elements_pinned_memory_cache: 5,
+            elements_memory_cache: 6,
+            size_pinned_memory_cache: 7,
+            size_memory_cache: 8,
+            elements_fs_cache: 9,
+            size_fs_cache: 10,
+            evictions_fs_cache: 11,
+            garbage_collected_fs_cache: 12,
+            migrated_fs_cache: 15,
+            pending_pinned_restores: 13,
+            failed_pinned_restores: 14,
+        }
+    }
+
+    #[test]
+    fn render_prometheus_works_without_contracts() {
+        let out = render_prometheus(&make_metrics(), &HashMap::new());
+        assert!(out.contains(
+            "# HELP cosmwasm_vm_cache_hits_total Number of modules served from a cache layer.\n\
+             # TYPE cosmwasm_vm_cache_hits_total counter\n\
+             cosmwasm_vm_cache_hits_total{layer=\"pinned_memory\"} 1\n\
+             cosmwasm_vm_cache_hits_total{layer=\"memory\"} 2\n\
+             cosmwasm_vm_cache_hits_total{layer=\"fs\"} 3\n"
+        ));
+        assert!(out.contains("cosmwasm_vm_cache_misses_total 4\n"));
+        assert!(out.contains("cosmwasm_vm_cache_elements{layer=\"fs\"} 9\n"));
+        assert!(out.contains("cosmwasm_vm_cache_size_bytes{layer=\"memory\"} 8\n"));
+        assert!(out.contains("cosmwasm_vm_cache_fs_evictions_total 11\n"));
+        assert!(out.contains("cosmwasm_vm_cache_fs_garbage_collected_total 12\n"));
+        assert!(out.contains("cosmwasm_vm_cache_fs_migrations_total 15\n"));
+        assert!(out.contains("cosmwasm_vm_cache_pinned_restores_pending 13\n"));
+        assert!(out.contains("cosmwasm_vm_cache_pinned_restores_failed_total 14\n"));
+        // families are rendered but have no samples
+        assert!(out.ends_with("# TYPE cosmwasm_vm_contract_module_size_bytes gauge\n"));
+        assert!(!out.contains("checksum="));
+    }
+
+    #[test]
+    fn render_prometheus_works_with_contracts() {
+        let checksum1 = Checksum::generate(b"one");
+        let checksum2 = Checksum::generate(b"two");
+        let mut per_contract = HashMap::new();
+        per_contract.insert(
+            checksum1,
+            ContractMetrics {
+                hits_pinned_memory_cache: 0,
+                hits_memory_cache: 3,
+                hits_fs_cache: 1,
+                misses: 2,
+                compile_time: Duration::from_millis(1500),
+                load_time: Duration::from_millis(250),
+                module_size: 4096,
+            },
+        );
+        per_contract.insert(checksum2, ContractMetrics::default());
+
+        let out = render_prometheus(&make_metrics(), &per_contract);
+        let hex1 = checksum1.to_hex();
+        let hex2 = checksum2.to_hex();
+        assert!(out.contains(&format!(
+            "cosmwasm_vm_contract_cache_hits_total{{checksum=\"{}\",layer=\"memory\"}} 3\n",
+            hex1
+        )));
+        assert!(out.contains(&format!(
+            "cosmwasm_vm_contract_cache_misses_total{{checksum=\"{}\"}} 2\n",
+            hex1
+        )));
+        assert!(out.contains(&format!(
+            "cosmwasm_vm_contract_compile_seconds_total{{checksum=\"{}\"}} 1.5\n",
+            hex1
+        )));
+        assert!(out.contains(&format!(
+            "cosmwasm_vm_contract_load_seconds_total{{checksum=\"{}\"}} 0.25\n",
+            hex1
+        )));
+        assert!(out.contains(&format!(
+            "cosmwasm_vm_contract_module_size_bytes{{checksum=\"{}\"}} 4096\n",
+            hex1
+        )));
+        assert!(out.contains(&format!(
+            "cosmwasm_vm_contract_module_size_bytes{{checksum=\"{}\"}} 0\n",
+            hex2
+        )));
+
+        // sorted by checksum
+        let pos1 = out
+            .find(&format!("misses_total{{checksum=\"{}\"}}", hex1))
+            .unwrap();
+        let pos2 = out
+            .find(&format!("misses_total{{checksum=\"{}\"}}", hex2))
+            .unwrap();
+        assert_eq!(pos1 < pos2, hex1 < hex2);
+    }
+}
@@ -0,0 +1,720 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+
+use once_cell::sync::Lazy;
+
+use cosmwasm_std::{Binary, ContractResult, SystemResult};
+#[cfg(feature = "iterator")]
+use cosmwasm_std::{Order, Record};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::{Backend, BackendApi, BackendError, BackendResult, GasInfo, Querier, Storage};
+
+/// A call into the backend, without the response
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendRequest {
+    StorageGet {
+        key: Binary,
+    },
+    #[cfg(feature = "iterator")]
+    StorageScan {
+        start: Option<Binary>,
+        end: Option<Binary>,
+        /// The FFI representation of [`Order`]
+        order: i32,
+    },
+    #[cfg(feature = "iterator")]
+    StorageNext {
+        iterator_id: u32,
+    },
+    StorageSet {
+        key: Binary,
+        value: Binary,
+    },
+    StorageRemove {
+        key: Binary,
+    },
+    QueryRaw {
+        request: Binary,
+        gas_limit: u64,
+    },
+    CanonicalAddress {
+        human: String,
+    },
+    HumanAddress {
+        canonical: Binary,
+    },
+}
+
+/// The successful response to a [`BackendRequest`]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendResponse {
+    Value(Option<Binary>),
+    #[cfg(feature = "iterator")]
+    IteratorId(u32),
+    #[cfg(feature = "iterator")]
+    Record(Option<(Binary, Binary)>),
+    /// The response to requests that do not return data, such as [`BackendRequest::StorageSet`]
+    Empty,
+    QueryResult(SystemResult<ContractResult<Binary>>),
+    CanonicalAddress(Binary),
+    HumanAddress(String),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RecordedCall {
+    pub request: BackendRequest,
+    pub result: Result<BackendResponse, BackendError>,
+    pub gas_info: GasInfo,
+}
+
+/// All calls into the backend made during a recording, in the order they were made
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BackendLog {
+    pub calls: Vec<RecordedCall>,
+}
+
+/// The state shared by the wrappers of one recording or replay.
+///
+/// Since [`BackendApi`] implementations must be `Copy`, the api wrappers cannot hold a reference
+/// to it. Instead they look it up by its id in a global registry, from which it is removed once
+/// the last reference is dropped.
+struct Shared<T> {
+    id: u64,
+    value: Mutex<T>,
+}
+
+type Registry = Mutex<HashMap<u64, Weak<dyn Any + Send + Sync>>>;
+
+static REGISTRY: Lazy<Registry> = Lazy::new(Default::default);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+impl<T: Send + 'static> Shared<T> {
+    fn new(value: T) -> Arc<Self> {
+        let shared = Arc::new(Shared {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            value: Mutex::new(value),
+        });
+        let entry: Arc<dyn Any + Send + Sync> = shared.clone();
+        REGISTRY
+            .lock()
+            .unwrap()
+            .insert(shared.id, Arc::downgrade(&entry));
+        shared
+    }
+
+    /// Returns the state with the given id, unless all references to it were dropped
+    fn lookup(id: u64) -> Option<Arc<Self>> {
+        let entry = REGISTRY.lock().unwrap().get(&id)?.upgrade()?;
+        entry.downcast().ok()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, T> {
+        self.value.lock().unwrap()
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap().remove(&self.id);
+    }
+}
+
+fn record<R, F>(
+    log: &Shared<Vec<RecordedCall>>,
+    request: BackendRequest,
+    (result, gas_info): BackendResult<R>,
+    to_response: F,
+) -> BackendResult<R>
+where
+    F: FnOnce(&R) -> BackendResponse,
+{
+    log.lock().push(RecordedCall {
+        request,
+        result: result.as_ref().map(to_response).map_err(Clone::clone),
+        gas_info,
+    });
+    (result, gas_info)
+}
+
+/// Records all calls into a backend. See [`RecordingBackend`].
+#[derive(Clone)]
+pub struct BackendRecorder {
+    log: Arc<Shared<Vec<RecordedCall>>>,
+}
+
+impl BackendRecorder {
+    pub fn new() -> Self {
+        BackendRecorder {
+            log: Shared::new(Vec::new()),
+        }
+    }
+
+    /// Wraps the api, storage and querier of the given backend such that they record into
+    /// this recorder.
+    pub fn wrap<A: BackendApi, S: Storage, Q: Querier>(
+        &self,
+        backend: Backend<A, S, Q>,
+    ) -> Backend<RecordingApi<A>, RecordingBackend<S>, RecordingBackend<Q>> {
+        Backend {
+            api: RecordingApi::new(backend.api, self),
+            storage: RecordingBackend::new(backend.storage, self),
+            querier: RecordingBackend::new(backend.querier, self),
+        }
+    }
+
+    /// Returns the calls recorded so far and starts a new log
+    pub fn take_log(&self) -> BackendLog {
+        BackendLog {
+            calls: std::mem::take(&mut *self.log.lock()),
+        }
+    }
+}
+
+impl Default for BackendRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Wraps a [`Storage`] or [`Querier`] and records all calls and their responses into a
+/// [`BackendRecorder`]. The log can be used to run the same execution again with a
+/// [`ReplayBackend`], e.g. to debug a mainnet execution offline.
+#[derive(Clone)]
+pub struct RecordingBackend<T> {
+    inner: T,
+    log: Arc<Shared<Vec<RecordedCall>>>,
+}
+
+impl<T> RecordingBackend<T> {
+    pub fn new(inner: T, recorder: &BackendRecorder) -> Self {
+        RecordingBackend {
+            inner,
+            log: recorder.log.clone(),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record<R, F>(
+        &self,
+        request: BackendRequest,
+        result: BackendResult<R>,
+        to_response: F,
+    ) -> BackendResult<R>
+    where
+        F: FnOnce(&R) -> BackendResponse,
+    {
+        record(&self.log, request, result, to_response)
+    }
+}
+
+/// Wraps a [`BackendApi`] and records all calls and their responses into a [`BackendRecorder`]
+/// (see [`RecordingBackend`]). Calls made after the recorder and all other wrappers recording
+/// into it were dropped are not recorded.
+#[derive(Clone, Copy)]
+pub struct RecordingApi<A> {
+    inner: A,
+    log_id: u64,
+}
+
+impl<A> RecordingApi<A> {
+    pub fn new(inner: A, recorder: &BackendRecorder) -> Self {
+        RecordingApi {
+            inner,
+            log_id: recorder.log.id,
+        }
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn record<R, F>(
+        &self,
+        request: BackendRequest,
+        result: BackendResult<R>,
+        to_response: F,
+    ) -> BackendResult<R>
+    where
+        F: FnOnce(&R) -> BackendResponse,
+    {
+        match Shared::lookup(self.log_id) {
+            Some(log) => record(&log, request, result, to_response),
+            None => result,
+        }
+    }
+}
+
+impl<S: Storage> Storage for RecordingBackend<S> {
+    fn get(&self, key: &[u8]) -> BackendResult<Option<Vec<u8>>> {
+        let request = BackendRequest::StorageGet { key: key.into() };
+        self.record(request, self.inner.get(key), |value| {
+            BackendResponse::Value(value.as_deref().map(Binary::from))
+        })
+    }
+
+    #[cfg(feature = "iterator")]
+    fn scan(
+        &mut self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> BackendResult<u32> {
+        let request = BackendRequest::StorageScan {
+            start: start.map(Binary::from),
+            end: end.map(Binary::from),
+            order: order as i32,
+        };
+        let result = self.inner.scan(start, end, order);
+        self.record(request, result, |&id| BackendResponse::IteratorId(id))
+    }
+
+    #[cfg(feature = "iterator")]
+    fn next(&mut self, iterator_id: u32) -> BackendResult<Option<Record>> {
+        let request = BackendRequest::StorageNext { iterator_id };
+        let result = self.inner.next(iterator_id);
+        self.record(request, result, |record| {
+            BackendResponse::Record(
+                record
+                    .as_ref()
+                    .map(|(key, value)| (Binary::from(&key[..]), Binary::from(&value[..]))),
+            )
+        })
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> BackendResult<()> {
+        let request = BackendRequest::StorageSet {
+            key: key.into(),
+            value: value.into(),
+        };
+        let result = self.inner.set(key, value);
+        self.record(request, result, |_| BackendResponse::Empty)
+    }
+
+    fn remove(&mut self, key: &[u8]) -> BackendResult<()> {
+        let request = BackendRequest::StorageRemove { key: key.into() };
+        let result = self.inner.remove(key);
+        self.record(request, result, |_| BackendResponse::Empty)
+    }
+}
+
+impl<Q: Querier> Querier for RecordingBackend<Q> {
+    fn query_raw(
+        &self,
+        request: &[u8],
+        gas_limit: u64,
+    ) -> BackendResult<SystemResult<ContractResult<Binary>>> {
+        let recorded = BackendRequest::QueryRaw {
+            request: request.into(),
+            gas_limit,
+        };
+        self.record(
+            recorded,
+            self.inner.query_raw(request, gas_limit),
+            |result| BackendResponse::QueryResult(result.clone()),
+        )
+    }
+}
+
+impl<A: BackendApi> BackendApi for RecordingApi<A> {
+    fn canonical_address(&self, human: &str) -> BackendResult<Vec<u8>> {
+        let request = BackendRequest::CanonicalAddress {
+            human: human.to_string(),
+        };
+        self.record(request, self.inner.canonical_address(human), |canonical| {
+            BackendResponse::CanonicalAddress(canonical.as_slice().into())
+        })
+    }
+
+    fn human_address(&self, canonical: &[u8]) -> BackendResult<String> {
+        let request = BackendRequest::HumanAddress {
+            canonical: canonical.into(),
+        };
+        self.record(request, self.inner.human_address(canonical), |human| {
+            BackendResponse::HumanAddress(human.clone())
+        })
+    }
+}
+
+#[derive(Debug)]
+struct ReplayState {
+    calls: Vec<RecordedCall>,
+    /// The index of the next call to replay
+    next: usize,
+    /// Once the execution diverged, all further calls fail with this error
+    mismatch: Option<BackendError>,
+}
+
+/// A backend that serves the responses of a [`BackendLog`] instead of executing the calls.
+///
+/// Every call must match the next call of the log. Otherwise it fails with a
+/// [`BackendError::ReplayMismatch`], which aborts contract execution. This can be used as
+/// storage and querier at the same time. [`ReplayBackend::backend`] creates a backend that
+/// also replays the calls of the api.
+#[derive(Clone)]
+pub struct ReplayBackend {
+    state: Arc<Shared<ReplayState>>,
+}
+
+impl ReplayBackend {
+    pub fn new(log: BackendLog) -> Self {
+        ReplayBackend {
+            state: Shared::new(ReplayState {
+                calls: log.calls,
+                next: 0,
+                mismatch: None,
+            }),
+        }
+    }
+
+    pub fn backend(&self) -> Backend<ReplayApi, Self, Self> {
+        Backend {
+            api: ReplayApi {
+                state_id: self.state.id,
+            },
+            storage: self.clone(),
+            querier: self.clone(),
+        }
+    }
+
+    /// Checks that the execution made exactly the calls of the log
+    pub fn finish(&self) -> Result<(), BackendError> {
+        let state = self.state.lock();
+        if let Some(mismatch) = &state.mismatch {
+            return Err(mismatch.clone());
+        }
+        match state.calls.get(state.next) {
+            Some(call) => Err(BackendError::replay_mismatch(
+                state.next,
+                format!("{:?}", call.request),
+                "end of execution",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn replay<R, F>(&self, request: BackendRequest, from_response: F) -> BackendResult<R>
+    where
+        F: FnOnce(BackendResponse) -> Option<R>,
+    {
+        self.state.lock().replay(request, from_response)
+    }
+}
+
+impl ReplayState {
+    fn replay<R, F>(&mut self, request: BackendRequest, from_response: F) -> BackendResult<R>
+    where
+        F: FnOnce(BackendResponse) -> Option<R>,
+    {
+        if let Some(mismatch) = &self.mismatch {
+            return (Err(mismatch.clone()), GasInfo::free());
+        }
+
+        let index = self.next;
+        let call = match self.calls.get(index) {
+            Some(call) if call.request == request => call.clone(),
+            Some(call) => {
+                let expected = format!("{:?}", call.request);
+                return self.diverge(index, expected, &request);
+            }
+            None => return self.diverge(index, "end of log".to_string(), &request),
+        };
+        self.next += 1;
+
+        let result = match call.result {
+            Ok(response) => from_response(response.clone()).ok_or_else(|| {
+                BackendError::unknown(format!(
+                    "Invalid response in replay log for call {}: {:?}",
+                    index, response
+                ))
+            }),
+            Err(err) => Err(err),
+        };
+        (result, call.gas_info)
+    }
+
+    fn diverge<R>(
+        &mut self,
+        index: usize,
+        expected: String,
+        actual: &BackendRequest,
+    ) -> BackendResult<R> {
+        let mismatch = BackendError::replay_mismatch(index, expected, format!("{:?}", actual));
+        self.mismatch = Some(mismatch.clone());
+        (Err(mismatch), GasInfo::free())
+    }
+}
+
+/// Replays the calls of the api for a [`ReplayBackend`]. Created by [`ReplayBackend::backend`].
+#[derive(Clone, Copy)]
+pub struct ReplayApi {
+    state_id: u64,
+}
+
+impl ReplayApi {
+    fn replay<R, F>(&self, request: BackendRequest, from_response: F) -> BackendResult<R>
+    where
+        F: FnOnce(BackendResponse) -> Option<R>,
+    {
+        match Shared::<ReplayState>::lookup(self.state_id) {
+            Some(state) => state.lock().replay(request, from_response),
+            None => (
+                Err(BackendError::unknown("The replay backend was dropped")),
+                GasInfo::free(),
+            ),
+        }
+    }
+}
+
+impl Storage for ReplayBackend {
+    fn get(&self, key: &[u8]) -> BackendResult<Option<Vec<u8>>> {
+        let request = BackendRequest::StorageGet { key: key.into() };
+        self.replay(request, |response| match response {
+            BackendResponse::Value(value) => Some(value.map(Binary::into)),
+            _ => None,
+        })
+    }
+
+    #[cfg(feature = "iterator")]
+    fn scan(
+        &mut self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> BackendResult<u32> {
+        let request = BackendRequest::StorageScan {
+            start: start.map(Binary::from),
+            end: end.map(Binary::from),
+            order: order as i32,
+        };
+        self.replay(request, |response| match response {
+            BackendResponse::IteratorId(id) => Some(id),
+            _ => None,
+        })
+    }
+
+    #[cfg(feature = "iterator")]
+    fn next(&mut self, iterator_id: u32) -> BackendResult<Option<Record>> {
+        let request = BackendRequest::StorageNext { iterator_id };
+        self.replay(request, |response| match response {
+            BackendResponse::Record(record) => {
+                Some(record.map(|(key, value)| (key.into(), value.into())))
+            }
+            _ => None,
+        })
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) -> BackendResult<()> {
+        let request = BackendRequest::StorageSet {
+            key: key.into(),
+            value: value.into(),
+        };
+        self.replay(request, |response| match response {
+            BackendResponse::Empty => Some(()),
+            _ => None,
+        })
+    }
+
+    fn remove(&mut self, key: &[u8]) -> BackendResult<()> {
+        let request = BackendRequest::StorageRemove { key: key.into() };
+        self.replay(request, |response| match response {
+            BackendResponse::Empty => Some(()),
+            _ => None,
+        })
+    }
+}
+
+impl Querier for ReplayBackend {
+    fn query_raw(
+        &self,
+        request: &[u8],
+        gas_limit: u64,
+    ) -> BackendResult<SystemResult<ContractResult<Binary>>> {
+        let request = BackendRequest::QueryRaw {
+            request: request.into(),
+            gas_limit,
+        };
+        self.replay(request, |response| match response {
+            BackendResponse::QueryResult(result) => Some(result),
+            _ => None,
+        })
+    }
+}
+
+impl BackendApi for ReplayApi {
+    fn canonical_address(&self, human: &str) -> BackendResult<Vec<u8>> {
+        let request = BackendRequest::CanonicalAddress {
+            human: human.to_string(),
+        };
+        self.replay(request, |response| match response {
+            BackendResponse::CanonicalAddress(canonical) => Some(canonical.into()),
+            _ => None,
+        })
+    }
+
+    fn human_address(&self, canonical: &[u8]) -> BackendResult<String> {
+        let request = BackendRequest::HumanAddress {
+            canonical: canonical.into(),
+        };
+        self.replay(request, |response| match response {
+            BackendResponse::HumanAddress(human) => Some(human),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::calls::{call_execute, call_instantiate};
+    use crate::errors::VmError;
+    use crate::testing::{mock_backend, mock_env, mock_info, mock_instance_options};
+    use crate::{GasReport, Instance};
+    use cosmwasm_std::{coins, Empty, Response};
+
+    static CONTRACT: &[u8] = include_bytes!("../testdata/hackatom.wasm");
+
+    const INSTANTIATE_MSG: &[u8] = br#"{"verifier": "verifies", "beneficiary": "benefits"}"#;
+
+    /// Instantiates hackatom and releases the funds
+    fn run<A: BackendApi + 'static, S: Storage + 'static, Q: Querier + 'static>(
+        backend: Backend<A, S, Q>,
+        instantiate_msg: &[u8],
+    ) -> (Result<(Response, Response), VmError>, GasReport) {
+        let (instance_options, memory_limit) = mock_instance_options();
+        let mut instance =
+            Instance::from_code(CONTRACT, backend, instance_options, memory_limit).unwrap();
+
+        let info = mock_info("creator", &coins(1000, "earth"));
+        let result =
+            call_instantiate::<_, _, _, Empty>(&mut instance, &mock_env(), &info, instantiate_msg)
+                .and_then(|instantiate| {
+                    let info = mock_info("verifies", &[]);
+                    let execute = call_execute::<_, _, _, Empty>(
+                        &mut instance,
+                        &mock_env(),
+                        &info,
+                        br#"{"release":{}}"#,
+                    )?;
+                    Ok((instantiate.unwrap(), execute.unwrap()))
+                });
+        (result, instance.create_gas_report())
+    }
+
+    #[test]
+    fn replay_reproduces_recorded_execution() {
+        let recorder = BackendRecorder::new();
+        let backend = recorder.wrap(mock_backend(&coins(1000, "earth")));
+        let (recorded_result, recorded_report) = run(backend, INSTANTIATE_MSG);
+        let log = recorder.take_log();
+        assert!(log
+            .calls
+            .iter()
+            .any(|call| matches!(call.request, BackendRequest::QueryRaw { .. })));
+        assert_eq!(recorder.take_log(), BackendLog::default());
+
+        // The log survives serialization, e.g. when attached to a bug report
+        let log: BackendLog = serde_json::from_slice(&serde_json::to_vec(&log).unwrap()).unwrap();
+
+        let replay = ReplayBackend::new(log);
+        let (replayed_result, replayed_report) = run(replay.backend(), INSTANTIATE_MSG);
+        replay.finish().unwrap();
+        assert_eq!(replayed_result.unwrap(), recorded_result.unwrap());
+        assert_eq!(replayed_report.limit, recorded_report.limit);
+        assert_eq!(replayed_report.remaining, recorded_report.remaining);
+        assert_eq!(
+            replayed_report.used_externally,
+            recorded_report.used_externally
+        );
+        assert_eq!(
+            replayed_report.used_internally,
+            recorded_report.used_internally
+        );
+    }
+
+    #[test]
+    fn replay_reports_mismatch() {
+        let recorder = BackendRecorder::new();
+        let backend = recorder.wrap(mock_backend(&coins(1000, "earth")));
+        run(backend, INSTANTIATE_MSG).0.unwrap();
+        let replay = ReplayBackend::new(recorder.take_log());
+
+        let other_msg = br#"{"verifier": "someone", "beneficiary": "benefits"}"#;
+        let err = run(replay.backend(), other_msg).0.unwrap_err();
+        let expected = BackendError::replay_mismatch(
+            0,
+            r#"CanonicalAddress { human: "verifies" }"#,
+            r#"CanonicalAddress { human: "someone" }"#,
+        );
+        match err {
+            VmError::RuntimeErr { msg, .. } => assert!(msg.contains(&expected.to_string())),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        // The structured error is available after the execution
+        assert_eq!(replay.finish().unwrap_err(), expected);
+    }
+
+    #[test]
+    fn replay_finish_reports_missing_calls() {
+        let recorder = BackendRecorder::new();
+        let mut storage = RecordingBackend::new(crate::testing::MockStorage::new(), &recorder);
+        storage.set(b"foo", b"bar").0.unwrap();
+        assert_eq!(storage.get(b"foo").0.unwrap(), Some(b"bar".to_vec()));
+
+        let mut replay = ReplayBackend::new(recorder.take_log());
+        replay.set(b"foo", b"bar").0.unwrap();
+        assert_eq!(
+            replay.finish().unwrap_err(),
+            BackendError::replay_mismatch(
+                1,
+                "StorageGet { key: Binary(666f6f) }",
+                "end of execution"
+            )
+        );
+        assert_eq!(replay.get(b"foo").0.unwrap(), Some(b"bar".to_vec()));
+        replay.finish().unwrap();
+
+        // Calls beyond the end of the log diverge
+        let (result, gas_info) = replay.remove(b"foo");
+        assert_eq!(
+            result.unwrap_err(),
+            BackendError::replay_mismatch(2, "end of log", "StorageRemove { key: Binary(666f6f) }")
+        );
+        assert_eq!(gas_info, GasInfo::free());
+    }
+
+    #[test]
+    fn shared_state_is_released() {
+        let recorder = BackendRecorder::new();
+        let backend = recorder.wrap(mock_backend(&coins(1000, "earth")));
+        let log_id = recorder.log.id;
+        drop(recorder);
+        // The storage and querier keep the log alive for the api
+        assert!(Shared::<Vec<RecordedCall>>::lookup(log_id).is_some());
+        backend.api.canonical_address("creator").0.unwrap();
+        assert_eq!(backend.storage.log.lock().len(), 1);
+        drop(backend);
+        assert!(Shared::<Vec<RecordedCall>>::lookup(log_id).is_none());
+        assert!(!REGISTRY.lock().unwrap().contains_key(&log_id));
+
+        let replay = ReplayBackend::new(BackendLog::default());
+        let backend = replay.backend();
+        let state_id = replay.state.id;
+        drop(replay);
+        drop(backend.storage);
+        drop(backend.querier);
+        assert!(!REGISTRY.lock().unwrap().contains_key(&state_id));
+        let (result, _) = backend.api.canonical_address("creator");
+        assert_eq!(
+            result.unwrap_err(),
+            BackendError::unknown("The replay backend was dropped")
+        );
+    }
+}
@@ -0,0 +1,287 @@
+//! A simple streaming archive format for exporting and importing the contents of a cache.
+//!
+//! A snapshot starts with [`SNAPSHOT_MAGIC`], followed by any number of records and an
+//! end marker. Every record starts with a one byte tag:
+//!
+//! - `0x01` Wasm blob: checksum (32 bytes), length (u64, little endian), uncompressed Wasm
+//! - `0x02` compiled module: checksum (32 bytes), Wasmer module version (u32, little endian),
+//!   length (u64, little endian), module file including its integrity envelope
+//! - `0x00` end of snapshot
+//!
+//! The end marker allows detecting truncated snapshots.
+
+use std::io::{Read, Write};
+
+use crate::checksum::Checksum;
+use crate::errors::{VmError, VmResult};
+
+const SNAPSHOT_MAGIC: &[u8; 8] = b"CWSNAPV1";
+
+/// The maximum length of a Wasm blob in a snapshot. This is not taken from the current
+/// [`crate::WasmLimits`], since the contracts may have been stored under other limits.
+/// It matches the maximum size of a decompressed Wasm blob in the state directory.
+const MAX_WASM_LEN: u64 = 64 * 1024 * 1024;
+/// The maximum length of a compiled module file in a snapshot
+const MAX_MODULE_LEN: u64 = 512 * 1024 * 1024;
+
+const TAG_END: u8 = 0x00;
+const TAG_WASM: u8 = 0x01;
+const TAG_MODULE: u8 = 0x02;
+
+/// The result of [`crate::Cache::export_snapshot`], [`crate::Cache::import_snapshot`] and
+/// [`crate::Cache::import_snapshot_with_modules`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotReport {
+    /// Number of exported or imported Wasm blobs
+    pub wasm_blobs: usize,
+    /// Number of exported or imported compiled modules
+    pub modules: usize,
+    /// Number of compiled modules that were not imported, either because they were created
+    /// for a different Wasmer module version or gas cost table or because the import does not
+    /// accept compiled modules
+    pub skipped_modules: usize,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SnapshotEntry {
+    Wasm {
+        checksum: Checksum,
+        wasm: Vec<u8>,
+    },
+    Module {
+        checksum: Checksum,
+        wasmer_module_version: u32,
+        data: Vec<u8>,
+    },
+}
+
+pub struct SnapshotWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> SnapshotWriter<W> {
+    pub fn new(mut writer: W) -> VmResult<Self> {
+        writer.write_all(SNAPSHOT_MAGIC).map_err(write_err)?;
+        Ok(SnapshotWriter { writer })
+    }
+
+    pub fn write_wasm(&mut self, checksum: &Checksum, wasm: &[u8]) -> VmResult<()> {
+        self.write_parts(&[
+            &[TAG_WASM],
+            checksum.as_slice(),
+            &(wasm.len() as u64).to_le_bytes(),
+            wasm,
+        ])
+    }
+
+    pub fn write_module(
+        &mut self,
+        checksum: &Checksum,
+        wasmer_module_version: u32,
+        data: &[u8],
+    ) -> VmResult<()> {
+        self.write_parts(&[
+            &[TAG_MODULE],
+            checksum.as_slice(),
+            &wasmer_module_version.to_le_bytes(),
+            &(data.len() as u64).to_le_bytes(),
+            data,
+        ])
+    }
+
+    /// Writes the end marker and returns the underlying writer.
+    pub fn finish(mut self) -> VmResult<W> {
+        self.write_parts(&[&[TAG_END]])?;
+        self.writer.flush().map_err(write_err)?;
+        Ok(self.writer)
+    }
+
+    fn write_parts(&mut self, parts: &[&[u8]]) -> VmResult<()> {
+        for part in parts {
+            self.writer.write_all(part).map_err(write_err)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct SnapshotReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> SnapshotReader<R> {
+    pub fn new(mut reader: R) -> VmResult<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(read_err)?;
+        if &magic != SNAPSHOT_MAGIC {
+            return Err(VmError::cache_err("Invalid snapshot: unknown format"));
+        }
+        Ok(SnapshotReader { reader })
+    }
+
+    /// Returns the next entry or `None` at the end of the snapshot.
+    pub fn next_entry(&mut self) -> VmResult<Option<SnapshotEntry>> {
+        let [tag] = self.read_array::<1>()?;
+        match tag {
+            TAG_END => Ok(None),
+            TAG_WASM => {
+                let checksum = Checksum::from(self.read_array::<32>()?);
+                let wasm = self.read_data(MAX_WASM_LEN)?;
+                Ok(Some(SnapshotEntry::Wasm { checksum, wasm }))
+            }
+            TAG_MODULE => {
+                let checksum = Checksum::from(self.read_array::<32>()?);
+                let wasmer_module_version = u32::from_le_bytes(self.read_array::<4>()?);
+                let data = self.read_data(MAX_MODULE_LEN)?;
+                Ok(Some(SnapshotEntry::Module {
+                    checksum,
+                    wasmer_module_version,
+                    data,
+                }))
+            }
+            _ => Err(VmError::cache_err(format!(
+                "Invalid snapshot: unknown record tag {}",
+                tag
+            ))),
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> VmResult<[u8; N]> {
+        let mut out = [0u8; N];
+        self.reader.read_exact(&mut out).map_err(read_err)?;
+        Ok(out)
+    }
+
+    /// Reads a length prefixed byte string of at most `max_len` bytes.
+    /// The length is not trusted for allocation.
+    fn read_data(&mut self, max_len: u64) -> VmResult<Vec<u8>> {
+        let len = u64::from_le_bytes(self.read_array::<8>()?);
+        if len > max_len {
+            return Err(VmError::cache_err(format!(
+                "Invalid snapshot: record length {} exceeds limit of {} bytes",
+                len, max_len
+            )));
+        }
+        let mut data = Vec::new();
+        (&mut self.reader)
+            .take(len)
+            .read_to_end(&mut data)
+            .map_err(read_err)?;
+        if (data.len() as u64) != len {
+            return Err(VmError::cache_err(
+                "Invalid snapshot: unexpected end of data",
+            ));
+        }
+        Ok(data)
+    }
+}
+
+fn write_err(err: std::io::Error) -> VmError {
+    VmError::cache_err(format!("Error writing snapshot: {}", err))
+}
+
+fn read_err(err: std::io::Error) -> VmError {
+    match err.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            VmError::cache_err("Invalid snapshot: unexpected end of data")
+        }
+        _ => VmError::cache_err(format!("Error reading snapshot: {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_round_trip_works() {
+        let checksum1 = Checksum::generate(b"one");
+        let checksum2 = Checksum::generate(b"two");
+
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        writer.write_wasm(&checksum1, b"one").unwrap();
+        writer.write_module(&checksum1, 7, b"module one").unwrap();
+        writer.write_wasm(&checksum2, b"").unwrap();
+        let data = writer.finish().unwrap();
+        assert!(data.starts_with(SNAPSHOT_MAGIC));
+
+        let mut reader = SnapshotReader::new(data.as_slice()).unwrap();
+        assert_eq!(
+            reader.next_entry().unwrap(),
+            Some(SnapshotEntry::Wasm {
+                checksum: checksum1,
+                wasm: b"one".to_vec()
+            })
+        );
+        assert_eq!(
+            reader.next_entry().unwrap(),
+            Some(SnapshotEntry::Module {
+                checksum: checksum1,
+                wasmer_module_version: 7,
+                data: b"module one".to_vec()
+            })
+        );
+        assert_eq!(
+            reader.next_entry().unwrap(),
+            Some(SnapshotEntry::Wasm {
+                checksum: checksum2,
+                wasm: vec![]
+            })
+        );
+        assert_eq!(reader.next_entry().unwrap(), None);
+    }
+
+    #[test]
+    fn snapshot_reader_detects_invalid_data() {
+        match SnapshotReader::new(b"CWSNAPV2".as_slice()).err().unwrap() {
+            VmError::CacheErr { msg, .. } => assert_eq!(msg, "Invalid snapshot: unknown format"),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+
+        let mut writer = SnapshotWriter::new(Vec::new()).unwrap();
+        writer
+            .write_wasm(&Checksum::generate(b"one"), b"one")
+            .unwrap();
+        let data = writer.finish().unwrap();
+
+        // Truncated in the middle of a record and before the end marker
+        for len in [data.len() - 2, data.len() - 1] {
+            let mut reader = SnapshotReader::new(&data[..len]).unwrap();
+            match reader.next_entry().and_then(|_| reader.next_entry()) {
+                Err(VmError::CacheErr { msg, .. }) => {
+                    assert_eq!(msg, "Invalid snapshot: unexpected end of data")
+                }
+                other => panic!("Unexpected result: {:?}", other),
+            }
+        }
+
+        let mut invalid_tag = data;
+        invalid_tag[SNAPSHOT_MAGIC.len()] = 0x17;
+        let mut reader = SnapshotReader::new(invalid_tag.as_slice()).unwrap();
+        match reader.next_entry().unwrap_err() {
+            VmError::CacheErr { msg, .. } => {
+                assert_eq!(msg, "Invalid snapshot: unknown record tag 23")
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn snapshot_reader_limits_record_length() {
+        let mut data = SNAPSHOT_MAGIC.to_vec();
+        data.push(TAG_WASM);
+        data.extend_from_slice(&[0u8; 32]);
+        data.extend_from_slice(&(MAX_WASM_LEN + 1).to_le_bytes());
+        let mut reader = SnapshotReader::new(data.as_slice()).unwrap();
+        match reader.next_entry().unwrap_err() {
+            VmError::CacheErr { msg, .. } => assert_eq!(
+                msg,
+                format!(
+                    "Invalid snapshot: record length {} exceeds limit of {} bytes",
+                    MAX_WASM_LEN + 1,
+                    MAX_WASM_LEN
+                )
+            ),
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+}
@@ -1,5 +1,5 @@
-use parity_wasm::elements::{deserialize_buffer, Internal, Module};
-use std::collections::HashSet;
+use parity_wasm::elements::{deserialize_buffer, External, ImportEntry, Internal, Module};
+use std::collections::{BTreeSet, HashSet};
 
 use crate::errors::{VmError, VmResult};
 
@@ -12,6 +12,24 @@ pub const REQUIRED_IBC_EXPORTS: &[&str] = &[
     "ibc_packet_timeout",
 ];
 
+/// All entry points the VM knows how to call, including the required "instantiate".
+pub const ENTRY_POINTS: &[&str] = &[
+    "instantiate",
+    "execute",
+    "migrate",
+    "sudo",
+    "reply",
+    "query",
+    "ibc_channel_open",
+    "ibc_channel_connect",
+    "ibc_channel_close",
+    "ibc_packet_receive",
+    "ibc_packet_ack",
+    "ibc_packet_timeout",
+];
+
+pub const INTERFACE_VERSION_PREFIX: &str = "interface_version_";
+
 pub fn deserialize_wasm(wasm_code: &[u8]) -> VmResult<Module> {
     deserialize_buffer(wasm_code).map_err(|err| {
         VmError::static_validation_err(format!(
@@ -82,6 +100,147 @@ pub fn has_ibc_entry_points(module: &impl ExportInfo) -> bool {
         .all(|required| available_exports.contains(*required))
 }
 
+/// Returns the names of all exported functions that are known entry points ([`ENTRY_POINTS`]).
+pub fn entry_points(module: &impl ExportInfo) -> BTreeSet<String> {
+    let available_exports = module.exported_function_names(None);
+    ENTRY_POINTS
+        .iter()
+        .filter(|entry_point| available_exports.contains(**entry_point))
+        .map(|entry_point| entry_point.to_string())
+        .collect()
+}
+
+/// Returns the version from the interface_version_* marker export if there is exactly one
+/// such export with a numeric version.
+pub fn interface_version(module: &impl ExportInfo) -> Option<u32> {
+    let markers = module.exported_function_names(Some(INTERFACE_VERSION_PREFIX));
+    if markers.len() != 1 {
+        return None;
+    }
+    markers
+        .iter()
+        .next()
+        .and_then(|marker| marker[INTERFACE_VERSION_PREFIX.len()..].parse().ok())
+}
+
+/// Returns the full names ("module.field") of all imported functions.
+pub fn imported_function_names(module: &Module) -> BTreeSet<String> {
+    module
+        .import_section()
+        .map_or(BTreeSet::default(), |import_section| {
+            import_section
+                .entries()
+                .iter()
+                .filter(|entry| matches!(entry.external(), External::Function(_)))
+                .map(full_import_name)
+                .collect()
+        })
+}
+
+pub fn full_import_name(ie: &ImportEntry) -> String {
+    format!("{}.{}", ie.module(), ie.field())
+}
+
+/// Returns the number of functions defined in the module. Imported functions are not included.
+pub fn function_count(module: &Module) -> usize {
+    module
+        .function_section()
+        .map_or(0, |section| section.entries().len())
+}
+
+/// Returns the initial size of the first memory in pages or 0 if there is no memory.
+pub fn initial_memory_pages(module: &Module) -> u32 {
+    module
+        .memory_section()
+        .and_then(|section| section.entries().first())
+        .map_or(0, |memory| memory.limits().initial())
+}
+
+/// Returns the cumulative size of all data segments in bytes.
+pub fn data_segment_size(module: &Module) -> usize {
+    module.data_section().map_or(0, |section| {
+        section
+            .entries()
+            .iter()
+            .map(|segment| segment.value().len())
+            .sum()
+    })
+}
+
+/// The id of the code section in the binary format
+const CODE_SECTION_ID: u8 = 10;
+
+/// Returns the size in bytes of every function body in the code section, in the order of
+/// the function definitions.
+///
+/// parity-wasm does not retain the encoded sizes, so this walks the section headers of the
+/// binary. It is meant to be called after [`deserialize_wasm`] succeeded.
+pub fn function_body_sizes(wasm_code: &[u8]) -> VmResult<Vec<usize>> {
+    let mut reader = ByteReader {
+        data: wasm_code,
+        pos: 8, // magic and version
+    };
+    while reader.pos < wasm_code.len() {
+        let id = reader.byte()?;
+        let size = reader.varuint32()? as usize;
+        if id != CODE_SECTION_ID {
+            reader.skip(size)?;
+            continue;
+        }
+
+        let count = reader.varuint32()?;
+        let mut sizes = Vec::with_capacity(count.min(1024) as usize);
+        for _ in 0..count {
+            let body_size = reader.varuint32()? as usize;
+            reader.skip(body_size)?;
+            sizes.push(body_size);
+        }
+        return Ok(sizes);
+    }
+    Ok(vec![])
+}
+
+struct ByteReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl ByteReader<'_> {
+    fn byte(&mut self) -> VmResult<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| VmError::static_validation_err("Wasm bytecode is truncated"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn skip(&mut self, len: usize) -> VmResult<()> {
+        match self.pos.checked_add(len) {
+            Some(end) if end <= self.data.len() => {
+                self.pos = end;
+                Ok(())
+            }
+            _ => Err(VmError::static_validation_err("Wasm bytecode is truncated")),
+        }
+    }
+
+    /// Reads an unsigned LEB128 encoded integer of at most 32 bits
+    fn varuint32(&mut self) -> VmResult<u32> {
+        let mut result = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            result |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+        }
+        Err(VmError::static_validation_err(
+            "Wasm bytecode contains an invalid integer",
+        ))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -91,6 +250,39 @@ mod tests {
     static CONTRACT: &[u8] = include_bytes!("../testdata/hackatom.wasm");
     static CORRUPTED: &[u8] = include_bytes!("../testdata/corrupted.wasm");
 
+    #[test]
+    fn function_body_sizes_works() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (import "env" "foo" (func))
+                (memory 1)
+                (func nop)
+                (func (local i32) i32.const 1 drop)
+                (data (i32.const 0) "abc")
+            )"#,
+        )
+        .unwrap();
+        // body 1: 0 local declarations, nop, end
+        // body 2: 1 local declaration (count, type), i32.const 1, drop, end
+        assert_eq!(function_body_sizes(&wasm).unwrap(), vec![3, 7]);
+
+        let wasm = wat::parse_str("(module)").unwrap();
+        assert_eq!(function_body_sizes(&wasm).unwrap(), Vec::<usize>::new());
+
+        let sizes = function_body_sizes(CONTRACT).unwrap();
+        assert_eq!(
+            sizes.len(),
+            function_count(&deserialize_wasm(CONTRACT).unwrap())
+        );
+
+        match function_body_sizes(&CONTRACT[..CONTRACT.len() / 2]).unwrap_err() {
+            VmError::StaticValidationErr { msg, .. } => {
+                assert_eq!(msg, "Wasm bytecode is truncated")
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
     #[test]
     fn deserialize_wasm_works() {
         let module = deserialize_wasm(CONTRACT).unwrap();
@@ -306,4 +498,49 @@ mod tests {
         let module = deserialize_wasm(&wasm).unwrap();
         assert!(!has_ibc_entry_points(&module));
     }
+
+    #[test]
+    fn module_info_helpers_work() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "db_read" (func (param i32) (result i32)))
+            (import "env" "table" (table 1 funcref))
+            (memory 3)
+            (func (export "instantiate") nop)
+            (func (export "ibc_packet_ack") nop)
+            (func (export "helper") nop)
+            (func (export "interface_version_8") nop)
+            (data (i32.const 0) "abc")
+            (data (i32.const 16) "defgh")
+            )"#,
+        )
+        .unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+
+        assert_eq!(
+            entry_points(&module),
+            BTreeSet::from_iter(["instantiate".to_string(), "ibc_packet_ack".to_string()])
+        );
+        assert_eq!(interface_version(&module), Some(8));
+        assert_eq!(
+            imported_function_names(&module),
+            BTreeSet::from_iter(["env.db_read".to_string()])
+        );
+        assert_eq!(function_count(&module), 4);
+        assert_eq!(initial_memory_pages(&module), 3);
+        assert_eq!(data_segment_size(&module), 8);
+    }
+
+    #[test]
+    fn module_info_helpers_work_for_minimal_module() {
+        let wasm = wat::parse_str(r#"(module (func (export "interface_version_x") nop))"#).unwrap();
+        let module = deserialize_wasm(&wasm).unwrap();
+
+        assert_eq!(entry_points(&module), BTreeSet::new());
+        assert_eq!(interface_version(&module), None);
+        assert_eq!(imported_function_names(&module), BTreeSet::new());
+        assert_eq!(function_count(&module), 1);
+        assert_eq!(initial_memory_pages(&module), 0);
+        assert_eq!(data_segment_size(&module), 0);
+    }
 }
@@ -2,6 +2,7 @@ use wasmer::Module;
 
 use crate::backend::{Backend, Storage};
 use crate::compatibility::check_wasm;
+use crate::environment::GasConfig;
 use crate::instance::Instance;
 use crate::size::Size;
 use crate::wasm_backend::compile;
@@ -28,7 +29,7 @@ impl Contract {
         options: &MockInstanceOptions,
         memory_limit: Option<Size>,
     ) -> TestingResult<Self> {
-        check_wasm(wasm, &options.available_capabilities)?;
+        check_wasm(wasm, &options.available_capabilities, &options.wasm_limits)?;
         let module = compile(wasm, memory_limit, &[])?;
         let storage = MockStorage::new();
         let contract = Self { module, storage };
@@ -44,7 +45,7 @@ impl Contract {
         options: &MockInstanceOptions,
         memory_limit: Option<Size>,
     ) -> TestingResult<()> {
-        check_wasm(wasm, &options.available_capabilities)?;
+        check_wasm(wasm, &options.available_capabilities, &options.wasm_limits)?;
         let module = compile(wasm, memory_limit, &[])?;
         self.module = module;
         Ok(())
@@ -63,14 +64,18 @@ impl Contract {
             storage,
             querier,
         };
-        let instance = Instance::from_module(
+        let mut instance = Instance::from_module(
             &self.module,
             backend,
             options.gas_limit,
-            options.print_debug,
+            &options.wasm_limits,
+            &GasConfig::default(),
             None,
             None,
         )?;
+        if options.print_debug {
+            instance.set_debug_handler(|message, _info| println!("{}", message));
+        }
         Ok(instance)
     }
 
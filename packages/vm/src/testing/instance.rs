@@ -8,6 +8,7 @@ use crate::capabilities::capabilities_from_csv;
 use crate::compatibility::check_wasm;
 use crate::instance::{Instance, InstanceOptions};
 use crate::size::Size;
+use crate::wasm_limits::WasmLimits;
 use crate::{Backend, BackendApi, Querier, Storage};
 
 use super::mock::{MockApi, MOCK_CONTRACT_ADDR};
@@ -90,9 +91,12 @@ pub struct MockInstanceOptions<'a> {
     pub available_capabilities: HashSet<String>,
     /// Gas limit measured in [CosmWasm gas](https://github.com/CosmWasm/cosmwasm/blob/main/docs/GAS.md).
     pub gas_limit: u64,
+    /// Prints the debug messages of the contract to stdout using [`Instance::set_debug_handler`]
     pub print_debug: bool,
     /// Memory limit in bytes. Use a value that is divisible by the Wasm page size 65536, e.g. full MiBs.
     pub memory_limit: Option<Size>,
+    /// Limits used for the static validation of the Wasm and during execution
+    pub wasm_limits: WasmLimits,
 }
 
 impl MockInstanceOptions<'_> {
@@ -118,6 +122,7 @@ impl Default for MockInstanceOptions<'_> {
             gas_limit: DEFAULT_GAS_LIMIT,
             print_debug: DEFAULT_PRINT_DEBUG,
             memory_limit: DEFAULT_MEMORY_LIMIT,
+            wasm_limits: WasmLimits::default(),
         }
     }
 }
@@ -126,7 +131,7 @@ pub fn mock_instance_with_options(
     wasm: &[u8],
     options: MockInstanceOptions,
 ) -> Instance<MockApi, MockStorage, MockQuerier> {
-    check_wasm(wasm, &options.available_capabilities).unwrap();
+    check_wasm(wasm, &options.available_capabilities, &options.wasm_limits).unwrap();
     let contract_address = MOCK_CONTRACT_ADDR;
 
     // merge balances
@@ -151,11 +156,19 @@ pub fn mock_instance_with_options(
         querier: MockQuerier::new(&balances),
     };
     let memory_limit = options.memory_limit;
+    let print_debug = options.print_debug;
     let options = InstanceOptions {
         gas_limit: options.gas_limit,
-        print_debug: options.print_debug,
+        gas_config: None,
+        gas_profiling: false,
+        wasm_backtraces: false,
+        wasm_limits: Some(options.wasm_limits),
     };
-    Instance::from_code(wasm, backend, options, memory_limit).unwrap()
+    let mut instance = Instance::from_code(wasm, backend, options, memory_limit).unwrap();
+    if print_debug {
+        instance.set_debug_handler(|message, _info| println!("{}", message));
+    }
+    instance
 }
 
 /// Creates InstanceOptions for testing
@@ -163,7 +176,10 @@ pub fn mock_instance_options() -> (InstanceOptions, Option<Size>) {
     (
         InstanceOptions {
             gas_limit: DEFAULT_GAS_LIMIT,
-            print_debug: DEFAULT_PRINT_DEBUG,
+            gas_config: None,
+            gas_profiling: false,
+            wasm_backtraces: false,
+            wasm_limits: None,
         },
         DEFAULT_MEMORY_LIMIT,
     )
@@ -0,0 +1,202 @@
+use cosmwasm_std::Binary;
+use serde::{Deserialize, Serialize};
+
+use crate::backend::GasInfo;
+
+/// All host calls (imports) a contract made while tracing was enabled, in the order they were made.
+///
+/// See [`crate::Instance::set_host_call_tracing`] and [`crate::Instance::take_trace`].
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostCallTrace {
+    pub calls: Vec<HostCall>,
+}
+
+/// A single call of an import
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct HostCall {
+    /// The name of the import, e.g. `db_read`
+    pub name: String,
+    /// The data the contract passed in, in the order of the arguments (e.g. key and value
+    /// for `db_write`). Optional arguments that were not set are recorded as empty.
+    pub inputs: Vec<Binary>,
+    /// The data written to contract memory, e.g. the value returned by `db_read`
+    pub output: Option<Binary>,
+    /// The value returned to the contract. This is `None` for imports without a return value
+    /// and calls that failed.
+    pub return_value: Option<u64>,
+    /// The error that stopped contract execution, if any
+    pub error: Option<String>,
+    /// The sum of all gas charged during the call, including the VM side costs
+    pub gas_info: GasInfo,
+    pub gas_left_before: u64,
+    pub gas_left_after: u64,
+}
+
+/// Converts the value returned by an import to the representation in the trace
+pub trait TraceReturnValue {
+    fn trace_return_value(&self) -> Option<u64>;
+}
+
+impl TraceReturnValue for () {
+    fn trace_return_value(&self) -> Option<u64> {
+        None
+    }
+}
+
+impl TraceReturnValue for u32 {
+    fn trace_return_value(&self) -> Option<u64> {
+        Some((*self).into())
+    }
+}
+
+impl TraceReturnValue for u64 {
+    fn trace_return_value(&self) -> Option<u64> {
+        Some(*self)
+    }
+}
+
+/// Records the host calls of an instance
+#[derive(Debug, Default)]
+pub struct HostCallTracer {
+    calls: Vec<HostCall>,
+    /// Indices of the calls that did not return yet. Host calls can be nested when
+    /// the host calls back into the contract, e.g. to allocate memory.
+    open: Vec<usize>,
+}
+
+impl HostCallTracer {
+    pub fn begin(&mut self, name: &str, gas_left: u64) {
+        self.open.push(self.calls.len());
+        self.calls.push(HostCall {
+            name: name.to_string(),
+            inputs: Vec::new(),
+            output: None,
+            return_value: None,
+            error: None,
+            gas_info: GasInfo::free(),
+            gas_left_before: gas_left,
+            gas_left_after: gas_left,
+        });
+    }
+
+    pub fn end(&mut self, return_value: Option<u64>, error: Option<String>, gas_left: u64) {
+        if let Some(call) = self.open.pop().map(|index| &mut self.calls[index]) {
+            call.return_value = return_value;
+            call.error = error;
+            call.gas_left_after = gas_left;
+        }
+    }
+
+    pub fn add_input(&mut self, data: &[u8]) {
+        if let Some(call) = self.current() {
+            call.inputs.push(Binary::from(data));
+        }
+    }
+
+    pub fn set_output(&mut self, data: &[u8]) {
+        if let Some(call) = self.current() {
+            call.output = Some(Binary::from(data));
+        }
+    }
+
+    pub fn add_gas_info(&mut self, info: GasInfo) {
+        if let Some(call) = self.current() {
+            call.gas_info.cost = call.gas_info.cost.saturating_add(info.cost);
+            call.gas_info.externally_used = call
+                .gas_info
+                .externally_used
+                .saturating_add(info.externally_used);
+        }
+    }
+
+    /// Returns the calls recorded so far and starts a new trace
+    pub fn take(&mut self) -> HostCallTrace {
+        self.open.clear();
+        HostCallTrace {
+            calls: std::mem::take(&mut self.calls),
+        }
+    }
+
+    fn current(&mut self) -> Option<&mut HostCall> {
+        let index = *self.open.last()?;
+        self.calls.get_mut(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracer_records_nested_calls() {
+        let mut tracer = HostCallTracer::default();
+        tracer.begin("db_read", 1000);
+        tracer.add_input(b"key");
+        tracer.add_gas_info(GasInfo::new(10, 5));
+        tracer.begin("debug", 900);
+        tracer.add_input(b"allocating");
+        tracer.end(None, None, 890);
+        tracer.add_gas_info(GasInfo::with_cost(3));
+        tracer.set_output(b"value");
+        tracer.end(Some(42), None, 800);
+        tracer.begin("abort", 800);
+        tracer.end(None, Some("Aborted: oops".to_string()), 790);
+
+        let trace = tracer.take();
+        assert_eq!(
+            trace.calls,
+            vec![
+                HostCall {
+                    name: "db_read".to_string(),
+                    inputs: vec![Binary::from(b"key")],
+                    output: Some(Binary::from(b"value")),
+                    return_value: Some(42),
+                    error: None,
+                    gas_info: GasInfo::new(13, 5),
+                    gas_left_before: 1000,
+                    gas_left_after: 800,
+                },
+                HostCall {
+                    name: "debug".to_string(),
+                    inputs: vec![Binary::from(b"allocating")],
+                    output: None,
+                    return_value: None,
+                    error: None,
+                    gas_info: GasInfo::free(),
+                    gas_left_before: 900,
+                    gas_left_after: 890,
+                },
+                HostCall {
+                    name: "abort".to_string(),
+                    inputs: vec![],
+                    output: None,
+                    return_value: None,
+                    error: Some("Aborted: oops".to_string()),
+                    gas_info: GasInfo::free(),
+                    gas_left_before: 800,
+                    gas_left_after: 790,
+                },
+            ]
+        );
+
+        // Taking starts a new trace
+        assert_eq!(tracer.take(), HostCallTrace::default());
+    }
+
+    #[test]
+    fn trace_serializes_to_json() {
+        let mut tracer = HostCallTracer::default();
+        tracer.begin("db_write", 100);
+        tracer.add_input(b"foo");
+        tracer.add_input(b"bar");
+        tracer.end(None, None, 90);
+        let trace = tracer.take();
+
+        let json = serde_json::to_string(&trace).unwrap();
+        assert_eq!(
+            json,
+            r#"{"calls":[{"name":"db_write","inputs":["Zm9v","YmFy"],"output":null,"return_value":null,"error":null,"gas_info":{"cost":0,"externally_used":0},"gas_left_before":100,"gas_left_after":90}]}"#
+        );
+        assert_eq!(serde_json::from_str::<HostCallTrace>(&json).unwrap(), trace);
+    }
+}
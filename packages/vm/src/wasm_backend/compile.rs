@@ -5,18 +5,46 @@ use wasmer::{Module, ModuleMiddleware};
 use crate::errors::VmResult;
 use crate::size::Size;
 
+use super::gas_costs::GasCostTable;
+use super::gas_profiler::add_profiler_imports;
 use super::store::make_compile_time_store;
 
 /// Compiles a given Wasm bytecode into a module.
 /// The given memory limit (in bytes) is used when memories are created.
 /// If no memory limit is passed, the resulting compiled module should
 /// not be used for execution.
+///
+/// Operators are metered with the default [`GasCostTable`].
 pub fn compile(
     code: &[u8],
     memory_limit: Option<Size>,
     middlewares: &[Arc<dyn ModuleMiddleware>],
 ) -> VmResult<Module> {
-    let store = make_compile_time_store(memory_limit, middlewares);
+    compile_with_gas_costs(code, memory_limit, middlewares, &GasCostTable::default())
+}
+
+/// Like [`compile`] but meters operators with the costs from the given table.
+pub fn compile_with_gas_costs(
+    code: &[u8],
+    memory_limit: Option<Size>,
+    middlewares: &[Arc<dyn ModuleMiddleware>],
+    gas_costs: &GasCostTable,
+) -> VmResult<Module> {
+    let store = make_compile_time_store(code, memory_limit, middlewares, gas_costs, false)?;
+    let module = Module::new(&store, code)?;
+    Ok(module)
+}
+
+/// Like [`compile_with_gas_costs`] but instruments the code for gas profiling.
+/// The resulting module requires the profiler imports (see [`add_profiler_imports`]) and uses
+/// the same amount of gas as a module compiled without profiling.
+pub fn compile_with_gas_profiling(
+    code: &[u8],
+    memory_limit: Option<Size>,
+    gas_costs: &GasCostTable,
+) -> VmResult<Module> {
+    let code = add_profiler_imports(code)?;
+    let store = make_compile_time_store(&code, memory_limit, &[], gas_costs, true)?;
     let module = Module::new(&store, code)?;
     Ok(module)
 }
@@ -24,9 +52,47 @@ pub fn compile(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use wasmer::{imports, Instance};
+    use wasmer_middlewares::metering::{
+        get_remaining_points, set_remaining_points, MeteringPoints,
+    };
 
     static CONTRACT: &[u8] = include_bytes!("../../testdata/floaty.wasm");
 
+    #[test]
+    fn compile_with_gas_costs_works() {
+        let wasm = wat::parse_str(
+            r#"(module (func (export "f") (result i32) i32.const 6 i32.const 3 i32.div_u))"#,
+        )
+        .unwrap();
+        let expensive_division = GasCostTable {
+            division: 1_000_000,
+            ..GasCostTable::v1()
+        };
+
+        // 4 operators including the final `end`
+        for (gas_costs, expected) in [
+            (GasCostTable::v1(), 4 * 150_000),
+            (expensive_division, 3 * 150_000 + 1_000_000),
+        ] {
+            let module = compile_with_gas_costs(&wasm, None, &[], &gas_costs).unwrap();
+            let instance = Instance::new(&module, &imports! {}).unwrap();
+            set_remaining_points(&instance, 10_000_000);
+            instance
+                .exports
+                .get_function("f")
+                .unwrap()
+                .call(&[])
+                .unwrap();
+            match get_remaining_points(&instance) {
+                MeteringPoints::Remaining(remaining) => {
+                    assert_eq!(10_000_000 - remaining, expected)
+                }
+                MeteringPoints::Exhausted => panic!("Out of gas"),
+            }
+        }
+    }
+
     #[test]
     fn contract_with_floats_fails_check() {
         let err = compile(CONTRACT, None, &[]).unwrap_err();
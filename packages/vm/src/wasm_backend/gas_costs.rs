@@ -0,0 +1,327 @@
+use sha2::{Digest, Sha256};
+use wasmer::wasmparser::Operator;
+
+/// Gas costs of Wasm operators, charged by the metering middleware that is compiled
+/// into every module.
+///
+/// Operators are grouped into classes that are charged the same cost. Since the costs are
+/// part of the compiled module, all nodes of a chain must use the same table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GasCostTable {
+    /// Blocks, loops, branches and returns
+    pub control_flow: u64,
+    /// Function calls (direct and indirect)
+    pub calls: u64,
+    /// Access to local and global variables
+    pub locals: u64,
+    /// Memory loads and stores
+    pub memory_access: u64,
+    /// `memory.grow` (not including the cost of the newly allocated pages)
+    pub memory_grow: u64,
+    /// Integer constants, arithmetic, comparisons, bit operations and conversions
+    pub integer_arithmetic: u64,
+    /// Integer division and remainder
+    pub division: u64,
+    /// All other operators, e.g. `drop`, `select` and `memory.size`
+    pub other: u64,
+    /// Cost per page (64 KiB) requested by `memory.grow`, on top of the operator's cost.
+    /// This is not charged if set to 0.
+    pub memory_page: u64,
+}
+
+impl GasCostTable {
+    /// The flat fee of 150_000 gas for each operation used before the introduction of
+    /// cost tables. The target is 1 Teragas per millisecond (see GAS.md).
+    ///
+    /// In https://github.com/CosmWasm/cosmwasm/pull/1042 a profiler is developed to
+    /// identify runtime differences between different Wasm operation, but this is not yet
+    /// precise enough to derive insights from it.
+    pub const fn v1() -> Self {
+        const FLAT_COST: u64 = 150_000;
+        GasCostTable {
+            control_flow: FLAT_COST,
+            calls: FLAT_COST,
+            locals: FLAT_COST,
+            memory_access: FLAT_COST,
+            memory_grow: FLAT_COST,
+            integer_arithmetic: FLAT_COST,
+            division: FLAT_COST,
+            other: FLAT_COST,
+            memory_page: 0,
+        }
+    }
+
+    /// Like [`GasCostTable::v1`] with an additional charge for growing memory.
+    /// A page costs as much as initializing it with 64 bit stores in Wasm.
+    ///
+    /// This changes the gas consumption of contracts, so chains need to switch to it
+    /// in a coordinated upgrade.
+    pub const fn v2() -> Self {
+        GasCostTable {
+            memory_page: 8192 * 150_000,
+            ..Self::v1()
+        }
+    }
+
+    /// Returns the cost of the given operator.
+    pub fn cost(&self, operator: &Operator) -> u64 {
+        match operator {
+            Operator::Unreachable
+            | Operator::Nop
+            | Operator::Block { .. }
+            | Operator::Loop { .. }
+            | Operator::If { .. }
+            | Operator::Else
+            | Operator::End
+            | Operator::Br { .. }
+            | Operator::BrIf { .. }
+            | Operator::BrTable { .. }
+            | Operator::Return => self.control_flow,
+
+            Operator::Call { .. } | Operator::CallIndirect { .. } => self.calls,
+
+            Operator::LocalGet { .. }
+            | Operator::LocalSet { .. }
+            | Operator::LocalTee { .. }
+            | Operator::GlobalGet { .. }
+            | Operator::GlobalSet { .. } => self.locals,
+
+            Operator::I32Load { .. }
+            | Operator::I64Load { .. }
+            | Operator::I32Load8S { .. }
+            | Operator::I32Load8U { .. }
+            | Operator::I32Load16S { .. }
+            | Operator::I32Load16U { .. }
+            | Operator::I64Load8S { .. }
+            | Operator::I64Load8U { .. }
+            | Operator::I64Load16S { .. }
+            | Operator::I64Load16U { .. }
+            | Operator::I64Load32S { .. }
+            | Operator::I64Load32U { .. }
+            | Operator::I32Store { .. }
+            | Operator::I64Store { .. }
+            | Operator::I32Store8 { .. }
+            | Operator::I32Store16 { .. }
+            | Operator::I64Store8 { .. }
+            | Operator::I64Store16 { .. }
+            | Operator::I64Store32 { .. } => self.memory_access,
+
+            Operator::MemoryGrow { .. } => self.memory_grow,
+
+            Operator::I32DivS
+            | Operator::I32DivU
+            | Operator::I32RemS
+            | Operator::I32RemU
+            | Operator::I64DivS
+            | Operator::I64DivU
+            | Operator::I64RemS
+            | Operator::I64RemU => self.division,
+
+            Operator::I32Const { .. }
+            | Operator::I64Const { .. }
+            | Operator::I32Eqz
+            | Operator::I32Eq
+            | Operator::I32Ne
+            | Operator::I32LtS
+            | Operator::I32LtU
+            | Operator::I32GtS
+            | Operator::I32GtU
+            | Operator::I32LeS
+            | Operator::I32LeU
+            | Operator::I32GeS
+            | Operator::I32GeU
+            | Operator::I64Eqz
+            | Operator::I64Eq
+            | Operator::I64Ne
+            | Operator::I64LtS
+            | Operator::I64LtU
+            | Operator::I64GtS
+            | Operator::I64GtU
+            | Operator::I64LeS
+            | Operator::I64LeU
+            | Operator::I64GeS
+            | Operator::I64GeU
+            | Operator::I32Clz
+            | Operator::I32Ctz
+            | Operator::I32Popcnt
+            | Operator::I32Add
+            | Operator::I32Sub
+            | Operator::I32Mul
+            | Operator::I32And
+            | Operator::I32Or
+            | Operator::I32Xor
+            | Operator::I32Shl
+            | Operator::I32ShrS
+            | Operator::I32ShrU
+            | Operator::I32Rotl
+            | Operator::I32Rotr
+            | Operator::I64Clz
+            | Operator::I64Ctz
+            | Operator::I64Popcnt
+            | Operator::I64Add
+            | Operator::I64Sub
+            | Operator::I64Mul
+            | Operator::I64And
+            | Operator::I64Or
+            | Operator::I64Xor
+            | Operator::I64Shl
+            | Operator::I64ShrS
+            | Operator::I64ShrU
+            | Operator::I64Rotl
+            | Operator::I64Rotr
+            | Operator::I32WrapI64
+            | Operator::I64ExtendI32S
+            | Operator::I64ExtendI32U
+            | Operator::I32Extend8S
+            | Operator::I32Extend16S
+            | Operator::I64Extend8S
+            | Operator::I64Extend16S
+            | Operator::I64Extend32S => self.integer_arithmetic,
+
+            _ => self.other,
+        }
+    }
+
+    /// A short identifier of the table which is stored with compiled modules, such that
+    /// modules compiled with a different table are not used.
+    ///
+    /// This is 0 for [`GasCostTable::v1`] in order to keep modules compiled before the
+    /// introduction of cost tables valid. For all other tables it is derived from a hash
+    /// of the costs and never 0.
+    pub fn fingerprint(&self) -> u32 {
+        if *self == Self::v1() {
+            return 0;
+        }
+        let mut hasher = Sha256::new();
+        for cost in [
+            self.control_flow,
+            self.calls,
+            self.locals,
+            self.memory_access,
+            self.memory_grow,
+            self.integer_arithmetic,
+            self.division,
+            self.other,
+            self.memory_page,
+        ] {
+            hasher.update(cost.to_le_bytes());
+        }
+        let hash = hasher.finalize();
+        match u32::from_le_bytes([hash[0], hash[1], hash[2], hash[3]]) {
+            0 => 1,
+            fingerprint => fingerprint,
+        }
+    }
+}
+
+/// The default is [`GasCostTable::v1`] in order to keep the gas consumption of existing chains.
+/// Other tables need to be activated explicitly, e.g. through [`crate::CacheOptions::gas_cost_table`].
+impl Default for GasCostTable {
+    fn default() -> Self {
+        Self::v1()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmer::wasmparser::MemoryImmediate;
+
+    #[test]
+    fn v1_is_flat() {
+        let table = GasCostTable::v1();
+        let memarg = MemoryImmediate {
+            align: 2,
+            offset: 0,
+            memory: 0,
+        };
+        for operator in [
+            Operator::Nop,
+            Operator::Call { function_index: 3 },
+            Operator::LocalGet { local_index: 0 },
+            Operator::I32Load { memarg },
+            Operator::MemoryGrow {
+                mem: 0,
+                mem_byte: 0,
+            },
+            Operator::I64Add,
+            Operator::I64DivU,
+            Operator::Drop,
+        ] {
+            assert_eq!(table.cost(&operator), 150_000);
+        }
+        assert_eq!(GasCostTable::default(), table);
+    }
+
+    #[test]
+    fn v2_charges_memory_pages() {
+        let table = GasCostTable::v2();
+        assert_eq!(table.memory_page, 1_228_800_000);
+        assert_eq!(
+            GasCostTable {
+                memory_page: 0,
+                ..table
+            },
+            GasCostTable::v1()
+        );
+        assert_ne!(table.fingerprint(), 0);
+    }
+
+    #[test]
+    fn cost_uses_operator_classes() {
+        let table = GasCostTable {
+            control_flow: 1,
+            calls: 2,
+            locals: 3,
+            memory_access: 4,
+            memory_grow: 5,
+            integer_arithmetic: 6,
+            division: 7,
+            other: 8,
+            memory_page: 9,
+        };
+        let memarg = MemoryImmediate {
+            align: 0,
+            offset: 0,
+            memory: 0,
+        };
+        assert_eq!(table.cost(&Operator::BrIf { relative_depth: 0 }), 1);
+        assert_eq!(
+            table.cost(&Operator::CallIndirect {
+                index: 0,
+                table_index: 0
+            }),
+            2
+        );
+        assert_eq!(table.cost(&Operator::GlobalSet { global_index: 0 }), 3);
+        assert_eq!(table.cost(&Operator::I64Store8 { memarg }), 4);
+        assert_eq!(
+            table.cost(&Operator::MemoryGrow {
+                mem: 0,
+                mem_byte: 0
+            }),
+            5
+        );
+        assert_eq!(table.cost(&Operator::I32Const { value: 42 }), 6);
+        assert_eq!(table.cost(&Operator::I32RemS), 7);
+        assert_eq!(table.cost(&Operator::Select), 8);
+    }
+
+    #[test]
+    fn fingerprint_works() {
+        assert_eq!(GasCostTable::v1().fingerprint(), 0);
+
+        let table1 = GasCostTable {
+            division: 400_000,
+            ..GasCostTable::v1()
+        };
+        let table2 = GasCostTable {
+            memory_grow: 400_000,
+            ..GasCostTable::v1()
+        };
+        assert_ne!(table1.fingerprint(), 0);
+        assert_ne!(table2.fingerprint(), 0);
+        assert_ne!(table1.fingerprint(), table2.fingerprint());
+        assert_eq!(table1.fingerprint(), table1.fingerprint());
+    }
+}
@@ -0,0 +1,275 @@
+use std::sync::Mutex;
+
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use parity_wasm::elements::{
+    External, FunctionType, ImportEntry, ImportSection, Instruction, Internal, Section, Type,
+    TypeSection, ValueType,
+};
+use wasmer::wasmparser::Operator;
+use wasmer::{
+    FunctionMiddleware, LocalFunctionIndex, MiddlewareError, MiddlewareReaderState,
+    ModuleMiddleware,
+};
+use wasmer_types::{ImportIndex, ModuleInfo};
+
+use crate::errors::{VmError, VmResult};
+use crate::static_analysis::deserialize_wasm;
+
+/// The import module of the functions the instrumented code calls to report calls
+pub const PROFILER_IMPORT_MODULE: &str = "cosmwasm_profiler";
+/// Called with the index of a function whenever it is entered
+pub const PROFILER_ENTER: &str = "enter";
+/// Called with the index of the caller whenever a call returns
+pub const PROFILER_LEAVE: &str = "leave";
+/// Passed to `enter` right before an indirect call, whose callee is only known at runtime
+pub const PROFILER_INDIRECT_CALL: u32 = u32::MAX;
+
+/// The number of functions imported by [`add_profiler_imports`]. They come before all other
+/// functions, such that the index of every other function is shifted by this amount.
+pub const PROFILER_IMPORTS: u32 = 2;
+
+/// Adds the imports of the profiler as the first two functions of the module and adjusts all
+/// function indices accordingly. The function names of the name section are preserved.
+pub fn add_profiler_imports(wasm: &[u8]) -> VmResult<Vec<u8>> {
+    let module = deserialize_wasm(wasm)?;
+    // A broken name section is dropped since its indices cannot be adjusted
+    let mut module = module.parse_names().unwrap_or_else(|(_, mut module)| {
+        module.clear_custom_section("name");
+        module
+    });
+
+    let shift = |index: &mut u32| *index += PROFILER_IMPORTS;
+    if let Some(section) = module.code_section_mut() {
+        for body in section.bodies_mut() {
+            for instruction in body.code_mut().elements_mut() {
+                if let Instruction::Call(index) = instruction {
+                    shift(index);
+                }
+            }
+        }
+    }
+    if let Some(section) = module.export_section_mut() {
+        for entry in section.entries_mut() {
+            if let Internal::Function(index) = entry.internal_mut() {
+                shift(index);
+            }
+        }
+    }
+    if let Some(section) = module.elements_section_mut() {
+        for segment in section.entries_mut() {
+            segment.members_mut().iter_mut().for_each(shift);
+        }
+    }
+    if let Some(start) = module.start_section() {
+        module.set_start_section(start + PROFILER_IMPORTS);
+    }
+    if let Some(section) = module.names_section_mut() {
+        if let Some(functions) = section.functions_mut() {
+            let names = std::mem::take(functions.names_mut());
+            *functions.names_mut() = names
+                .into_iter()
+                .map(|(index, name)| (index + PROFILER_IMPORTS, name))
+                .collect();
+        }
+        if let Some(locals) = section.locals_mut() {
+            let names = std::mem::take(locals.local_names_mut());
+            *locals.local_names_mut() = names
+                .into_iter()
+                .map(|(index, names)| (index + PROFILER_IMPORTS, names))
+                .collect();
+        }
+    }
+
+    // Both imports have the type (i32) -> ()
+    let profiler_type = Type::Function(FunctionType::new(vec![ValueType::I32], vec![]));
+    let type_index = match module.type_section_mut() {
+        Some(section) => {
+            section.types_mut().push(profiler_type);
+            section.types().len() - 1
+        }
+        None => {
+            insert_section(&mut module, Section::Type(TypeSection::with_types(vec![])))?;
+            let section = module.type_section_mut().unwrap();
+            section.types_mut().push(profiler_type);
+            0
+        }
+    } as u32;
+    if module.import_section().is_none() {
+        insert_section(&mut module, Section::Import(ImportSection::default()))?;
+    }
+    let imports = module.import_section_mut().unwrap().entries_mut();
+    for (position, name) in [PROFILER_ENTER, PROFILER_LEAVE].into_iter().enumerate() {
+        imports.insert(
+            position,
+            ImportEntry::new(
+                PROFILER_IMPORT_MODULE.to_string(),
+                name.to_string(),
+                External::Function(type_index),
+            ),
+        );
+    }
+
+    parity_wasm::serialize(module)
+        .map_err(|err| VmError::compile_err(format!("Error adding profiler imports: {}", err)))
+}
+
+fn insert_section(module: &mut parity_wasm::elements::Module, section: Section) -> VmResult<()> {
+    module
+        .insert_section(section)
+        .map_err(|err| VmError::compile_err(format!("Error adding profiler imports: {}", err)))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ProfilerIndexes {
+    enter: u32,
+    leave: u32,
+    imported_functions: u32,
+}
+
+/// A middleware that reports all calls to the profiler imports added by [`add_profiler_imports`].
+///
+/// Every function calls `enter` with its own index when it starts. Since imported functions cannot
+/// be instrumented, `enter` is called before direct calls of imports instead. Before indirect
+/// calls, `enter` is called with [`PROFILER_INDIRECT_CALL`], which is replaced by the callee if it
+/// is not an import. This way every call adds exactly one frame. After every call, `leave` is
+/// called with the index of the caller, no matter how the callee returned.
+///
+/// This must be the last middleware, such that the additional calls are neither metered nor
+/// counted by the stack limiter. Gas consumption is the same as without profiling.
+#[derive(Debug, Default)]
+pub struct GasProfiler {
+    indexes: Mutex<Option<ProfilerIndexes>>,
+}
+
+impl GasProfiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl MemoryUsage for GasProfiler {
+    fn size_of_val(&self, _tracker: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+impl ModuleMiddleware for GasProfiler {
+    /// Generates a `FunctionMiddleware` for a given function.
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        let indexes = self
+            .indexes
+            .lock()
+            .unwrap()
+            .expect("GasProfiler::transform_module_info must be called first");
+        Box::new(FunctionGasProfiler {
+            function_index: indexes.imported_functions + local_function_index.as_u32(),
+            indexes,
+            entered: false,
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut indexes = self.indexes.lock().unwrap();
+        if indexes.is_some() {
+            panic!("GasProfiler::transform_module_info: Attempting to use a `GasProfiler` middleware from multiple modules.");
+        }
+
+        let import = |name: &str| {
+            module_info
+                .imports
+                .iter()
+                .find_map(|((module, field, _), index)| match index {
+                    ImportIndex::Function(index)
+                        if module == PROFILER_IMPORT_MODULE && field == name =>
+                    {
+                        Some(index.as_u32())
+                    }
+                    _ => None,
+                })
+                .expect("GasProfiler must be used with modules that contain the profiler imports")
+        };
+        *indexes = Some(ProfilerIndexes {
+            enter: import(PROFILER_ENTER),
+            leave: import(PROFILER_LEAVE),
+            imported_functions: module_info.num_imported_functions as u32,
+        });
+    }
+}
+
+#[derive(Debug)]
+struct FunctionGasProfiler {
+    function_index: u32,
+    indexes: ProfilerIndexes,
+    entered: bool,
+}
+
+impl FunctionMiddleware for FunctionGasProfiler {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let ProfilerIndexes {
+            enter,
+            leave,
+            imported_functions,
+        } = self.indexes;
+
+        if !self.entered {
+            self.entered = true;
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.function_index as i32,
+                },
+                Operator::Call {
+                    function_index: enter,
+                },
+            ]);
+        }
+
+        let is_call = match operator {
+            Operator::Call { function_index } => {
+                if function_index < imported_functions {
+                    // The arguments of the call stay on the stack
+                    state.extend(&[
+                        Operator::I32Const {
+                            value: function_index as i32,
+                        },
+                        Operator::Call {
+                            function_index: enter,
+                        },
+                    ]);
+                }
+                true
+            }
+            Operator::CallIndirect { .. } => {
+                // The arguments and the table index stay on the stack
+                state.extend(&[
+                    Operator::I32Const {
+                        value: PROFILER_INDIRECT_CALL as i32,
+                    },
+                    Operator::Call {
+                        function_index: enter,
+                    },
+                ]);
+                true
+            }
+            _ => false,
+        };
+        state.push_operator(operator);
+        if is_call {
+            state.extend(&[
+                Operator::I32Const {
+                    value: self.function_index as i32,
+                },
+                Operator::Call {
+                    function_index: leave,
+                },
+            ]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::Mutex;
+
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use wasmer::wasmparser::{Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType};
+use wasmer::{
+    ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, LocalFunctionIndex, MiddlewareError,
+    MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::ModuleInfo;
+
+/// Names of the globals exported by the Metering middleware
+const REMAINING_POINTS_EXPORT: &str = "wasmer_metering_remaining_points";
+const POINTS_EXHAUSTED_EXPORT: &str = "wasmer_metering_points_exhausted";
+
+#[derive(Debug, Clone, Copy)]
+struct GlobalIndexes {
+    /// The remaining points of the Metering middleware
+    remaining_points: u32,
+    /// The exhausted flag of the Metering middleware
+    points_exhausted: u32,
+    /// Scratch global holding the argument of `memory.grow`
+    delta: u32,
+    /// Scratch global holding the result of `memory.grow`
+    result: u32,
+    /// Scratch global holding the cost of the current `memory.grow`
+    cost: u32,
+}
+
+/// A middleware that charges gas per page allocated by `memory.grow`.
+///
+/// The cost is deducted from the points of the Metering middleware, which must come before this
+/// middleware in the chain. It is charged right after the memory was grown successfully, failed
+/// attempts are not charged. Running out of gas traps like it does for other operators.
+#[derive(Debug)]
+pub struct MemoryMetering {
+    cost_per_page: u64,
+    global_indexes: Mutex<Option<GlobalIndexes>>,
+}
+
+impl MemoryMetering {
+    pub fn new(cost_per_page: u64) -> Self {
+        Self {
+            // Ensures the cost of the maximum memory size (65536 pages) does not overflow
+            cost_per_page: cost_per_page.min(u64::MAX / 65536),
+            global_indexes: Mutex::new(None),
+        }
+    }
+}
+
+impl MemoryUsage for MemoryMetering {
+    fn size_of_val(&self, _tracker: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+impl ModuleMiddleware for MemoryMetering {
+    /// Generates a `FunctionMiddleware` for a given function.
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionMemoryMetering {
+            cost_per_page: self.cost_per_page,
+            global_indexes: self
+                .global_indexes
+                .lock()
+                .unwrap()
+                .expect("MemoryMetering::transform_module_info must be called first"),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut global_indexes = self.global_indexes.lock().unwrap();
+        if global_indexes.is_some() {
+            panic!("MemoryMetering::transform_module_info: Attempting to use a `MemoryMetering` middleware from multiple modules.");
+        }
+
+        let metering_global = |name: &str| match module_info.exports.get(name) {
+            Some(ExportIndex::Global(index)) => index.as_u32(),
+            _ => panic!("MemoryMetering must be used after the Metering middleware"),
+        };
+        let remaining_points = metering_global(REMAINING_POINTS_EXPORT);
+        let points_exhausted = metering_global(POINTS_EXHAUSTED_EXPORT);
+
+        let delta = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        let result = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        let cost = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I64Const(0));
+
+        *global_indexes = Some(GlobalIndexes {
+            remaining_points,
+            points_exhausted,
+            delta: delta.as_u32(),
+            result: result.as_u32(),
+            cost: cost.as_u32(),
+        });
+    }
+}
+
+#[derive(Debug)]
+struct FunctionMemoryMetering {
+    cost_per_page: u64,
+    global_indexes: GlobalIndexes,
+}
+
+impl FunctionMiddleware for FunctionMemoryMetering {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if let Operator::MemoryGrow { .. } = operator {
+            let GlobalIndexes {
+                remaining_points,
+                points_exhausted,
+                delta,
+                result,
+                cost,
+            } = self.global_indexes;
+            state.push_operator(Operator::GlobalSet {
+                global_index: delta,
+            });
+            state.push_operator(Operator::GlobalGet {
+                global_index: delta,
+            });
+            state.push_operator(operator);
+            state.extend(&[
+                Operator::GlobalSet {
+                    global_index: result,
+                },
+                // Growing failed if the result is -1
+                Operator::GlobalGet {
+                    global_index: result,
+                },
+                Operator::I32Const { value: -1 },
+                Operator::I32Ne,
+                Operator::If {
+                    ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+                },
+                // cost = delta * cost_per_page, where delta is at most 65536 pages
+                Operator::GlobalGet {
+                    global_index: delta,
+                },
+                Operator::I64ExtendI32U,
+                Operator::I64Const {
+                    value: self.cost_per_page as i64,
+                },
+                Operator::I64Mul,
+                Operator::GlobalSet { global_index: cost },
+                // Same as the Metering middleware does for the accumulated cost of operators
+                Operator::GlobalGet {
+                    global_index: remaining_points,
+                },
+                Operator::GlobalGet { global_index: cost },
+                Operator::I64LtU,
+                Operator::If {
+                    ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+                },
+                Operator::I32Const { value: 1 },
+                Operator::GlobalSet {
+                    global_index: points_exhausted,
+                },
+                Operator::Unreachable,
+                Operator::End,
+                Operator::GlobalGet {
+                    global_index: remaining_points,
+                },
+                Operator::GlobalGet { global_index: cost },
+                Operator::I64Sub,
+                Operator::GlobalSet {
+                    global_index: remaining_points,
+                },
+                Operator::End,
+                // Restore the result of memory.grow
+                Operator::GlobalGet {
+                    global_index: result,
+                },
+            ]);
+        } else {
+            state.push_operator(operator);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use wasmer::{imports, CompilerConfig, Cranelift, Instance, Module, Store, Universal};
+    use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
+    use wasmer_middlewares::Metering;
+
+    const GROW_WAT: &str = r#"(module
+        (memory 1)
+        (export "memory" (memory 0))
+        (func (export "grow") (param i32) (result i32)
+            local.get 0
+            memory.grow
+        )
+    )"#;
+
+    fn make_instance(gas_limit: u64, cost_per_page: u64) -> Instance {
+        let wasm = wat::parse_str(GROW_WAT).unwrap();
+        let mut compiler_config = Cranelift::default();
+        compiler_config.push_middleware(Arc::new(Metering::new(gas_limit, |_: &Operator| 1)));
+        compiler_config.push_middleware(Arc::new(MemoryMetering::new(cost_per_page)));
+        let store = Store::new(&Universal::new(compiler_config).engine());
+        let module = Module::new(&store, wasm).unwrap();
+        Instance::new(&module, &imports! {}).unwrap()
+    }
+
+    fn remaining_points(instance: &Instance) -> u64 {
+        match get_remaining_points(instance) {
+            MeteringPoints::Remaining(points) => points,
+            MeteringPoints::Exhausted => panic!("Points exhausted"),
+        }
+    }
+
+    #[test]
+    fn memory_grow_is_charged_per_page() {
+        let instance = make_instance(1_000_000, 1_000);
+        let grow = instance.exports.get_function("grow").unwrap();
+
+        // 3 operators (local.get, memory.grow, end) plus 3 pages
+        let result = grow.call(&[3.into()]).unwrap();
+        assert_eq!(result[0].unwrap_i32(), 1);
+        assert_eq!(remaining_points(&instance), 1_000_000 - 3 - 3 * 1_000);
+
+        // Growing by 0 pages only costs the operators
+        let result = grow.call(&[0.into()]).unwrap();
+        assert_eq!(result[0].unwrap_i32(), 4);
+        assert_eq!(remaining_points(&instance), 1_000_000 - 6 - 3 * 1_000);
+    }
+
+    #[test]
+    fn memory_grow_is_not_charged_when_failing() {
+        let instance = make_instance(1_000_000, 1_000);
+        let grow = instance.exports.get_function("grow").unwrap();
+
+        // Exceeds the maximum of 65536 pages
+        let result = grow.call(&[(-1).into()]).unwrap();
+        assert_eq!(result[0].unwrap_i32(), -1);
+        assert_eq!(remaining_points(&instance), 1_000_000 - 3);
+    }
+
+    #[test]
+    fn memory_grow_exhausts_points() {
+        let instance = make_instance(10_000, 1_000);
+        let grow = instance.exports.get_function("grow").unwrap();
+
+        grow.call(&[11.into()]).unwrap_err();
+        assert_eq!(get_remaining_points(&instance), MeteringPoints::Exhausted);
+    }
+}
@@ -1,8 +1,18 @@
 mod compile;
+mod gas_costs;
+mod gas_profiler;
 mod gatekeeper;
 mod limiting_tunables;
+mod memory_metering;
+mod stack_limiter;
 mod store;
 
-pub use compile::compile;
+pub use compile::{compile, compile_with_gas_costs, compile_with_gas_profiling};
+pub use gas_costs::GasCostTable;
+pub use gas_profiler::{
+    PROFILER_ENTER, PROFILER_IMPORTS, PROFILER_IMPORT_MODULE, PROFILER_INDIRECT_CALL,
+    PROFILER_LEAVE,
+};
 pub use limiting_tunables::LimitingTunables;
+pub use stack_limiter::{get_stack_height, get_stack_limit, set_stack_height, set_stack_limit};
 pub use store::make_runtime_store;
@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use loupe::{MemoryUsage, MemoryUsageTracker};
+use wasmer::wasmparser::{
+    BinaryReaderError, Operator, Parser, Type as WpType, TypeOrFuncType as WpTypeOrFuncType,
+    ValidPayload, Validator,
+};
+use wasmer::{
+    ExportIndex, FunctionMiddleware, FunctionType, GlobalInit, GlobalType, Instance,
+    LocalFunctionIndex, MiddlewareError, MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+};
+use wasmer_types::ModuleInfo;
+
+use crate::errors::{VmError, VmResult};
+
+/// Names of the exported globals of the StackLimiter middleware
+const STACK_HEIGHT_EXPORT: &str = "cosmwasm_stack_limiter_height";
+const STACK_LIMIT_EXPORT: &str = "cosmwasm_stack_limiter_limit";
+
+/// The largest supported limit. This ensures the stack height cannot overflow while
+/// the frame of a call is added.
+const MAX_STACK_LIMIT: u32 = i32::MAX as u32;
+
+/// The cost of a frame on top of its values, which accounts for the return address and other
+/// bookkeeping data of the call. This is the same value as in the stack height limiter of
+/// parity's pwasm-utils.
+const ACTIVATION_FRAME_COST: u64 = 2;
+
+#[derive(Debug, Clone, Copy)]
+struct GlobalIndexes {
+    /// The current stack height
+    height: u32,
+    /// The maximum stack height
+    limit: u32,
+}
+
+/// The frame costs of all functions and function types of a module
+#[derive(Debug)]
+struct FrameCosts {
+    /// Indexed by function index. Calls of imported functions are free.
+    functions: Vec<u32>,
+    /// Indexed by signature index, used for indirect calls
+    signatures: Vec<u32>,
+}
+
+#[derive(Debug)]
+struct ModuleState {
+    global_indexes: GlobalIndexes,
+    frame_costs: Arc<FrameCosts>,
+}
+
+/// A middleware that limits the depth of calls between Wasm functions in a deterministic way.
+///
+/// Every call adds the cost of a frame to the stack height, which approximates the number of values
+/// the called function keeps on the stack: [`ACTIVATION_FRAME_COST`] plus the number of its
+/// parameters, declared locals and the maximum height of its operand stack (see
+/// [`function_stack_usage`]). Indirect calls are charged the largest cost of all functions of the
+/// called type. The frame is removed again when the call returns. When the stack height exceeds
+/// the limit, execution traps. Otherwise deep recursion would eventually cause a stack overflow of
+/// the host, whose exact point depends on the machine and compiler.
+///
+/// The frame of the function called by the host is not counted. The limit is stored in a global
+/// that is set after instantiation (see [`set_stack_limit`]), such that it can be changed without
+/// compiling the module again. It is [`MAX_STACK_LIMIT`] by default.
+#[derive(Debug)]
+pub struct StackLimiter {
+    /// The result of [`function_stack_usage`] for the module that is compiled
+    stack_usage: Vec<u64>,
+    state: Mutex<Option<ModuleState>>,
+}
+
+impl StackLimiter {
+    /// Creates a limiter for the module whose functions have the given stack usage
+    /// (see [`function_stack_usage`]).
+    pub fn new(stack_usage: Vec<u64>) -> Self {
+        Self {
+            stack_usage,
+            state: Mutex::new(None),
+        }
+    }
+}
+
+impl MemoryUsage for StackLimiter {
+    fn size_of_val(&self, _tracker: &mut dyn MemoryUsageTracker) -> usize {
+        std::mem::size_of_val(self)
+    }
+}
+
+impl ModuleMiddleware for StackLimiter {
+    /// Generates a `FunctionMiddleware` for a given function.
+    fn generate_function_middleware(&self, _: LocalFunctionIndex) -> Box<dyn FunctionMiddleware> {
+        let state = self.state.lock().unwrap();
+        let state = state
+            .as_ref()
+            .expect("StackLimiter::transform_module_info must be called first");
+        Box::new(FunctionStackLimiter {
+            global_indexes: state.global_indexes,
+            frame_costs: state.frame_costs.clone(),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) {
+        let mut state = self.state.lock().unwrap();
+        if state.is_some() {
+            panic!("StackLimiter::transform_module_info: Attempting to use a `StackLimiter` middleware from multiple modules.");
+        }
+
+        assert_eq!(
+            self.stack_usage.len(),
+            module_info.functions.len() - module_info.num_imported_functions,
+            "StackLimiter::transform_module_info: The stack usage was computed for a different module."
+        );
+
+        let functions: Vec<u32> = module_info
+            .functions
+            .iter()
+            .map(
+                |(index, signature_index)| match module_info.local_func_index(index) {
+                    Some(local_index) => frame_cost(
+                        module_info.signatures[*signature_index].params().len(),
+                        self.stack_usage[local_index.as_u32() as usize],
+                    ),
+                    None => 0,
+                },
+            )
+            .collect();
+        // The callee of an indirect call can be any function of an equal type
+        let mut max_cost_by_type: HashMap<&FunctionType, u32> = HashMap::new();
+        for (index, signature_index) in module_info.functions.iter() {
+            let max_cost = max_cost_by_type
+                .entry(&module_info.signatures[*signature_index])
+                .or_default();
+            *max_cost = (*max_cost).max(functions[index.as_u32() as usize]);
+        }
+        let signatures = module_info
+            .signatures
+            .values()
+            .map(|signature| max_cost_by_type.get(signature).copied().unwrap_or_default())
+            .collect();
+
+        let height = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(0));
+        module_info
+            .exports
+            .insert(STACK_HEIGHT_EXPORT.to_string(), ExportIndex::Global(height));
+        let limit = module_info
+            .globals
+            .push(GlobalType::new(Type::I32, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I32Const(MAX_STACK_LIMIT as i32));
+        module_info
+            .exports
+            .insert(STACK_LIMIT_EXPORT.to_string(), ExportIndex::Global(limit));
+
+        *state = Some(ModuleState {
+            global_indexes: GlobalIndexes {
+                height: height.as_u32(),
+                limit: limit.as_u32(),
+            },
+            frame_costs: Arc::new(FrameCosts {
+                functions,
+                signatures,
+            }),
+        });
+    }
+}
+
+fn frame_cost(params: usize, stack_usage: u64) -> u32 {
+    let cost = ACTIVATION_FRAME_COST
+        .saturating_add(params as u64)
+        .saturating_add(stack_usage);
+    // Capping ensures the stack height does not overflow when a frame is added
+    cost.min(MAX_STACK_LIMIT.into()) as u32
+}
+
+/// Returns the number of values that each function defined in the Wasm keeps on the stack in
+/// addition to its parameters, i.e. the number of its declared locals plus the maximum height of
+/// its operand stack.
+///
+/// The Wasm is validated along the way, since the operand stack height is only defined for
+/// valid code.
+pub fn function_stack_usage(wasm: &[u8]) -> VmResult<Vec<u64>> {
+    let err = |e: BinaryReaderError| {
+        VmError::compile_err(format!("Error analyzing the stack usage: {}", e))
+    };
+
+    let mut validator = Validator::new();
+    let mut stack_usage = Vec::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload.map_err(err)?;
+        if let ValidPayload::Func(mut func, body) = validator.payload(&payload).map_err(err)? {
+            let mut reader = body.get_binary_reader();
+            let mut locals = 0u64;
+            for _ in 0..reader.read_var_u32().map_err(err)? {
+                let offset = reader.original_position();
+                let count = reader.read_var_u32().map_err(err)?;
+                let ty = reader.read_type().map_err(err)?;
+                func.define_locals(offset, count, ty).map_err(err)?;
+                locals += u64::from(count);
+            }
+            let mut max_operands = 0;
+            while !reader.eof() {
+                let offset = reader.original_position();
+                let operator = reader.read_operator().map_err(err)?;
+                func.op(offset, &operator).map_err(err)?;
+                max_operands = max_operands.max(func.operand_stack_height());
+            }
+            func.finish(reader.original_position()).map_err(err)?;
+            stack_usage.push(locals + u64::from(max_operands));
+        }
+    }
+    Ok(stack_usage)
+}
+
+#[derive(Debug)]
+struct FunctionStackLimiter {
+    global_indexes: GlobalIndexes,
+    frame_costs: Arc<FrameCosts>,
+}
+
+impl FunctionMiddleware for FunctionStackLimiter {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let cost = match operator {
+            Operator::Call { function_index } => self
+                .frame_costs
+                .functions
+                .get(function_index as usize)
+                .copied()
+                .unwrap_or_default(),
+            Operator::CallIndirect { index, .. } => self
+                .frame_costs
+                .signatures
+                .get(index as usize)
+                .copied()
+                .unwrap_or_default(),
+            _ => 0,
+        };
+        if cost == 0 {
+            state.push_operator(operator);
+            return Ok(());
+        }
+
+        let GlobalIndexes { height, limit } = self.global_indexes;
+        // The arguments of the call stay on the stack
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: height,
+            },
+            Operator::I32Const { value: cost as i32 },
+            Operator::I32Add,
+            Operator::GlobalSet {
+                global_index: height,
+            },
+            Operator::GlobalGet {
+                global_index: height,
+            },
+            Operator::GlobalGet {
+                global_index: limit,
+            },
+            Operator::I32GtU,
+            Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::Unreachable,
+            Operator::End,
+        ]);
+        state.push_operator(operator);
+        // The frame is removed no matter how the callee returned, unless it trapped
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: height,
+            },
+            Operator::I32Const { value: cost as i32 },
+            Operator::I32Sub,
+            Operator::GlobalSet {
+                global_index: height,
+            },
+        ]);
+        Ok(())
+    }
+}
+
+/// Returns the current stack height of an instance.
+///
+/// This can only be used with instances of modules compiled with the [`StackLimiter`] middleware.
+pub fn get_stack_height(instance: &Instance) -> u32 {
+    instance
+        .exports
+        .get_global(STACK_HEIGHT_EXPORT)
+        .expect("Can't get `cosmwasm_stack_limiter_height` from Instance")
+        .get()
+        .try_into()
+        .map(|height: i32| height as u32)
+        .expect("`cosmwasm_stack_limiter_height` from Instance has wrong type")
+}
+
+/// Sets the current stack height of an instance. This is used to clean up after a trap,
+/// which leaves the frames of the aborted calls behind.
+pub fn set_stack_height(instance: &Instance, height: u32) {
+    instance
+        .exports
+        .get_global(STACK_HEIGHT_EXPORT)
+        .expect("Can't get `cosmwasm_stack_limiter_height` from Instance")
+        .set((height as i32).into())
+        .expect("Can't set `cosmwasm_stack_limiter_height` in Instance");
+}
+
+/// Returns the stack limit of an instance.
+pub fn get_stack_limit(instance: &Instance) -> u32 {
+    instance
+        .exports
+        .get_global(STACK_LIMIT_EXPORT)
+        .expect("Can't get `cosmwasm_stack_limiter_limit` from Instance")
+        .get()
+        .try_into()
+        .map(|limit: i32| limit as u32)
+        .expect("`cosmwasm_stack_limiter_limit` from Instance has wrong type")
+}
+
+/// Sets the stack limit of an instance. Values above [`MAX_STACK_LIMIT`] are capped.
+pub fn set_stack_limit(instance: &Instance, limit: u32) {
+    instance
+        .exports
+        .get_global(STACK_LIMIT_EXPORT)
+        .expect("Can't get `cosmwasm_stack_limiter_limit` from Instance")
+        .set((limit.min(MAX_STACK_LIMIT) as i32).into())
+        .expect("Can't set `cosmwasm_stack_limiter_limit` in Instance");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wasmer::{imports, CompilerConfig, Cranelift, Module, Store, Universal};
+
+    const RECURSION_WAT: &str = r#"(module
+        (type $t (func (param i32) (result i32)))
+        (table 1 funcref)
+        (elem (i32.const 0) $recurse)
+        (func $recurse (export "recurse") (param i32) (result i32)
+            local.get 0
+            i32.eqz
+            if (result i32)
+                i32.const 0
+            else
+                local.get 0
+                i32.const 1
+                i32.sub
+                call $recurse
+                i32.const 1
+                i32.add
+            end
+        )
+        (func (export "recurse_indirect") (param i32) (result i32)
+            local.get 0
+            i32.const 0
+            call_indirect (type $t)
+        )
+    )"#;
+
+    fn make_instance(wat: &str) -> Instance {
+        let wasm = wat::parse_str(wat).unwrap();
+        let mut compiler_config = Cranelift::default();
+        compiler_config.push_middleware(Arc::new(StackLimiter::new(
+            function_stack_usage(&wasm).unwrap(),
+        )));
+        let store = Store::new(&Universal::new(compiler_config).engine());
+        let module = Module::new(&store, wasm).unwrap();
+        Instance::new(&module, &imports! {}).unwrap()
+    }
+
+    #[test]
+    fn function_stack_usage_works() {
+        // 2 operands each
+        let wasm = wat::parse_str(RECURSION_WAT).unwrap();
+        assert_eq!(function_stack_usage(&wasm).unwrap(), vec![2, 2]);
+
+        let wasm = wat::parse_str(
+            r#"(module
+            (import "env" "foo" (func))
+            (func (param i32 i32) (local i64 i64) (local f32)
+                i32.const 1
+                i32.const 2
+                i32.const 3
+                drop
+                drop
+                drop
+            )
+            (func)
+            )"#,
+        )
+        .unwrap();
+        assert_eq!(function_stack_usage(&wasm).unwrap(), vec![3 + 3, 0]);
+
+        // Invalid code
+        let wasm = wat::parse_str(r#"(module (func i32.add))"#).unwrap();
+        match function_stack_usage(&wasm).unwrap_err() {
+            VmError::CompileErr { msg, .. } => {
+                assert!(msg.starts_with("Error analyzing the stack usage"))
+            }
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn calls_are_counted() {
+        let instance = make_instance(RECURSION_WAT);
+        let recurse = instance.exports.get_function("recurse").unwrap();
+        assert_eq!(get_stack_limit(&instance), MAX_STACK_LIMIT);
+
+        // Frames cost 5 each (activation, 1 parameter, 2 operands)
+        set_stack_limit(&instance, 50);
+        let result = recurse.call(&[10.into()]).unwrap();
+        assert_eq!(result[0].unwrap_i32(), 10);
+        assert_eq!(get_stack_height(&instance), 0);
+
+        // Indirect calls are counted as well
+        let recurse_indirect = instance.exports.get_function("recurse_indirect").unwrap();
+        let result = recurse_indirect.call(&[9.into()]).unwrap();
+        assert_eq!(result[0].unwrap_i32(), 9);
+        assert_eq!(get_stack_height(&instance), 0);
+    }
+
+    #[test]
+    fn exceeding_the_limit_traps() {
+        let instance = make_instance(RECURSION_WAT);
+        let recurse = instance.exports.get_function("recurse").unwrap();
+
+        set_stack_limit(&instance, 50);
+        recurse.call(&[11.into()]).unwrap_err();
+        assert_eq!(get_stack_height(&instance), 55);
+
+        // Unbounded recursion stops deterministically
+        set_stack_height(&instance, 0);
+        set_stack_limit(&instance, 10_000);
+        recurse.call(&[(-1).into()]).unwrap_err();
+        assert_eq!(get_stack_height(&instance), 10_005);
+    }
+
+    #[test]
+    fn locals_are_counted() {
+        let wat = format!(
+            r#"(module
+            (func $recurse (export "recurse") (param i32) (local {})
+                local.get 0
+                if
+                    local.get 0
+                    i32.const 1
+                    i32.sub
+                    call $recurse
+                end
+            )
+            )"#,
+            "i64 ".repeat(1000)
+        );
+        let instance = make_instance(&wat);
+        let recurse = instance.exports.get_function("recurse").unwrap();
+
+        // Frames cost 1005 each (activation, 1 parameter, 1000 locals, 2 operands)
+        set_stack_limit(&instance, 10_000);
+        recurse.call(&[9.into()]).unwrap();
+        recurse.call(&[10.into()]).unwrap_err();
+        assert_eq!(get_stack_height(&instance), 10_050);
+    }
+
+    #[test]
+    fn set_stack_limit_caps_limit() {
+        let instance = make_instance(RECURSION_WAT);
+        set_stack_limit(&instance, u32::MAX);
+        assert_eq!(get_stack_limit(&instance), MAX_STACK_LIMIT);
+    }
+}
@@ -9,10 +9,15 @@ use wasmer::{
 };
 use wasmer_middlewares::Metering;
 
+use crate::errors::VmResult;
 use crate::size::Size;
 
+use super::gas_costs::GasCostTable;
+use super::gas_profiler::GasProfiler;
 use super::gatekeeper::Gatekeeper;
 use super::limiting_tunables::LimitingTunables;
+use super::memory_metering::MemoryMetering;
+use super::stack_limiter::{function_stack_usage, StackLimiter};
 
 /// WebAssembly linear memory objects have sizes measured in pages. Each page
 /// is 65536 (2^16) bytes. In WebAssembly version 1, a linear memory can have at
@@ -20,25 +25,32 @@ use super::limiting_tunables::LimitingTunables;
 /// https://github.com/WebAssembly/memory64/blob/master/proposals/memory64/Overview.md
 const MAX_WASM_PAGES: u32 = 65536;
 
-fn cost(_operator: &Operator) -> u64 {
-    // A flat fee for each operation
-    // The target is 1 Teragas per millisecond (see GAS.md).
-    //
-    // In https://github.com/CosmWasm/cosmwasm/pull/1042 a profiler is developed to
-    // identify runtime differences between different Wasm operation, but this is not yet
-    // precise enough to derive insights from it.
-    150_000
-}
-
-/// Created a store with the default compiler and the given memory limit (in bytes).
+/// Created a store with the default compiler and the given memory limit (in bytes) for compiling
+/// the given code. The store must not be used for compiling other code.
 /// If memory_limit is None, no limit is applied.
+/// Operators are metered with the costs from the given table and the depth of calls is limited
+/// (see [`StackLimiter`]).
+/// If gas_profiling is set, calls are instrumented for the [`GasProfiler`]. This requires the code
+/// to contain the profiler imports.
 pub fn make_compile_time_store(
+    code: &[u8],
     memory_limit: Option<Size>,
     middlewares: &[Arc<dyn ModuleMiddleware>],
-) -> Store {
+    gas_costs: &GasCostTable,
+    gas_profiling: bool,
+) -> VmResult<Store> {
     let gas_limit = 0;
     let deterministic = Arc::new(Gatekeeper::default());
-    let metering = Arc::new(Metering::new(gas_limit, cost));
+    let gas_costs = *gas_costs;
+    let metering = Arc::new(Metering::new(gas_limit, move |operator: &Operator| {
+        gas_costs.cost(operator)
+    }));
+    let memory_metering = match gas_costs.memory_page {
+        0 => None,
+        cost_per_page => Some(Arc::new(MemoryMetering::new(cost_per_page))),
+    };
+    let stack_limiter = Arc::new(StackLimiter::new(function_stack_usage(code)?));
+    let gas_profiler = gas_profiling.then(|| Arc::new(GasProfiler::new()));
 
     #[cfg(feature = "cranelift")]
     {
@@ -48,8 +60,15 @@ pub fn make_compile_time_store(
         }
         config.push_middleware(deterministic);
         config.push_middleware(metering);
+        if let Some(memory_metering) = memory_metering {
+            config.push_middleware(memory_metering);
+        }
+        config.push_middleware(stack_limiter);
+        if let Some(gas_profiler) = gas_profiler {
+            config.push_middleware(gas_profiler);
+        }
         let engine = Universal::new(config).engine();
-        make_store_with_engine(&engine, memory_limit)
+        Ok(make_store_with_engine(&engine, memory_limit))
     }
 
     #[cfg(not(feature = "cranelift"))]
@@ -60,8 +79,15 @@ pub fn make_compile_time_store(
         }
         config.push_middleware(deterministic);
         config.push_middleware(metering);
+        if let Some(memory_metering) = memory_metering {
+            config.push_middleware(memory_metering);
+        }
+        config.push_middleware(stack_limiter);
+        if let Some(gas_profiler) = gas_profiler {
+            config.push_middleware(gas_profiler);
+        }
         let engine = Universal::new(config).engine();
-        make_store_with_engine(&engine, memory_limit)
+        Ok(make_store_with_engine(&engine, memory_limit))
     }
 }
 
@@ -131,7 +157,8 @@ mod tests {
         let wasm = wat::parse_str(EXPORTED_MEMORY_WAT).unwrap();
 
         // No limit
-        let store = make_compile_time_store(None, &[]);
+        let store =
+            make_compile_time_store(&wasm, None, &[], &GasCostTable::default(), false).unwrap();
         let module = Module::new(&store, &wasm).unwrap();
         let module_memory = module.info().memories.last().unwrap();
         assert_eq!(module_memory.minimum, Pages(4));
@@ -148,7 +175,14 @@ mod tests {
         assert_eq!(instance_memory.ty().maximum, None);
 
         // Set limit
-        let store = make_compile_time_store(Some(Size::kibi(23 * 64)), &[]);
+        let store = make_compile_time_store(
+            &wasm,
+            Some(Size::kibi(23 * 64)),
+            &[],
+            &GasCostTable::default(),
+            false,
+        )
+        .unwrap();
         let module = Module::new(&store, &wasm).unwrap();
         let module_memory = module.info().memories.last().unwrap();
         assert_eq!(module_memory.minimum, Pages(4));
@@ -170,7 +204,8 @@ mod tests {
         // Compile
         let serialized = {
             let wasm = wat::parse_str(EXPORTED_MEMORY_WAT).unwrap();
-            let store = make_compile_time_store(None, &[]);
+            let store =
+                make_compile_time_store(&wasm, None, &[], &GasCostTable::default(), false).unwrap();
             let module = Module::new(&store, &wasm).unwrap();
             module.serialize().unwrap()
         };
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use gimli::{EndianArcSlice, LittleEndian};
+use wasmer::wasmparser::{Parser, Payload};
+use wasmer::{FrameInfo, RuntimeError};
+
+/// The Wasm functions that were running when a contract trapped, innermost first.
+///
+/// This is attached to [`crate::VmError::RuntimeErr`] for instances with
+/// [`crate::InstanceOptions::wasm_backtraces`] enabled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WasmBacktrace {
+    pub frames: Vec<WasmFrame>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WasmFrame {
+    /// The index of the function in the Wasm, including imported functions
+    pub function_index: u32,
+    /// The demangled function name from the name section or `func[<index>]` for unnamed functions
+    pub function_name: String,
+    /// The offset of the instruction in the Wasm
+    pub module_offset: usize,
+    /// The source location from the DWARF debug info, if present
+    pub location: Option<SourceLocation>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+}
+
+impl fmt::Display for WasmBacktrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Wasm backtrace:")?;
+        for (index, frame) in self.frames.iter().enumerate() {
+            writeln!(f, "  {}: {}", index, frame.function_name)?;
+            if let Some(location) = &frame.location {
+                write!(f, "        at {}", location.file)?;
+                if let Some(line) = location.line {
+                    write!(f, ":{}", line)?;
+                    if let Some(column) = location.column {
+                        write!(f, ":{}", column)?;
+                    }
+                }
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+type DwarfReader = EndianArcSlice<LittleEndian>;
+
+struct DwarfInfo {
+    context: addr2line::Context<DwarfReader>,
+    /// DWARF addresses of Wasm code are relative to the start of the code section
+    code_section_offset: usize,
+}
+
+/// Creates [`WasmBacktrace`]s for the traps of one module.
+///
+/// The DWARF context fills lazy caches while looking up locations, so this is `Send` but not
+/// `Sync`. It must only be used with exclusive access.
+pub struct WasmSymbolizer {
+    dwarf: Option<DwarfInfo>,
+}
+
+impl WasmSymbolizer {
+    /// Loads the DWARF debug info of the given Wasm, if present. Function names are taken from the
+    /// compiled module, so this works without debug info as well.
+    pub fn new(wasm: &[u8]) -> Self {
+        WasmSymbolizer {
+            // Broken debug info only costs the source locations
+            dwarf: load_dwarf(wasm),
+        }
+    }
+
+    pub fn backtrace(&mut self, error: &RuntimeError) -> WasmBacktrace {
+        WasmBacktrace {
+            frames: error
+                .trace()
+                .iter()
+                .map(|frame| self.frame(frame))
+                .collect(),
+        }
+    }
+
+    fn frame(&self, frame: &FrameInfo) -> WasmFrame {
+        let function_name = match frame.function_name() {
+            Some(name) => format!("{:#}", rustc_demangle::demangle(name)),
+            None => format!("func[{}]", frame.func_index()),
+        };
+        WasmFrame {
+            function_index: frame.func_index(),
+            function_name,
+            module_offset: frame.module_offset(),
+            location: self.location(frame.module_offset()),
+        }
+    }
+
+    fn location(&self, module_offset: usize) -> Option<SourceLocation> {
+        let dwarf = self.dwarf.as_ref()?;
+        let address = module_offset.checked_sub(dwarf.code_section_offset)?;
+        let location = dwarf.context.find_location(address as u64).ok()??;
+        Some(SourceLocation {
+            file: location.file?.to_string(),
+            line: location.line,
+            column: location.column,
+        })
+    }
+}
+
+fn load_dwarf(wasm: &[u8]) -> Option<DwarfInfo> {
+    let mut code_section_offset = None;
+    let mut sections = HashMap::new();
+    for payload in Parser::new(0).parse_all(wasm) {
+        match payload.ok()? {
+            Payload::CodeSectionStart { range, .. } => code_section_offset = Some(range.start),
+            Payload::CustomSection { name, data, .. } if name.starts_with(".debug_") => {
+                sections.insert(name, data);
+            }
+            _ => {}
+        }
+    }
+    if !sections.contains_key(".debug_info") {
+        return None;
+    }
+
+    let dwarf = gimli::Dwarf::load(|id| -> Result<_, gimli::Error> {
+        let data = sections.get(id.name()).copied().unwrap_or_default();
+        Ok(DwarfReader::new(Arc::from(data), LittleEndian))
+    })
+    .ok()?;
+    Some(DwarfInfo {
+        context: addr2line::Context::from_dwarf(dwarf).ok()?,
+        code_section_offset: code_section_offset?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{mock_backend, mock_instance_options};
+    use crate::{Instance, InstanceOptions, VmError};
+
+    /// Contains DWARF debug info and a name section
+    static CONTRACT: &[u8] = include_bytes!("../testdata/queue_1.0.0_with_migrate.wasm");
+
+    fn call_and_get_backtrace(wasm: &[u8], name: &str, args: &[wasmer::Val]) -> WasmBacktrace {
+        let (instance_options, memory_limit) = mock_instance_options();
+        let instance_options = InstanceOptions {
+            wasm_backtraces: true,
+            ..instance_options
+        };
+        let instance =
+            Instance::from_code(wasm, mock_backend(&[]), instance_options, memory_limit).unwrap();
+        match instance.call_function0(name, args).unwrap_err() {
+            VmError::RuntimeErr {
+                wasm_backtrace: Some(wasm_backtrace),
+                ..
+            } => wasm_backtrace,
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn backtrace_uses_name_section_and_dwarf() {
+        // Panics since the region pointer is null
+        let backtrace = call_and_get_backtrace(CONTRACT, "deallocate", &[0u32.into()]);
+        let names: Vec<_> = backtrace
+            .frames
+            .iter()
+            .map(|frame| frame.function_name.as_str())
+            .collect();
+        assert_eq!(names.first(), Some(&"__rust_start_panic"));
+        assert!(names.contains(&"core::panicking::panic_fmt"));
+        assert_eq!(names.last(), Some(&"deallocate"));
+
+        let location = backtrace.frames[0].location.as_ref().unwrap();
+        assert!(location.file.ends_with("library/panic_abort/src/lib.rs"));
+        assert_eq!(location.line, Some(85));
+    }
+
+    #[test]
+    fn backtrace_works_without_dwarf() {
+        let wasm = wat::parse_str(
+            r#"(module
+            (func $inner unreachable)
+            (func call $inner)
+            (func (export "run") call 1)
+            )"#,
+        )
+        .unwrap();
+        let backtrace = call_and_get_backtrace(&wasm, "run", &[]);
+        let names: Vec<_> = backtrace
+            .frames
+            .iter()
+            .map(|frame| frame.function_name.as_str())
+            .collect();
+        assert_eq!(names, ["inner", "func[1]", "func[2]"]);
+        assert!(backtrace
+            .frames
+            .iter()
+            .all(|frame| frame.location.is_none()));
+        assert_eq!(
+            backtrace.to_string(),
+            "Wasm backtrace:\n  0: inner\n  1: func[1]\n  2: func[2]\n"
+        );
+    }
+
+    #[test]
+    fn backtraces_are_disabled_by_default() {
+        let (instance_options, memory_limit) = mock_instance_options();
+        let instance =
+            Instance::from_code(CONTRACT, mock_backend(&[]), instance_options, memory_limit)
+                .unwrap();
+        let err = instance
+            .call_function0("deallocate", &[0u32.into()])
+            .unwrap_err();
+        match &err {
+            VmError::RuntimeErr { wasm_backtrace, .. } => assert_eq!(*wasm_backtrace, None),
+            err => panic!("Unexpected error: {:?}", err),
+        }
+        assert_eq!(
+            err.to_string(),
+            "Error executing Wasm: Wasmer runtime error: RuntimeError: unreachable"
+        );
+    }
+
+    #[test]
+    fn symbolizer_is_send() {
+        fn assert_send<T: Send>(_: &T) {}
+        let symbolizer = WasmSymbolizer::new(CONTRACT);
+        assert!(symbolizer.dwarf.is_some());
+        assert_send(&symbolizer);
+    }
+}
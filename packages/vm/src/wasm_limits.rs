@@ -0,0 +1,72 @@
+/// A kibi (kilo binary)
+const KI: usize = 1024;
+/// A mibi (mega binary)
+const MI: usize = 1024 * 1024;
+
+/// Limits for contracts that are enforced by static validation (see [`crate::internals::check_wasm`]),
+/// during execution and by the VM when reading arguments of imports from Wasm memory.
+///
+/// The defaults of limits that existed in previous versions of the VM equal the values hard-coded
+/// there. The other defaults leave plenty of room for contracts built by the usual toolchains.
+/// Chains that change them must make sure all nodes use the same limits, since they affect consensus.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Maximum initial size of a contract's memory in Wasm pages (64 KiB each)
+    pub max_memory_pages: u32,
+    /// Maximum number of functions defined in a contract (not counting imports)
+    pub max_functions: usize,
+    /// Maximum number of parameters of a function type
+    pub max_function_params: usize,
+    /// Maximum number of results of a function type
+    pub max_function_results: usize,
+    /// Maximum size of the Wasm blob in bytes
+    pub max_wasm_size: usize,
+    /// Maximum size of a single function body in bytes
+    pub max_function_body_size: usize,
+    /// Maximum number of locals declared in a single function (not counting parameters)
+    pub max_function_locals: u64,
+    /// Maximum initial number of table elements
+    pub max_table_size: u32,
+    /// Maximum number of globals
+    pub max_globals: usize,
+    /// Maximum size of a single data segment in bytes
+    pub max_data_segment_size: usize,
+    /// Maximum number of element segments
+    pub max_element_segments: usize,
+    /// Maximum stack height during execution. Every call of a Wasm function adds the size of its
+    /// frame (a constant activation cost, its parameters, its declared locals and its maximum
+    /// operand stack height) to the stack height until it returns. Exceeding this aborts the
+    /// execution with [`crate::VmError::StackLimitExceeded`].
+    ///
+    /// This is not limited by default, since any limit may break existing contracts. Chains that
+    /// set it must do so in a coordinated upgrade, since it affects consensus.
+    pub max_stack_height: Option<u32>,
+    /// Max key length for db_write/db_read/db_remove/db_scan (when VM reads the key argument from Wasm memory)
+    pub max_length_db_key: usize,
+    /// Max value length for db_write (when VM reads the value argument from Wasm memory)
+    pub max_length_db_value: usize,
+    /// Max length of a query_chain request (when VM reads the request argument from Wasm memory)
+    pub max_length_query_chain_request: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            max_memory_pages: 512,
+            max_functions: 10000,
+            max_function_params: 50,
+            max_function_results: 1,
+            max_wasm_size: 3 * MI,
+            max_function_body_size: 256 * KI,
+            max_function_locals: 10_000,
+            max_table_size: 2500,
+            max_globals: 256,
+            max_data_segment_size: 2 * MI,
+            max_element_segments: 100,
+            max_stack_height: None,
+            max_length_db_key: 64 * KI,
+            max_length_db_value: 128 * KI,
+            max_length_query_chain_request: 64 * KI,
+        }
+    }
+}